@@ -1,10 +1,34 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use grepq::initialise;
+use grepq::input;
+use grepq::matcher::PatternMatcher;
+use grepq::patterns::PatternSet;
 use grepq::quality;
+use regex::bytes::RegexSet;
+use seq_io::fastq::{self, Record};
+use seq_io::parallel::parallel_fastq;
+use std::fs::File;
 use std::hint::black_box;
-use std::io::Write;
+use std::io::{BufReader, Write};
 use tempfile::NamedTempFile;
 
+// Function: random_sequences
+// Generates the given number of pseudo-random DNA sequences of the given length, the same
+// on every run.
+fn random_sequences(count: usize, length: usize, seed: u32) -> Vec<Vec<u8>> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            (0..length)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    b"ACGT"[(state >> 16) as usize % 4]
+                })
+                .collect()
+        })
+        .collect()
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("quality_calculation", |b| {
         let quality = vec![b'I'; 1000];
@@ -13,7 +37,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function("iupac_conversion", |b| {
         let pattern = "ACGTYRWSKMBDHVN";
-        b.iter(|| initialise::convert_iupac_to_regex(black_box(pattern)))
+        b.iter(|| initialise::convert_iupac_to_regex(black_box(pattern)).unwrap())
     });
 
     c.bench_function("pattern_parsing", |b| {
@@ -49,7 +73,107 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                        AGTC[AG]TAACAAG\n\
                        CTTGTTA[CT]GACT";
         temp_file.as_file().write_all(patterns.as_bytes()).unwrap();
-        b.iter(|| initialise::parse_patterns_file(black_box(temp_file.path().to_str().unwrap())))
+        b.iter(|| PatternSet::from_file(black_box(temp_file.path().to_str().unwrap()), None))
+    });
+
+    // Matching reads against thousands of literal patterns (e.g. barcodes or probes), and
+    // against degenerate primers that expand to bounded sets of literals, with the
+    // Aho-Corasick fast path of PatternMatcher and with a RegexSet alone.
+    let reads = random_sequences(1000, 150, 1);
+    let literal_patterns: Vec<String> = random_sequences(2000, 20, 2)
+        .into_iter()
+        .map(|pattern| String::from_utf8(pattern).unwrap())
+        .collect();
+    let primer_patterns: Vec<String> = [
+        "[AG]AAT[AT]G[AG]CGGGG",
+        "GG[AG][ACGT]GGC[ACGT]GCAG",
+        "C[ACG]GC[ACGT]GC[CT]GCGG",
+        "TAGA[AT]ACCC[ACGT][ACGT]G",
+        "[GC][CT]GGCG[ACGT]ACGGG",
+        "G[CT]AC[AT]C[AT]CCGCC",
+        "G[AC]GGTGAAAT[TG]C",
+        "AT[CT][AC]TGGCTCAG",
+    ]
+    .iter()
+    .map(|pattern| pattern.to_string())
+    .collect();
+    for (name, patterns) in [
+        ("literals", &literal_patterns),
+        ("primers", &primer_patterns),
+    ] {
+        let matcher = PatternMatcher::new(patterns, &[], false).unwrap();
+        c.bench_function(&format!("{}_literal_fast_path", name), |b| {
+            b.iter(|| {
+                reads
+                    .iter()
+                    .filter(|read| matcher.is_match(black_box(read)))
+                    .count()
+            })
+        });
+        let regex_set = RegexSet::new(patterns).unwrap();
+        c.bench_function(&format!("{}_regex_set", name), |b| {
+            b.iter(|| {
+                reads
+                    .iter()
+                    .filter(|read| regex_set.is_match(black_box(read)))
+                    .count()
+            })
+        });
+    }
+
+    // Reading a FASTQ file in parallel with seq_io, as for a single input file, and as one
+    // of several input files, with the same reader capacity; both should have the same
+    // throughput.
+    let mut fastq = NamedTempFile::new().unwrap();
+    for (i, read) in random_sequences(50_000, 150, 3).iter().enumerate() {
+        let qual = vec![b'I'; read.len()];
+        writeln!(fastq, "@read{}", i).unwrap();
+        fastq.write_all(read).unwrap();
+        fastq.write_all(b"\n+\n").unwrap();
+        fastq.write_all(&qual).unwrap();
+        fastq.write_all(b"\n").unwrap();
+    }
+    fastq.flush().unwrap();
+    let path = fastq.path().to_string_lossy().into_owned();
+    let matcher = PatternMatcher::new(&primer_patterns, &[], false).unwrap();
+    c.bench_function("parallel_fastq_single_file", |b| {
+        b.iter(|| {
+            let mut count = 0;
+            let file = BufReader::new(File::open(&path).unwrap());
+            let reader = fastq::Reader::with_capacity(file, 8 * 1024 * 1024);
+            parallel_fastq(
+                reader,
+                4,
+                4,
+                |record, found: &mut Vec<bool>| {
+                    found.clear();
+                    found.push(matcher.is_match(record.seq()));
+                },
+                |_, found| {
+                    count += found[0] as usize;
+                    None::<()>
+                },
+            )
+            .unwrap();
+            count
+        })
+    });
+    c.bench_function("parallel_fastq_files", |b| {
+        b.iter(|| {
+            let mut count = 0;
+            input::parallel_fastq_files(
+                input::FastqFiles::new(vec![path.clone()], None),
+                4,
+                4,
+                |record, found: &mut Vec<bool>| {
+                    found.clear();
+                    found.push(matcher.is_match(record.seq()));
+                },
+                |_, found, _| count += found[0] as usize,
+            )
+            .unwrap();
+            count
+        })
     });
 
     c.bench_function("quality_encoding", |b| {
@@ -0,0 +1,175 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module finds amplicons in reads. An amplicon is given by a forward and a reverse
+// primer (each a regex of the set) and the bounds of the length of the insert between
+// them. A read contains the amplicon if the forward primer is followed, after an insert
+// of that length, by the reverse complement of the reverse primer, or (for a read of the
+// other strand) if the reverse primer is followed by the reverse complement of the
+// forward primer. The insert is the sequence between the two primers, excluding both.
+// Each primer keeps the window and match quality of its regex, which apply to its reverse
+// complement too.
+
+use crate::initialise::convert_iupac_to_regex;
+use crate::iupac::reverse_complement_regex;
+use crate::matcher::{Hit, PatternMatcher};
+use crate::patterns::{MatchQuality, Pattern, PatternError, Strand};
+
+// The primers of an amplicon, as compiled: for the forward strand, the forward primer
+// and the reverse complement of the reverse primer; for the reverse strand, the reverse
+// primer and the reverse complement of the forward primer.
+const FORWARD_STRAND: (usize, usize) = (0, 1);
+const REVERSE_STRAND: (usize, usize) = (2, 3);
+
+// Struct: AmpliconHit
+// The location of an amplicon within a read: from the start of the first primer to the
+// end of the second, with the insert between them. Positions are 0-based, and ends are
+// exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AmpliconHit {
+    pub start: usize,
+    pub end: usize,
+    pub insert_start: usize,
+    pub insert_end: usize,
+    // Forward if the read holds the forward primer first, reverse otherwise.
+    pub strand: Strand,
+}
+
+impl AmpliconHit {
+    // Returns the length of the insert between the primers.
+    pub fn insert_length(&self) -> usize {
+        self.insert_end - self.insert_start
+    }
+}
+
+// Struct: Amplicon
+// An amplicon of a regex set, with its primers compiled into a PatternMatcher.
+pub struct Amplicon {
+    pub name: String,
+    // The regexNames of the forward and reverse primers.
+    pub forward: String,
+    pub reverse: String,
+    pub minimum_insert: usize,
+    pub maximum_insert: Option<usize>,
+    matcher: PatternMatcher,
+    // The match quality of each compiled primer.
+    match_quality: [MatchQuality; 4],
+}
+
+impl Amplicon {
+    // Function: new
+    // Compiles an amplicon from its forward and reverse primers, which keep the distance
+    // they tolerate, their window and their match quality, matching lowercase bases too
+    // if case_insensitive is set.
+    pub fn new(
+        name: &str,
+        forward: &Pattern,
+        reverse: &Pattern,
+        insert: (usize, Option<usize>),
+        case_insensitive: bool,
+    ) -> Result<Self, PatternError> {
+        let reversed = |pattern: &Pattern| {
+            convert_iupac_to_regex(&reverse_complement_regex(&pattern.original)?)
+        };
+        let regexes = [
+            forward.regex.clone(),
+            reversed(reverse)?,
+            reverse.regex.clone(),
+            reversed(forward)?,
+        ];
+        let distances = [
+            forward.distance,
+            reverse.distance,
+            reverse.distance,
+            forward.distance,
+        ];
+        let windows = vec![
+            forward.window,
+            reverse.window,
+            reverse.window,
+            forward.window,
+        ];
+        Ok(Self {
+            name: name.to_string(),
+            forward: forward.name.clone(),
+            reverse: reverse.name.clone(),
+            minimum_insert: insert.0,
+            maximum_insert: insert.1,
+            matcher: PatternMatcher::new(&regexes, &distances, case_insensitive)?
+                .with_windows(windows),
+            match_quality: [
+                forward.match_quality,
+                reverse.match_quality,
+                reverse.match_quality,
+                forward.match_quality,
+            ],
+        })
+    }
+
+    // Function: find
+    // Returns the first location of the amplicon in a record (given by its sequence and
+    // quality), on the forward strand if there is one. The first primer hit is paired with
+    // the nearest second primer hit that leaves an insert of the allowed length. Primer
+    // hits whose bases fall short of the primer's match quality are ignored.
+    pub fn find(&self, seq: &[u8], qual: &[u8], quality_encoding: &str) -> Option<AmpliconHit> {
+        let found = self.matcher.matches(seq);
+        [
+            (FORWARD_STRAND, Strand::Forward),
+            (REVERSE_STRAND, Strand::Reverse),
+        ]
+        .into_iter()
+        .filter(|((first, second), _)| found.contains(first) && found.contains(second))
+        .find_map(|((first, second), strand)| {
+            let seconds = self.hits(second, seq, qual, quality_encoding);
+            self.hits(first, seq, qual, quality_encoding)
+                .into_iter()
+                .find_map(|a| {
+                    let b = seconds
+                        .iter()
+                        .find(|b| b.start >= a.end && self.accepts_insert(b.start - a.end))?;
+                    Some(AmpliconHit {
+                        start: a.start,
+                        end: b.end,
+                        insert_start: a.end,
+                        insert_end: b.start,
+                        strand,
+                    })
+                })
+        })
+    }
+
+    // Returns the hits of the compiled primer at the given index that meet its match
+    // quality.
+    fn hits(&self, index: usize, seq: &[u8], qual: &[u8], quality_encoding: &str) -> Vec<Hit> {
+        let mut hits = self.matcher.find_iter(index, seq);
+        let match_quality = &self.match_quality[index];
+        if match_quality.is_set() {
+            hits.retain(|hit| match_quality.accepts(&qual[hit.start..hit.end], quality_encoding));
+        }
+        hits
+    }
+
+    // Returns true if an insert of the given length is within the bounds of the amplicon.
+    fn accepts_insert(&self, length: usize) -> bool {
+        length >= self.minimum_insert && self.maximum_insert.is_none_or(|max| length <= max)
+    }
+}
@@ -20,16 +20,18 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::paired::PairRule;
+use crate::patterns::PatternFormat;
 use clap::Parser;
 use colored::*;
 use std::sync::LazyLock;
 
 static AFTER_HELP: LazyLock<String> = LazyLock::new(|| {
     format!(
-        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
         "Overview:".bold().underline(),
         "\n\n`grepq` searches the sequence line of FASTQ records for regular
-expressions that are contained in a text or JSON file, or it searches for the
+expressions that are contained in a text, JSON or YAML file, or it searches for the
 absence of those regular expressions when used with the `inverted` command. The 
 FASTQ file on which it operates can be supplied uncompressed or in gzip or zstd
 compressed format. Use the `tune` or `summarise` command in a simple shell script
@@ -127,6 +129,20 @@ named after each regexName, with the output in FASTQ format, and write a SQLite
 database file, limiting the number of tetranucleotides in the TNF and CTNF fields
 to two".italic(),
         "\n    grepq -R --read-gzip --writeSQL -N 2 --bucket regex.json file.fastq.gz".bold(),
+        "\n\nCount the matching records of several compressed FASTQ files, read from standard input"
+            .italic(),
+        "\n    cat *.fastq.gz | grepq -c regex.txt -".bold(),
+        "\n\nCount the matching records of each FASTQ file of a run, and of all of them"
+            .italic(),
+        "\n    grepq -c regex.json 'run1/*.fastq.gz'".bold(),
+        "\n\nCheck a pattern file for empty, duplicate, subsumed and reverse complement
+patterns, writing the issues found in JSON format (no FASTQ file is needed)".italic(),
+        "\n    grepq regex.json lint --json".bold(),
+        "\n\nRewrite a pattern file written for an older version of the schema to the
+current schema, in place".italic(),
+        "\n    grepq old-regex.json migrate-patterns --in-place".bold(),
+        "\n\nPrint the JSON schema of pattern files (no pattern file is needed)".italic(),
+        "\n    grepq schema".bold(),
         "\n\nTips:".bold().underline(),
         "\n\n1. Predicates can be used to filter on the header field (= record ID line)
 using a regex, minimum sequence length, and minimum average quality score
@@ -156,15 +172,22 @@ is specified.
 3. Use the `inverted` command to identify records that do not match any of the
 regex patterns in your pattern file.
 
-4. Ensure you have enough storage space for output files.",
+4. Use the `lint` command to check your pattern file before a run. It reports
+patterns that are empty (and so match every sequence), that duplicate or are
+subsumed by another pattern once IUPAC codes are converted, that are the reverse
+complement of another pattern, that have variants they cannot match, or that
+compile to a very large automaton, and exits with status 1 if any are found.
+
+5. Ensure you have enough storage space for output files.",
         "\n\nNotes:".bold().underline(),
         "\n\n1. `grepq` can output to several formats, including those that are
 gzip or zstd compressed. `grepq`, however, will only accept a FASTQ file or a 
-compressed (gzip or zstd) FASTQ file as the sequence data file. If you get an
-error message, check that the input data file is a FASTQ file or a gzip or zstd
-compressed FASTQ file, and that you have specified the correct file format 
-(--read-gzip or --read-zstd for FASTQ files compressed by gzip and zstd,
-respectively), and file path.
+compressed (gzip or zstd) FASTQ file as the sequence data file. The compression
+of the sequence data file is detected from its first bytes, so the --read-gzip
+and --read-zstd options are only needed to insist on gzip or zstd compressed
+input, respectively; `grepq` then exits with an error if the file is compressed
+otherwise. If you get an error message, check that the input data file is a FASTQ
+file or a gzip or zstd compressed FASTQ file, and the file path.
 
 2. Other than when the `inverted` command is given, output to a SQLite database
 is supported with the `writeSQL` option. The SQLite database will contain a table
@@ -185,7 +208,7 @@ FASTQ records. A summary of the invoked query (pattern and data files) is writte
 to a second table called `query`.
 
 3. Pattern files must contain one regex pattern per line or be given in JSON
-format, and patterns are case-sensitive (you can supply an empty pattern file to
+or YAML format, and patterns are case-sensitive (you can supply an empty pattern file to
 count the total number of records in the FASTQ file). The regex patterns should
 only include the DNA sequence characters (A, C, G, T), or IUPAC ambiguity codes
 (N, R, Y, ...). See 16S-no-iupac.txt, 16S-iupac.json and  
@@ -239,7 +262,9 @@ Journal of Open Source Software, 10(110), 8048, <https://doi.org/10.21105/joss.0
     version = clap::crate_version!(),
     about = "Quickly filter FASTQ files",
     long_about = "Copyright (c) 2024 - present: Nicholas D. Crosbie, licensed under the MIT License.",
-    after_help = &**AFTER_HELP
+    after_help = &**AFTER_HELP,
+    subcommand_negates_reqs = true,
+    subcommand_precedence_over_arg = true
 )]
 pub struct Cli {
     #[arg(long, hide = true)]
@@ -273,7 +298,8 @@ output (i.e. FASTQ format)"
     #[arg(
  //       short = 'x',
         long = "read-gzip",
-        help = "Read the FASTQ file in gzip compressed format"
+        help = "Read the FASTQ file in gzip compressed format (detected automatically
+when not given), failing if it is not gzip compressed"
     )]
     pub gzip_input: bool,
 
@@ -286,7 +312,9 @@ output (i.e. FASTQ format)"
 
     #[arg(
         long = "read-zstd",
-        help = "Read the FASTQ file in zstd compressed format"
+        conflicts_with = "gzip_input",
+        help = "Read the FASTQ file in zstd compressed format (detected automatically
+when not given), failing if it is not zstd compressed"
     )]
     pub zstd_input: bool,
 
@@ -330,11 +358,82 @@ tetranucleotides in the sequence of the matched FASTQ records",
     )]
     pub num_tetranucleotides: Option<usize>,
 
-    #[arg(help = "Path to the patterns file in plain text or JSON format")]
-    pub patterns: String,
+    #[arg(
+        long = "patterns-format",
+        value_enum,
+        help = "Format of the patterns file, overriding detection of the format
+from its content"
+    )]
+    pub patterns_format: Option<PatternFormat>,
+
+    #[arg(
+        required = true,
+        help = "Path to the patterns file in plain text, JSON, YAML or FASTA format (not
+needed by the `schema` command)"
+    )]
+    pub patterns: Option<String>,
+
+    #[arg(
+        required = true,
+        value_name = "FILE",
+        help = "Paths to one or more FASTQ files in plain text, gzip or zstd compressed
+format, or - to read from standard input. A quoted glob, e.g. 'run1/*.fastq.gz', is
+expanded by grepq. The records of all files are filtered together, with counts and
+summaries given for each file and in total (not needed by the `lint`,
+`migrate-patterns` and `schema` commands)"
+    )]
+    pub files: Vec<String>,
+
+    #[arg(
+        long = "R2",
+        value_name = "FILE",
+        conflicts_with = "interleaved",
+        help = "Path to the FASTQ file of second mates (R2) of paired-end reads, in
+which case the FASTQ file holds the first mates (R1)"
+    )]
+    pub mate_file: Option<String>,
+
+    #[arg(
+        long = "interleaved",
+        help = "The FASTQ file holds paired-end reads, each R1 record followed by
+its R2 mate"
+    )]
+    pub interleaved: bool,
+
+    #[arg(
+        long = "pair-rule",
+        value_enum,
+        default_value = "either",
+        help = "Which mates of paired-end reads the patterns are applied to: a pair
+matches if either mate matches, both mates match, or only R1 (r1) or R2 (r2) is
+searched. With --R2-patterns, each mate is searched with its own patterns"
+    )]
+    pub pair_rule: PairRule,
 
-    #[arg(help = "Path to the FASTQ file in plain text or gzip compressed format")]
-    pub file: String,
+    #[arg(
+        long = "R2-patterns",
+        value_name = "FILE",
+        help = "Path to a patterns file for the R2 mates of paired-end reads, in
+which case the patterns file is applied to the R1 mates only"
+    )]
+    pub mate_patterns: Option<String>,
+
+    #[arg(
+        long = "out-R1",
+        value_name = "FILE",
+        requires = "output_r2",
+        help = "Write the R1 mates of matching paired-end reads to this file (by
+default, mates are written interleaved)"
+    )]
+    pub output_r1: Option<String>,
+
+    #[arg(
+        long = "out-R2",
+        value_name = "FILE",
+        requires = "output_r1",
+        help = "Write the R2 mates of matching paired-end reads to this file"
+    )]
+    pub output_r2: Option<String>,
 
     #[command(subcommand)]
     pub command: Option<Commands>,
@@ -349,6 +448,58 @@ pub enum Commands {
     #[command(about = "Summarise records matching regex patterns and variants in
 the FASTQ file")]
     Summarise(Summarise),
+    #[command(about = "Check the patterns file for empty, redundant and costly patterns")]
+    Lint(Lint),
+    #[command(
+        about = "Rewrite a JSON or YAML patterns file of an older schema version to
+the current schema"
+    )]
+    MigratePatterns(MigratePatterns),
+    #[command(about = "Print the JSON schema of patterns files")]
+    Schema(Schema),
+}
+
+impl Cli {
+    // Returns the path to the patterns file, which every command but schema requires.
+    pub fn patterns(&self) -> &str {
+        self.patterns.as_deref().unwrap_or_default()
+    }
+
+    // Returns the path to the (first) FASTQ file, which every command but lint requires.
+    pub fn file(&self) -> &str {
+        self.files.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+#[derive(Parser)]
+pub struct Lint {
+    #[arg(
+        long = "json",
+        help = "Write the issues found as JSON, for use by other tools"
+    )]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct MigratePatterns {
+    #[arg(
+        short = 'i',
+        long = "in-place",
+        help = "Rewrite the patterns file in place, rather than printing the migrated
+file"
+    )]
+    pub in_place: bool,
+}
+
+#[derive(Parser)]
+pub struct Schema {
+    #[arg(
+        long = "schema-version",
+        value_name = "VERSION",
+        help = "Print the schema of an older version of patterns files, rather than the
+current schema"
+    )]
+    pub schema_version: Option<u64>,
 }
 
 #[derive(Parser)]
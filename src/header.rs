@@ -0,0 +1,294 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module filters records on their header (= record ID line). A header condition
+// compares a field of the header with a value, e.g. "lane == 2", and either includes or
+// excludes the records for which it holds. Fields are parsed from Illumina headers
+// (instrument:run:flowcell:lane:tile:x:y read:filtered:control:index) or from the
+// key=value pairs of Nanopore headers. The fields "header" (the whole header) and "id"
+// (the header up to the first whitespace) can be used with any header.
+
+use regex::bytes::Regex;
+
+// The fields of an Illumina header, in order.
+const ILLUMINA_FIELDS: [&str; 11] = [
+    "instrument",
+    "run",
+    "flowcell",
+    "lane",
+    "tile",
+    "x",
+    "y",
+    "read",
+    "filtered",
+    "control",
+    "index",
+];
+
+// The number of fields in the read ID of an Illumina header, before the first whitespace.
+const ILLUMINA_ID_FIELDS: usize = 7;
+
+// Enum: HeaderFormat
+// The layout of the headers of a FASTQ file, giving the fields header conditions refer to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderFormat {
+    Illumina,
+    Nanopore,
+}
+
+impl HeaderFormat {
+    // Returns the header format with the given name, as written in a patterns file.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "illumina" => Some(HeaderFormat::Illumina),
+            "nanopore" => Some(HeaderFormat::Nanopore),
+            _ => None,
+        }
+    }
+}
+
+// Struct: HeaderCondition
+// A header condition as written in a patterns file: an expression of the form
+// "FIELD OPERATOR VALUE", and whether the records for which it holds are excluded
+// rather than included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderCondition {
+    pub expression: String,
+    pub exclude: bool,
+}
+
+// Enum: Operator
+// Compares a header field with a value: as numbers, when both are numbers, for == and
+// != (and always for the ordering operators), or by regex for =~ and !~.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Matches,
+    NotMatches,
+}
+
+// The operators, with those sharing a prefix longest first.
+const OPERATORS: [(&str, Operator); 8] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("=~", Operator::Matches),
+    ("!~", Operator::NotMatches),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+// Enum: Field
+// A field of a header.
+enum Field {
+    Header,
+    Id,
+    // A field of an Illumina header, by its position.
+    Illumina(usize),
+    // The value of a key=value pair of a Nanopore header.
+    Key(Vec<u8>),
+}
+
+impl Field {
+    // Function: value
+    // Returns the value of the field in the given header, if present.
+    fn value<'a>(&self, head: &'a [u8]) -> Option<&'a [u8]> {
+        match self {
+            Field::Header => Some(head),
+            Field::Id => head.split(|b| b.is_ascii_whitespace()).next(),
+            Field::Illumina(index) => {
+                // The read ID is the first word with seven fields, so that headers prefixed
+                // with an accession (e.g. by the SRA) are parsed too.
+                let mut words = head
+                    .split(|b| b.is_ascii_whitespace())
+                    .skip_while(|word| word.split(|&b| b == b':').count() != ILLUMINA_ID_FIELDS);
+                let id = words.next()?;
+                if *index < ILLUMINA_ID_FIELDS {
+                    id.split(|&b| b == b':').nth(*index)
+                } else {
+                    words
+                        .find(|word| !word.is_empty())?
+                        .split(|&b| b == b':')
+                        .nth(index - ILLUMINA_ID_FIELDS)
+                }
+            }
+            Field::Key(key) => head
+                .split(|b| b.is_ascii_whitespace())
+                .skip(1)
+                .find_map(|pair| pair.strip_prefix(key.as_slice())?.strip_prefix(b"=")),
+        }
+    }
+}
+
+// Struct: Condition
+// A header condition, compiled.
+struct Condition {
+    field: Field,
+    operator: Operator,
+    value: Vec<u8>,
+    number: Option<f64>,
+    regex: Option<Regex>,
+    exclude: bool,
+}
+
+impl Condition {
+    // Function: new
+    // Parses the expression of a header condition, resolving its field for the given
+    // header format.
+    fn new(condition: &HeaderCondition, format: Option<HeaderFormat>) -> Result<Self, String> {
+        let expression = condition.expression.trim();
+        let invalid = || {
+            format!(
+                "Invalid header condition \"{}\": expected FIELD OPERATOR VALUE, e.g. \"lane == 2\"",
+                condition.expression
+            )
+        };
+        let name_end = expression
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(expression.len());
+        let (name, rest) = expression.split_at(name_end);
+        let rest = rest.trim_start();
+        let (symbol, operator) = OPERATORS
+            .iter()
+            .find(|(symbol, _)| rest.starts_with(symbol))
+            .ok_or_else(invalid)?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        let value = rest[symbol.len()..].trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        let field = match (name, format) {
+            ("header", _) => Field::Header,
+            ("id", _) => Field::Id,
+            (_, Some(HeaderFormat::Illumina)) => Field::Illumina(
+                ILLUMINA_FIELDS
+                    .iter()
+                    .position(|field| *field == name)
+                    .ok_or_else(|| format!("Unknown Illumina header field \"{}\"", name))?,
+            ),
+            (_, Some(HeaderFormat::Nanopore)) => Field::Key(name.as_bytes().to_vec()),
+            (_, None) => {
+                return Err(format!(
+                    "Header field \"{}\" requires a headerFormat (illumina or nanopore)",
+                    name
+                ))
+            }
+        };
+        let number = value.parse::<f64>().ok();
+        if matches!(
+            operator,
+            Operator::Less | Operator::LessOrEqual | Operator::Greater | Operator::GreaterOrEqual
+        ) && number.is_none()
+        {
+            return Err(format!(
+                "Invalid header condition \"{}\": {} requires a number",
+                condition.expression, symbol
+            ));
+        }
+        let regex = match operator {
+            Operator::Matches | Operator::NotMatches => Some(Regex::new(value).map_err(|e| {
+                format!(
+                    "Invalid header condition \"{}\": {}",
+                    condition.expression, e
+                )
+            })?),
+            _ => None,
+        };
+        Ok(Self {
+            field,
+            operator: *operator,
+            value: value.as_bytes().to_vec(),
+            number,
+            regex,
+            exclude: condition.exclude,
+        })
+    }
+
+    // Function: holds
+    // Returns true if the condition holds for the given header. A condition on a field
+    // the header does not have, or on a field that is not a number where one is
+    // required, does not hold.
+    fn holds(&self, head: &[u8]) -> bool {
+        let Some(value) = self.field.value(head) else {
+            return false;
+        };
+        let number = || std::str::from_utf8(value).ok()?.parse::<f64>().ok();
+        let compare = |holds: fn(f64, f64) -> bool| {
+            number().zip(self.number).is_some_and(|(a, b)| holds(a, b))
+        };
+        let equal = || match (number(), self.number) {
+            (Some(a), Some(b)) => a == b,
+            _ => value == self.value.as_slice(),
+        };
+        match self.operator {
+            Operator::Equal => equal(),
+            Operator::NotEqual => !equal(),
+            Operator::Less => compare(|a, b| a < b),
+            Operator::LessOrEqual => compare(|a, b| a <= b),
+            Operator::Greater => compare(|a, b| a > b),
+            Operator::GreaterOrEqual => compare(|a, b| a >= b),
+            Operator::Matches => self.regex.as_ref().is_some_and(|re| re.is_match(value)),
+            Operator::NotMatches => !self.regex.as_ref().is_some_and(|re| re.is_match(value)),
+        }
+    }
+}
+
+// Struct: HeaderFilter
+// A list of header conditions, compiled. A header passes if every include condition
+// holds and no exclude condition holds.
+pub struct HeaderFilter {
+    conditions: Vec<Condition>,
+}
+
+impl HeaderFilter {
+    // Function: new
+    // Compiles the header conditions for headers of the given format.
+    pub fn new(
+        conditions: &[HeaderCondition],
+        format: Option<HeaderFormat>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            conditions: conditions
+                .iter()
+                .map(|condition| Condition::new(condition, format))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Returns true if the given header passes the conditions.
+    #[inline(always)]
+    pub fn passes(&self, head: &[u8]) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.holds(head) != condition.exclude)
+    }
+}
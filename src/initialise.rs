@@ -21,31 +21,67 @@
 // SOFTWARE.
 
 use crate::arg::Cli;
+use crate::input::{input_files, FastqFiles};
+use crate::iupac::expand_iupac_regex;
+use crate::patterns::{PatternError, PatternSet};
 use flate2::read::MultiGzDecoder;
 use flate2::write::GzEncoder as MultiGzEncoder;
 pub(crate) use flate2::Compression;
-use regex::bytes::RegexSet;
 use seq_io::fastq::Reader;
-use serde_json::Value;
 use std::fs::File;
 use std::io::BufWriter;
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use zstd::stream::{read::Decoder as ZstdDecoder, write::Encoder as ZstdEncoder};
 
 // Static JSON schema used to validate the input patterns file.
-static SCHEMA: &str = r#"
+pub(crate) static SCHEMA: &str = r##"
 {
     "$schema": "http://json-schema.org/draft-07/schema#",
     "title": "grepq",
-    "version": 2,
+    "version": 3,
     "type": "object",
     "properties": {
+        "schemaVersion": {
+            "type": "integer",
+            "const": 3
+        },
+        "regexSet": {
+            "$ref": "#/definitions/regexSet"
+        },
+        "regexSets": {
+            "type": "array",
+            "minItems": 1,
+            "items": {
+                "$ref": "#/definitions/regexSet"
+            }
+        }
+    },
+    "oneOf": [
+        {
+            "required": [
+                "regexSet"
+            ]
+        },
+        {
+            "required": [
+                "regexSets"
+            ]
+        }
+    ],
+    "definitions": {
         "regexSet": {
             "type": "object",
             "properties": {
                 "regexSetName": {
                     "type": "string"
                 },
+                "include": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "string"
+                    }
+                },
                 "regex": {
                     "type": "array",
                     "minItems": 1,
@@ -58,6 +94,24 @@ static SCHEMA: &str = r#"
                             "regexString": {
                                 "type": "string"
                             },
+                            "reverseComplement": {
+                                "type": "boolean"
+                            },
+                            "patternType": {
+                                "type": "string",
+                                "enum": [
+                                    "nucleotide",
+                                    "protein"
+                                ]
+                            },
+                            "maxMismatches": {
+                                "type": "integer",
+                                "minimum": 0
+                            },
+                            "maxEdits": {
+                                "type": "integer",
+                                "minimum": 0
+                            },
                             "variants": {
                                 "type": "array",
                                 "items": {
@@ -75,6 +129,47 @@ static SCHEMA: &str = r#"
                                         "variantString"
                                     ]
                                 }
+                            },
+                            "headerRegex": {
+                                "type": "string"
+                            },
+                            "headerConditions": {
+                                "$ref": "#/definitions/headerConditions"
+                            },
+                            "minimumSequenceLength": {
+                                "type": "number"
+                            },
+                            "minimumAverageQuality": {
+                                "type": "number"
+                            },
+                            "minimumMatchQuality": {
+                                "type": "integer",
+                                "minimum": 0
+                            },
+                            "minimumMatchAverageQuality": {
+                                "type": "number"
+                            },
+                            "window": {
+                                "type": "object",
+                                "properties": {
+                                    "first": {
+                                        "type": "integer",
+                                        "minimum": 1
+                                    },
+                                    "last": {
+                                        "type": "integer",
+                                        "minimum": 1
+                                    },
+                                    "start": {
+                                        "type": "integer",
+                                        "minimum": 0
+                                    },
+                                    "end": {
+                                        "type": "integer",
+                                        "minimum": 1
+                                    }
+                                },
+                                "additionalProperties": false
                             }
                         },
                         "required": [
@@ -86,6 +181,19 @@ static SCHEMA: &str = r#"
                 "headerRegex": {
                     "type": "string"
                 },
+                "headerConditions": {
+                    "$ref": "#/definitions/headerConditions"
+                },
+                "headerFormat": {
+                    "type": "string",
+                    "enum": [
+                        "illumina",
+                        "nanopore"
+                    ]
+                },
+                "caseInsensitive": {
+                    "type": "boolean"
+                },
                 "minimumSequenceLength": {
                     "type": "number"
                 },
@@ -94,220 +202,363 @@ static SCHEMA: &str = r#"
                 },
                 "qualityEncoding": {
                     "type": "string"
+                },
+                "minimumMatchQuality": {
+                    "type": "integer",
+                    "minimum": 0
+                },
+                "minimumMatchAverageQuality": {
+                    "type": "number"
+                },
+                "reverseComplement": {
+                    "type": "boolean"
+                },
+                "patternType": {
+                    "type": "string",
+                    "enum": [
+                        "nucleotide",
+                        "protein"
+                    ]
+                },
+                "combination": {
+                    "$ref": "#/definitions/combination"
+                },
+                "amplicons": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "ampliconName": {
+                                "type": "string"
+                            },
+                            "forward": {
+                                "type": "string"
+                            },
+                            "reverse": {
+                                "type": "string"
+                            },
+                            "minimumInsert": {
+                                "type": "integer",
+                                "minimum": 0
+                            },
+                            "maximumInsert": {
+                                "type": "integer",
+                                "minimum": 0
+                            }
+                        },
+                        "required": [
+                            "ampliconName",
+                            "forward",
+                            "reverse"
+                        ],
+                        "additionalProperties": false
+                    }
+                },
+                "output": {
+                    "type": "string"
                 }
             },
             "required": [
-                "regexSetName",
-                "regex"
+                "regexSetName"
+            ],
+            "anyOf": [
+                {
+                    "required": [
+                        "regex"
+                    ]
+                },
+                {
+                    "required": [
+                        "include"
+                    ]
+                }
             ]
+        },
+        "combination": {
+            "oneOf": [
+                {
+                    "type": "string"
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "all": {
+                            "type": "array",
+                            "minItems": 1,
+                            "items": {
+                                "$ref": "#/definitions/combination"
+                            }
+                        }
+                    },
+                    "required": [
+                        "all"
+                    ],
+                    "additionalProperties": false
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "any": {
+                            "type": "array",
+                            "minItems": 1,
+                            "items": {
+                                "$ref": "#/definitions/combination"
+                            }
+                        }
+                    },
+                    "required": [
+                        "any"
+                    ],
+                    "additionalProperties": false
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "atLeast": {
+                            "type": "integer",
+                            "minimum": 1
+                        },
+                        "of": {
+                            "type": "array",
+                            "minItems": 1,
+                            "items": {
+                                "$ref": "#/definitions/combination"
+                            }
+                        }
+                    },
+                    "required": [
+                        "atLeast",
+                        "of"
+                    ],
+                    "additionalProperties": false
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "not": {
+                            "$ref": "#/definitions/combination"
+                        }
+                    },
+                    "required": [
+                        "not"
+                    ],
+                    "additionalProperties": false
+                }
+            ]
+        },
+        "headerConditions": {
+            "type": "array",
+            "minItems": 1,
+            "items": {
+                "oneOf": [
+                    {
+                        "type": "object",
+                        "properties": {
+                            "include": {
+                                "type": "string"
+                            }
+                        },
+                        "required": [
+                            "include"
+                        ],
+                        "additionalProperties": false
+                    },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "exclude": {
+                                "type": "string"
+                            }
+                        },
+                        "required": [
+                            "exclude"
+                        ],
+                        "additionalProperties": false
+                    }
+                ]
+            }
         }
-    },
-    "required": [
-        "regexSet"
-    ]
+    }
 }
-"#;
-
-type ParseResult = Result<
-    (
-        RegexSet,
-        Option<String>,
-        Option<u64>,
-        Option<f32>,
-        Option<String>,
-        Vec<String>,           // regex_names
-        Vec<(String, String)>, // variants
-    ),
-    String,
->;
+"##;
 
 // Function: convert_iupac_to_regex
-// Converts IUPAC nucleotide codes to a regex pattern by replacing ambiguous characters.
-// It panics if an illegal character is encountered.
-pub fn convert_iupac_to_regex(pattern: &str) -> String {
-    let legal = "ACGTYRWSKMBDHVN";
+// Converts IUPAC nucleotide codes to a regex pattern by expanding ambiguous codes
+// wherever they appear as literals (see iupac::expand_iupac_regex).
+// It returns an error, locating the offending character, if an illegal character is encountered.
+pub fn convert_iupac_to_regex(pattern: &str) -> Result<String, PatternError> {
+    expand_iupac_regex(pattern)
+}
 
-    // Check if the pattern *could* be an IUPAC pattern (all chars are alphabetic).
-    let could_be_iupac = pattern.chars().all(|c| c.is_alphabetic());
+// Function: load_patterns
+// Reads the patterns file given on the command line. An invalid patterns file is
+// reported, with the location of the error, and grepq exits with a non-zero status.
+pub fn load_patterns(cli: &Cli) -> PatternSet {
+    load_patterns_file(cli, cli.patterns())
+}
 
-    if could_be_iupac {
-        // If it *could* be IUPAC, check for illegal characters and panic if found.
-        for c in pattern.chars() {
-            if c.is_alphabetic() && !legal.contains(c.to_ascii_uppercase()) {
-                panic!("Illegal character found in pattern: {}", c);
-            }
+// Function: load_patterns_file
+// Reads the given patterns file (e.g. the patterns for R2 mates), exiting as for
+// load_patterns if it is invalid.
+pub fn load_patterns_file(cli: &Cli, path: &str) -> PatternSet {
+    match PatternSet::from_file(path, cli.patterns_format) {
+        Ok(pattern_set) => pattern_set,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-
-        // If it's a valid IUPAC pattern, convert it to uppercase and replace characters.
-        let uppercase = pattern.to_uppercase();
-        uppercase
-            .replace('Y', "[CT]")
-            .replace('R', "[AG]")
-            .replace('W', "[AT]")
-            .replace('S', "[CG]")
-            .replace('K', "[GT]")
-            .replace('M', "[AC]")
-            .replace('B', "[CGT]")
-            .replace('D', "[AGT]")
-            .replace('H', "[ACT]")
-            .replace('V', "[ACG]")
-            .replace('N', "[ACGT]")
-    } else {
-        // If it's not IUPAC, return the original pattern unchanged.
-        pattern.to_string()
     }
 }
 
-// Function: validate_dna_sequence
-// Validates that a given sequence contains only valid DNA nucleotides.
-fn validate_dna_sequence(sequence: &str) -> Result<(), String> {
-    if sequence.chars().all(|c| "ACTG".contains(c)) {
-        Ok(())
-    } else {
-        Err(format!("Invalid DNA sequence: {}", sequence))
+// Function: load_pattern_sets
+// Reads each regex set of the patterns file given on the command line, exiting as for
+// load_patterns if the patterns file is invalid.
+pub fn load_pattern_sets(cli: &Cli) -> Vec<PatternSet> {
+    match PatternSet::sets_from_file(cli.patterns(), cli.patterns_format) {
+        Ok(pattern_sets) => pattern_sets,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
 
-// Function: parse_patterns_file
-// Parses a patterns file which may be JSON or plain text, validates it against a schema,
-// and compiles regex patterns. It also extracts optional settings.
-pub fn parse_patterns_file(patterns_path: &str) -> ParseResult {
-    if patterns_path.ends_with(".json") {
-        // Open and parse the JSON file.
-        let json_file =
-            File::open(patterns_path).map_err(|e| format!("Failed to open JSON file: {}", e))?;
-        let json: Value = serde_json::from_reader(json_file)
-            .map_err(|e| format!("Failed to parse JSON file: {}", e))?;
-        let schema: Value = serde_json::from_str(SCHEMA)
-            .map_err(|e| format!("Failed to parse embedded schema: {}", e))?;
-
-        let validator = jsonschema::validator_for(&schema)
-            .map_err(|e| format!("Failed to compile schema: {}", e))?;
+// Enum: InputCompression
+// The compression of a FASTQ file, detected from its first bytes (its magic number).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputCompression {
+    Plain,
+    Gzip,
+    Zstd,
+}
 
-        let mut error_messages = Vec::new();
-        for error in validator.iter_errors(&json) {
-            error_messages.push(format!(
-                "Error: {error}\nLocation: {}",
-                error.instance_path()
-            ));
+impl InputCompression {
+    // Function: detect
+    // Returns the compression of a file starting with the given bytes. Anything that is
+    // neither gzip nor zstd compressed is read as plain text.
+    pub fn detect(start: &[u8]) -> Self {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            InputCompression::Gzip
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            InputCompression::Zstd
+        } else {
+            InputCompression::Plain
         }
+    }
 
-        if !error_messages.is_empty() {
-            return Err(format!("JSON validation errors: {:?}", error_messages));
+    // Returns a description of the compression, for error messages.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            InputCompression::Plain => "not compressed",
+            InputCompression::Gzip => "gzip compressed",
+            InputCompression::Zstd => "zstd compressed",
         }
+    }
+}
 
-        // Convert patterns using IUPAC-to-regex conversion.
-        let regex_strings: Vec<String> = json["regexSet"]["regex"]
-            .as_array()
-            .ok_or("Invalid JSON structure")?
-            .iter()
-            .filter_map(|r| {
-                r.get("regexString")
-                    .and_then(|s| s.as_str())
-                    .map(convert_iupac_to_regex)
-            })
-            .collect();
+// Function: open_input
+// Opens the given FASTQ file for reading, or standard input if the path is "-". Named
+// pipes (e.g. from process substitution) are opened as any other file. grepq exits with
+// an error if the file cannot be opened.
+pub fn open_input(file_path: &str) -> Box<dyn Read + Send> {
+    if file_path == "-" {
+        return Box::new(io::stdin());
+    }
+    match File::open(file_path) {
+        Ok(file) => Box::new(file),
+        Err(e) => {
+            eprintln!("Error: Failed to open {}: {}", file_path, e);
+            std::process::exit(1);
+        }
+    }
+}
 
-        let regex_set = RegexSet::new(&regex_strings)
-            .map_err(|e| format!("Failed to compile regex patterns: {}", e))?;
-        let header_regex = json["regexSet"]["headerRegex"]
-            .as_str()
-            .map(|s| s.to_string());
-        let minimum_sequence_length = json["regexSet"]["minimumSequenceLength"].as_u64();
-        let minimum_quality = json["regexSet"]["minimumAverageQuality"]
-            .as_f64()
-            .map(|q| q as f32);
-        let quality_encoding = json["regexSet"]["qualityEncoding"]
-            .as_str()
-            .map(|s| s.to_string());
+// Function: input_paths
+// Returns the input files, with globs among them expanded (see input_files). grepq exits
+// with an error if a file does not exist or a glob matches no file.
+pub fn input_paths(cli: &Cli) -> Vec<String> {
+    match input_files(&cli.files) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-        // Process regex variants with additional DNA validation.
-        let variants: Vec<_> = json["regexSet"]["regex"]
-            .as_array()
-            .ok_or("Invalid JSON structure")?
-            .iter()
-            .filter_map(|r| r.get("variants"))
-            .flat_map(|v| v.as_array().unwrap_or(&Vec::new()).clone())
-            .map(|variant| -> Result<_, String> {
-                let variant_name = variant["variantName"]
-                    .as_str()
-                    .ok_or("Invalid variantName")?
-                    .to_string();
-                let variant_string = variant["variantString"]
-                    .as_str()
-                    .ok_or("Invalid variantString")?
-                    .to_string();
-                validate_dna_sequence(&variant_string)?;
-                Ok((variant_name, variant_string))
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+// Function: create_reader
+// Creates the reader of the input files, which reads them one after the other, detecting
+// the compression of each (see decode_input), for parallel_fastq_files.
+pub fn create_reader(cli: &Cli) -> FastqFiles {
+    FastqFiles::new(input_paths(cli), forced_compression(cli))
+}
 
-        let regex_names = json["regexSet"]["regex"]
-            .as_array()
-            .ok_or("Invalid JSON structure")?
-            .iter()
-            .map(|r| {
-                r.get("regexName")
-                    .and_then(|s| s.as_str())
-                    .unwrap_or_else(|| r.get("regexString").and_then(|s| s.as_str()).unwrap())
-                    .to_string()
-            })
-            .collect::<Vec<_>>();
+// Function: create_file_reader
+// Creates a buffered reader for the given FASTQ file (e.g. the R2 file of paired-end
+// reads), or standard input if the path is "-", detecting its compression (see
+// decode_input).
+pub fn create_file_reader(cli: &Cli, file_path: &str) -> Reader<Box<dyn BufRead + Send>> {
+    Reader::with_capacity(
+        decode_input(file_path, forced_compression(cli)),
+        8 * 1024 * 1024,
+    )
+}
 
-        Ok((
-            regex_set,
-            header_regex,
-            minimum_sequence_length,
-            minimum_quality,
-            quality_encoding,
-            regex_names, // Regex pattern names.
-            variants,    // Regex variants.
-        ))
+// Function: forced_compression
+// Returns the compression forced by --read-gzip or --read-zstd, with the flag forcing it.
+pub fn forced_compression(cli: &Cli) -> Option<(InputCompression, &'static str)> {
+    if cli.gzip_input {
+        Some((InputCompression::Gzip, "--read-gzip"))
+    } else if cli.zstd_input {
+        Some((InputCompression::Zstd, "--read-zstd"))
     } else {
-        // Process plain-text pattern files.
-        let file = File::open(patterns_path)
-            .map_err(|e| format!("Failed to open patterns file: {}", e))?;
-        let reader = BufReader::new(file);
-        let lines: Result<Vec<_>, _> = reader.lines().collect();
-        let regex_strings: Vec<String> = lines
-            .map_err(|e| format!("Failed to read lines: {}", e))?
-            .iter()
-            .map(|line| convert_iupac_to_regex(line))
-            .collect();
-        let regex_set = RegexSet::new(&regex_strings)
-            .map_err(|e| format!("Failed to compile regex patterns: {}", e))?;
-        let regex_names = regex_strings.clone(); // Use regex strings as names.
-        Ok((regex_set, None, None, None, None, regex_names, Vec::new()))
+        None
     }
 }
 
-// Function: open_file
-// Opens the given file path and returns a File handle.
-pub fn open_file(file_path: &str) -> File {
-    File::open(file_path).expect("Failed to open file")
-}
-
-// Function: create_reader
-// Creates a buffered reader for the input file, handling compression based on CLI flags.
-pub fn create_reader(cli: &Cli) -> Reader<Box<dyn BufRead + Send>> {
-    let file = open_file(&cli.file);
-    let reader: Box<dyn BufRead + Send> = if cli.gzip_input {
+// Function: decode_input
+// Opens the given FASTQ file, or standard input if the path is "-", decompressing it.
+// The compression of the file is detected from its first bytes; if a compression is
+// forced, grepq exits with an error if the file is not compressed as forced.
+pub fn decode_input(
+    file_path: &str,
+    forced: Option<(InputCompression, &'static str)>,
+) -> Box<dyn BufRead + Send> {
+    let mut input = open_input(file_path);
+    // Read the first bytes, which a pipe may deliver a few at a time, and put them back
+    // in front of the rest of the input for the decoder.
+    let mut start = Vec::with_capacity(4);
+    if let Err(e) = (&mut input).take(4).read_to_end(&mut start) {
+        eprintln!("Error: Failed to read {}: {}", file_path, e);
+        std::process::exit(1);
+    }
+    let detected = InputCompression::detect(&start);
+    let file = BufReader::new(io::Cursor::new(start).chain(input));
+    if let Some((compression, flag)) = forced.filter(|(compression, _)| *compression != detected) {
+        eprintln!(
+            "Error: {} was given, but {} is not {} (it is {}). Omit the flag to detect the compression automatically.",
+            flag,
+            file_path,
+            compression.describe(),
+            detected.describe()
+        );
+        std::process::exit(1);
+    }
+    match detected {
         // Use Gzip decompression.
-        Box::new(BufReader::new(MultiGzDecoder::new(file)))
-    } else if cli.zstd_input {
+        InputCompression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
         // Use Zstd decompression.
-        match ZstdDecoder::new(file) {
+        InputCompression::Zstd => match ZstdDecoder::with_buffer(file) {
             Ok(decoder) => Box::new(BufReader::new(decoder)),
             Err(e) => {
                 eprintln!("Error: Failed to read zstd compressed file. The file may be corrupted or incomplete.");
                 eprintln!("Underlying error: {}", e);
                 std::process::exit(1);
             }
-        }
-    } else {
-        Box::new(BufReader::new(file))
-    };
-    Reader::with_capacity(reader, 8 * 1024 * 1024)
+        },
+        InputCompression::Plain => Box::new(file),
+    }
 }
 
 // Struct ZstdWriter
@@ -359,8 +610,25 @@ impl<W: Write> Drop for ZstdWriter<W> {
 // Function: create_writer
 // Creates a writer for the output file, handling various compression or formatting options based on CLI flags.
 pub fn create_writer(cli: &Cli) -> Box<dyn Write> {
-    let stdout_lock = io::stdout().lock();
+    wrap_writer(cli, io::stdout().lock())
+}
+
+// Function: create_file_writer
+// Creates a writer for the given output file (e.g. the R1 or R2 output of paired-end
+// reads), handling compression based on CLI flags.
+pub fn create_file_writer(cli: &Cli, file_path: &str) -> Box<dyn Write> {
+    match File::create(file_path) {
+        Ok(file) => wrap_writer(cli, BufWriter::new(file)),
+        Err(e) => {
+            eprintln!("Error: Failed to create output file {}: {}", file_path, e);
+            std::process::exit(1);
+        }
+    }
+}
 
+// Function: wrap_writer
+// Wraps an output stream with the compression or buffering given by CLI flags.
+fn wrap_writer<W: Write + 'static>(cli: &Cli, output: W) -> Box<dyn Write> {
     if cli.gzip_output {
         // Write output using Gzip compression.
         let compression = if cli.fast_compression {
@@ -370,7 +638,7 @@ pub fn create_writer(cli: &Cli) -> Box<dyn Write> {
         } else {
             Compression::default()
         };
-        Box::new(MultiGzEncoder::new(stdout_lock, compression))
+        Box::new(MultiGzEncoder::new(output, compression))
     } else if cli.zstd_output {
         // Write output using Zstd compression.
         let level = if cli.fast_compression {
@@ -380,12 +648,12 @@ pub fn create_writer(cli: &Cli) -> Box<dyn Write> {
         } else {
             3
         };
-        Box::new(ZstdWriter::new(stdout_lock, level).unwrap())
+        Box::new(ZstdWriter::new(output, level).unwrap())
     } else if cli.with_fasta {
         // Write output in FASTA format.
-        Box::new(BufWriter::new(stdout_lock))
+        Box::new(BufWriter::new(output))
     } else {
         // Default to writing plain output.
-        Box::new(stdout_lock)
+        Box::new(output)
     }
 }
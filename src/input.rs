@@ -0,0 +1,176 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module reads several FASTQ files, one after the other, through a single parallel
+// pipeline, so that the patterns are compiled once, and attributes each record to the file
+// it was read from, for counts and summaries given per file. Each file has a reader of
+// its own, and the records are passed on in sets, each tagged with its file. Globs among
+// the file arguments, e.g. quoted so that the shell leaves them alone, are expanded here.
+
+use crate::initialise::{decode_input, InputCompression};
+use seq_io::fastq::{self, RecordSet, RefRecord};
+use seq_io::parallel::{read_parallel, Reader, ReusableReader};
+use std::io::BufRead;
+use std::path::Path;
+
+// Function: input_files
+// Returns the FASTQ files to read, in order, with each glob replaced by the files it
+// matches, sorted. An argument that names an existing file is taken as is, even if it
+// contains glob characters. As in the shell, a wildcard does not match a leading '.'.
+// Returns an error for an invalid glob, a glob matching no file, a file that does not
+// exist, or standard input ("-") given more than once.
+pub fn input_files(arguments: &[String]) -> Result<Vec<String>, String> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let mut files = Vec::new();
+    for argument in arguments {
+        if argument == "-" {
+            if files.iter().any(|file| file == "-") {
+                return Err("Standard input (-) can only be given once".to_string());
+            }
+            files.push(argument.clone());
+        } else if Path::new(argument).exists() || !argument.contains(['*', '?', '[']) {
+            if let Err(e) = std::fs::metadata(argument) {
+                return Err(format!("Failed to open {}: {}", argument, e));
+            }
+            files.push(argument.clone());
+        } else {
+            let paths = glob::glob_with(argument, options)
+                .map_err(|e| format!("Invalid glob {}: {}", argument, e))?;
+            let mut matched = Vec::new();
+            for path in paths {
+                let path = path.map_err(|e| format!("Failed to read {}: {}", argument, e))?;
+                matched.push(path.to_string_lossy().into_owned());
+            }
+            if matched.is_empty() {
+                return Err(format!("No files match {}", argument));
+            }
+            files.extend(matched);
+        }
+    }
+    Ok(files)
+}
+
+// Struct: FileRecordSet
+// A set of records read from one input file, and the index of that file.
+#[derive(Default)]
+pub struct FileRecordSet {
+    file: usize,
+    records: RecordSet,
+}
+
+// Struct: FastqFiles
+// Reads the records of several FASTQ files in sets, one file after the other, each file
+// opened when the one before it ends and decompressed as detected (see decode_input).
+pub struct FastqFiles {
+    paths: Vec<String>,
+    forced: Option<(InputCompression, &'static str)>,
+    reader: Option<fastq::Reader<Box<dyn BufRead + Send>>>,
+    file: usize,
+}
+
+impl FastqFiles {
+    // Function: new
+    // Creates the reader of the given files, decompressed as forced (see decode_input).
+    pub fn new(paths: Vec<String>, forced: Option<(InputCompression, &'static str)>) -> Self {
+        Self {
+            paths,
+            forced,
+            reader: None,
+            file: 0,
+        }
+    }
+
+    // Returns the paths of the files, in the order they are read.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+}
+
+impl Reader for FastqFiles {
+    type DataSet = FileRecordSet;
+    type Err = fastq::Error;
+
+    fn fill_data(&mut self, set: &mut FileRecordSet) -> Option<Result<(), fastq::Error>> {
+        while self.file < self.paths.len() {
+            let reader = self.reader.get_or_insert_with(|| {
+                let input = decode_input(&self.paths[self.file], self.forced);
+                fastq::Reader::with_capacity(input, 8 * 1024 * 1024)
+            });
+            match reader.read_record_set(&mut set.records) {
+                Some(result) => {
+                    set.file = self.file;
+                    return Some(result);
+                }
+                None => {
+                    self.reader = None;
+                    self.file += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+// Function: parallel_fastq_files
+// Processes the records of the input files in parallel, as seq_io's parallel_fastq does
+// for a single reader: work is applied to each record in a worker thread, and func to
+// each record, with the result of work and the index of the file the record was read
+// from, in the main thread. As with parallel_fastq, record sets are handed to func as
+// their work completes, so records may not be in input order, and the results of work
+// are recycled with their record set: work receives the result of an earlier record,
+// which it must overwrite.
+pub fn parallel_fastq_files<D, W, F>(
+    files: FastqFiles,
+    n_threads: u32,
+    queue_len: usize,
+    work: W,
+    mut func: F,
+) -> Result<(), fastq::Error>
+where
+    D: Default + Send,
+    W: Send + Sync + Fn(RefRecord, &mut D),
+    F: FnMut(RefRecord, &mut D, usize),
+{
+    read_parallel(
+        ReusableReader::new(files),
+        n_threads,
+        queue_len,
+        |(set, outs): &mut (FileRecordSet, Vec<D>)| {
+            outs.resize_with(set.records.len(), D::default);
+            for (record, out) in (&set.records).into_iter().zip(outs.iter_mut()) {
+                work(record, out);
+            }
+        },
+        |sets| {
+            while let Some(result) = sets.next() {
+                let ((set, outs), ()) = result?;
+                for (record, out) in (&set.records).into_iter().zip(outs.iter_mut()) {
+                    func(record, out, set.file);
+                }
+            }
+            Ok(())
+        },
+    )
+}
@@ -24,11 +24,12 @@
 // This command filters FASTQ records using a variety of criteria (sequence length, quality, header, regex)
 // and either counts matching records or outputs them in one of several formats.
 use crate::arg::Cli;
-use crate::initialise::{create_reader, create_writer, parse_patterns_file};
-use crate::output::{write_full_record, write_record_with_fasta, write_record_with_id};
-use regex::bytes::Regex;
+use crate::initialise::{create_reader, create_writer, load_patterns};
+use crate::input::parallel_fastq_files;
+use crate::output::{
+    write_counts, write_full_record, write_record_with_fasta, write_record_with_id,
+};
 use seq_io::fastq::Record;
-use seq_io::parallel::parallel_fastq;
 use std::io::Write;
 
 // Main function to run the inverted command
@@ -40,93 +41,55 @@ pub fn run_inverted(cli: &Cli) {
     let count = cli.count;
 
     // Parse the patterns file to extract regex patterns and optional filter parameters.
-    let (regex_set, header_regex, minimum_sequence_length, minimum_quality, quality_encoding, _, _) =
-        parse_patterns_file(&cli.patterns)
-            .map_err(std::io::Error::other)
-            .unwrap();
-
-    // Compile the optional header regex.
-    let header_regex = header_regex.map(|re| {
-        // Compile header regex filter.
-        Regex::new(&re).unwrap()
-    });
+    let pattern_set = load_patterns(cli);
 
     // Create input reader and output writer based on CLI flags.
     let reader = create_reader(cli);
+    let paths = reader.paths().to_vec();
     let mut writer = create_writer(cli);
 
-    // Determine which filters to apply.
-    let check_seq_len = minimum_sequence_length.is_some();
-    let check_qual = minimum_quality.is_some();
-    let check_header = header_regex.is_some();
-
     // Initialize buffers to reuse memory.
     let mut seq_buffer = Vec::new();
     let mut qual_buffer = Vec::new();
     let mut head_buffer = Vec::new();
 
     if count {
-        // Count mode: Only count records that match the filter criteria.
-        let mut match_count = 0;
-        parallel_fastq(
+        // Count mode: Only count records that match the filter criteria, for each input file.
+        let mut match_counts = vec![vec![0]; paths.len()];
+        parallel_fastq_files(
             reader,
             num_cpus::get() as u32,
             num_cpus::get(),
             |record, found| {
-                // Worker thread: Apply filters on each record.
-                *found = false;
-                let seq_len_check = !check_seq_len
-                    || record.seq().len() >= minimum_sequence_length.unwrap() as usize;
-                let qual_check = !check_qual
-                    || crate::quality::average_quality(
-                        record.qual(),
-                        quality_encoding.as_deref().unwrap_or("Phred+33"),
-                    ) >= minimum_quality.unwrap();
-                let header_check =
-                    !check_header || header_regex.as_ref().unwrap().is_match(record.head());
-                let regex_check = !regex_set.is_match(record.seq());
-
-                // Mark record as matching if all conditions are met.
-                if seq_len_check && qual_check && header_check && regex_check {
-                    *found = true;
-                }
+                // Worker thread: Apply filters on each record. A record is kept if it passes
+                // the set-wide predicates and no pattern is found in it.
+                *found = pattern_set.passes_predicates(record.head(), record.seq(), record.qual())
+                    && !pattern_set.is_match(record.head(), record.seq(), record.qual());
             },
-            |_, found| {
-                // Main thread: Increment count based on the worker's flag.
+            |_, found, file| {
+                // Main thread: Increment the count of the file the record was read from, based
+                // on the worker's flag.
                 if *found {
-                    match_count += 1;
+                    match_counts[file][0] += 1;
                 }
-                None::<()>
             },
         )
         .unwrap();
-        // Output the count.
-        writeln!(writer, "{}", match_count).unwrap();
+        // Output the counts.
+        let set_name = pattern_set.name().unwrap_or("Unknown");
+        write_counts(&mut writer, &paths, &[set_name], &match_counts).unwrap();
     } else {
         // Record output mode: Write records based on the selected output format.
-        parallel_fastq(
+        parallel_fastq_files(
             reader,
             num_cpus::get() as u32,
             num_cpus::get(),
             |record, found| {
                 // Worker thread: Check filter criteria.
-                *found = false;
-                let seq_len_check = !check_seq_len
-                    || record.seq().len() >= minimum_sequence_length.unwrap() as usize;
-                let qual_check = !check_qual
-                    || crate::quality::average_quality(
-                        record.qual(),
-                        quality_encoding.as_deref().unwrap_or("Phred+33"),
-                    ) >= minimum_quality.unwrap();
-                let header_check =
-                    !check_header || header_regex.as_ref().unwrap().is_match(record.head());
-                let regex_check = !regex_set.is_match(record.seq());
-
-                if seq_len_check && qual_check && header_check && regex_check {
-                    *found = true;
-                }
+                *found = pattern_set.passes_predicates(record.head(), record.seq(), record.qual())
+                    && !pattern_set.is_match(record.head(), record.seq(), record.qual());
             },
-            |record, found| {
+            |record, found, _| {
                 // Main thread: Write the record in the appropriate format if it passed the filters.
                 if *found {
                     if with_id {
@@ -136,6 +99,7 @@ pub fn run_inverted(cli: &Cli) {
                             &record,
                             &mut head_buffer,
                             &mut seq_buffer,
+                            b"",
                         );
                     } else if with_full_record {
                         // Output the full FASTQ record.
@@ -145,6 +109,7 @@ pub fn run_inverted(cli: &Cli) {
                             &mut head_buffer,
                             &mut seq_buffer,
                             &mut qual_buffer,
+                            b"",
                         );
                     } else if with_fasta {
                         // Output in FASTA format.
@@ -153,6 +118,7 @@ pub fn run_inverted(cli: &Cli) {
                             &record,
                             &mut head_buffer,
                             &mut seq_buffer,
+                            b"",
                         );
                     } else {
                         // Default: output the raw sequence followed by a newline.
@@ -160,7 +126,6 @@ pub fn run_inverted(cli: &Cli) {
                         writer.write_all(b"\n").unwrap();
                     }
                 }
-                None::<()>
             },
         )
         .unwrap();
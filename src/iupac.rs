@@ -0,0 +1,386 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module handles IUPAC nucleotide codes within regex patterns, including
+// expanding ambiguity codes into character classes, complementing codes and
+// computing the reverse complement of a whole pattern.
+
+use crate::patterns::PatternError;
+use regex_syntax::ast::parse::Parser;
+use regex_syntax::ast::print::Printer;
+use regex_syntax::ast::{
+    AssertionKind, Ast, ClassBracketed, ClassSet, ClassSetItem, ClassSetUnion, Literal,
+    LiteralKind, Span,
+};
+
+// Function: iupac_bases
+// Returns the nucleotides matched by a nucleotide or IUPAC ambiguity code (in either
+// case), or None if the character is not a nucleotide code.
+fn iupac_bases(c: char) -> Option<&'static str> {
+    let bases = match c.to_ascii_uppercase() {
+        'A' => "A",
+        'C' => "C",
+        'G' => "G",
+        'T' => "T",
+        'Y' => "CT",
+        'R' => "AG",
+        'W' => "AT",
+        'S' => "CG",
+        'K' => "GT",
+        'M' => "AC",
+        'B' => "CGT",
+        'D' => "AGT",
+        'H' => "ACT",
+        'V' => "ACG",
+        'N' => "ACGT",
+        _ => return None,
+    };
+    Some(bases)
+}
+
+// Function: expand_iupac_regex
+// Converts a pattern that may mix regex syntax and IUPAC codes into a regex. A pattern of
+// letters alone is a nucleotide sequence: it is converted to upper case, and a letter
+// that is not a nucleotide code is an error. Any other pattern is parsed into a syntax
+// tree, and each IUPAC ambiguity code in a literal position (including inside character
+// classes and next to quantifiers) is expanded into the bases it matches, in the case it
+// is written in, e.g. GGRNGG.{0,5}CAG becomes GG[AG][ACGT]GG.{0,5}CAG. Other literals,
+// including letters that are not nucleotide codes, and regex metacharacters are kept
+// intact. Returns an error if the pattern cannot be parsed.
+pub fn expand_iupac_regex(pattern: &str) -> Result<String, PatternError> {
+    if pattern.chars().all(|c| c.is_alphabetic()) {
+        if let Some((offset, c)) = pattern
+            .chars()
+            .enumerate()
+            .find(|(_, c)| iupac_bases(*c).is_none())
+        {
+            return Err(
+                PatternError::new(format!("Illegal character found in pattern: {}", c))
+                    .with_offset(offset),
+            );
+        }
+        let mut ast = parse_pattern(&pattern.to_ascii_uppercase())?;
+        expand_iupac_ast(&mut ast);
+        return print_pattern(&ast);
+    }
+    let mut ast = parse_pattern(pattern)?;
+    expand_iupac_ast(&mut ast);
+    print_pattern(&ast)
+}
+
+// Function: parse_pattern
+// Parses a regex pattern into a syntax tree, locating any syntax error.
+fn parse_pattern(pattern: &str) -> Result<Ast, PatternError> {
+    Parser::new().parse(pattern).map_err(|e| {
+        PatternError::new(format!(
+            "Failed to parse regex pattern {}: {}",
+            pattern,
+            e.kind()
+        ))
+        .with_offset(char_offset(e.span()))
+    })
+}
+
+// Function: print_pattern
+// Prints a syntax tree back into a regex pattern.
+fn print_pattern(ast: &Ast) -> Result<String, PatternError> {
+    let mut output = String::new();
+    Printer::new()
+        .print(ast, &mut output)
+        .map_err(|e| PatternError::new(format!("Failed to print regex pattern: {}", e)))?;
+    Ok(output)
+}
+
+// Function: char_offset
+// Returns the offset, in characters and counting from 0, of the start of a span.
+fn char_offset(span: &Span) -> usize {
+    span.start.column - 1
+}
+
+// Function: expand_iupac_ast
+// Recursively expands the IUPAC codes of a regex syntax tree in place.
+fn expand_iupac_ast(ast: &mut Ast) {
+    match ast {
+        Ast::Literal(literal) => {
+            if let Some(bases) = expand_literal(literal) {
+                // An ambiguity code becomes a character class in its own right.
+                *ast = Ast::class_bracketed(ClassBracketed {
+                    span: bases.span,
+                    negated: false,
+                    kind: ClassSet::Item(ClassSetItem::Union(bases)),
+                });
+            }
+        }
+        Ast::ClassBracketed(class) => expand_class_set(&mut class.kind),
+        Ast::Repetition(repetition) => expand_iupac_ast(&mut repetition.ast),
+        Ast::Group(group) => expand_iupac_ast(&mut group.ast),
+        Ast::Alternation(alternation) => {
+            for ast in alternation.asts.iter_mut() {
+                expand_iupac_ast(ast);
+            }
+        }
+        Ast::Concat(concat) => {
+            for ast in concat.asts.iter_mut() {
+                expand_iupac_ast(ast);
+            }
+        }
+        // Dots, assertions, empty patterns, flags and Perl/Unicode classes are kept as is.
+        _ => {}
+    }
+}
+
+// Function: expand_literal
+// For a literal ambiguity code, returns the union of the bases it matches, in the case
+// the code is written in. Bases, escaped literals and characters that are not nucleotide
+// codes are left as they are.
+fn expand_literal(literal: &Literal) -> Option<ClassSetUnion> {
+    if literal.kind != LiteralKind::Verbatim {
+        return None;
+    }
+    let bases = iupac_bases(literal.c).filter(|bases| bases.len() > 1)?;
+    Some(ClassSetUnion {
+        span: literal.span,
+        items: bases
+            .chars()
+            .map(|c| {
+                let c = if literal.c.is_ascii_lowercase() {
+                    c.to_ascii_lowercase()
+                } else {
+                    c
+                };
+                ClassSetItem::Literal(Literal {
+                    span: literal.span,
+                    kind: LiteralKind::Verbatim,
+                    c,
+                })
+            })
+            .collect(),
+    })
+}
+
+// Function: expand_class_set
+// Expands the IUPAC codes of a bracketed character class in place.
+fn expand_class_set(set: &mut ClassSet) {
+    match set {
+        ClassSet::Item(item) => expand_class_item(item),
+        ClassSet::BinaryOp(op) => {
+            expand_class_set(&mut op.lhs);
+            expand_class_set(&mut op.rhs);
+        }
+    }
+}
+
+// Function: expand_class_item
+// Expands the IUPAC codes of a single item of a bracketed character class in place.
+// Ranges are taken as written.
+fn expand_class_item(item: &mut ClassSetItem) {
+    match item {
+        ClassSetItem::Literal(literal) => {
+            if let Some(bases) = expand_literal(literal) {
+                *item = ClassSetItem::Union(bases);
+            }
+        }
+        ClassSetItem::Bracketed(class) => expand_class_set(&mut class.kind),
+        ClassSetItem::Union(union) => {
+            for item in union.items.iter_mut() {
+                expand_class_item(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Function: complement_iupac
+// Returns the complement of a nucleotide or IUPAC ambiguity code, preserving case.
+// Characters that are not nucleotide codes are returned unchanged.
+pub fn complement_iupac(c: char) -> char {
+    let complement = match c.to_ascii_uppercase() {
+        'A' => 'T',
+        'T' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        'S' => 'S',
+        'W' => 'W',
+        'N' => 'N',
+        _ => return c,
+    };
+    if c.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
+    }
+}
+
+// Function: reverse_complement_sequence
+// Computes the reverse complement of a plain nucleotide (or IUPAC) sequence.
+pub fn reverse_complement_sequence(sequence: &str) -> String {
+    sequence.chars().rev().map(complement_iupac).collect()
+}
+
+// Function: reverse_complement_regex
+// Computes the reverse complement of a regex pattern. Concatenations are reversed,
+// literals and character class members are complemented (IUPAC codes included),
+// and start/end anchors are swapped. Returns an error if the pattern cannot be parsed.
+pub fn reverse_complement_regex(pattern: &str) -> Result<String, PatternError> {
+    let mut ast = parse_pattern(pattern)?;
+    reverse_complement_ast(&mut ast)?;
+    print_pattern(&ast)
+}
+
+// Function: reverse_complement_ast
+// Recursively reverses and complements a regex syntax tree in place.
+fn reverse_complement_ast(ast: &mut Ast) -> Result<(), PatternError> {
+    match ast {
+        Ast::Literal(literal) => complement_literal(literal),
+        Ast::Assertion(assertion) => {
+            assertion.kind = match assertion.kind.clone() {
+                AssertionKind::StartLine => AssertionKind::EndLine,
+                AssertionKind::EndLine => AssertionKind::StartLine,
+                AssertionKind::StartText => AssertionKind::EndText,
+                AssertionKind::EndText => AssertionKind::StartText,
+                AssertionKind::WordBoundaryStart => AssertionKind::WordBoundaryEnd,
+                AssertionKind::WordBoundaryEnd => AssertionKind::WordBoundaryStart,
+                AssertionKind::WordBoundaryStartAngle => AssertionKind::WordBoundaryEndAngle,
+                AssertionKind::WordBoundaryEndAngle => AssertionKind::WordBoundaryStartAngle,
+                AssertionKind::WordBoundaryStartHalf => AssertionKind::WordBoundaryEndHalf,
+                AssertionKind::WordBoundaryEndHalf => AssertionKind::WordBoundaryStartHalf,
+                kind => kind,
+            };
+        }
+        Ast::ClassBracketed(class) => complement_class_set(&mut class.kind),
+        Ast::Repetition(repetition) => reverse_complement_ast(&mut repetition.ast)?,
+        Ast::Group(group) => reverse_complement_ast(&mut group.ast)?,
+        Ast::Alternation(alternation) => {
+            for ast in alternation.asts.iter_mut() {
+                reverse_complement_ast(ast)?;
+            }
+        }
+        Ast::Concat(concat) => {
+            // Leading inline flags, such as (?i), must stay at the front of the pattern.
+            let leading_flags = concat
+                .asts
+                .iter()
+                .take_while(|ast| matches!(ast, Ast::Flags(_)))
+                .count();
+            let body = &mut concat.asts[leading_flags..];
+            if let Some(flags) = body.iter().find(|ast| matches!(ast, Ast::Flags(_))) {
+                return Err(PatternError::new(
+                    "Inline flags are only supported at the start of a reverse complemented pattern",
+                )
+                .with_offset(char_offset(flags.span())));
+            }
+            body.reverse();
+            for ast in body.iter_mut() {
+                reverse_complement_ast(ast)?;
+            }
+        }
+        // Dots, empty patterns, flags and Perl/Unicode classes are unaffected by complementing.
+        _ => {}
+    }
+    Ok(())
+}
+
+// Function: complement_literal
+// Complements a single literal character in place.
+fn complement_literal(literal: &mut Literal) {
+    let complement = complement_iupac(literal.c);
+    if complement != literal.c {
+        literal.c = complement;
+        literal.kind = LiteralKind::Verbatim;
+    }
+}
+
+// Function: complement_class_set
+// Complements every member of a bracketed character class in place. Ranges over
+// nucleotide letters are expanded into their individual (complemented) members.
+fn complement_class_set(set: &mut ClassSet) {
+    match set {
+        ClassSet::Item(item) => complement_class_item(item),
+        ClassSet::BinaryOp(op) => {
+            complement_class_set(&mut op.lhs);
+            complement_class_set(&mut op.rhs);
+        }
+    }
+}
+
+// Function: complement_class_item
+// Complements a single item of a bracketed character class in place.
+fn complement_class_item(item: &mut ClassSetItem) {
+    match item {
+        ClassSetItem::Literal(literal) => complement_literal(literal),
+        ClassSetItem::Range(range) => {
+            let (start, end) = (range.start.c, range.end.c);
+            if !end.is_ascii() || (start..=end).all(|c| complement_iupac(c) == c) {
+                return;
+            }
+            let mut union = ClassSetUnion {
+                span: range.span,
+                items: (start..=end)
+                    .map(|c| {
+                        ClassSetItem::Literal(Literal {
+                            span: range.span,
+                            kind: LiteralKind::Verbatim,
+                            c: complement_iupac(c),
+                        })
+                    })
+                    .collect(),
+            };
+            sort_union(&mut union);
+            *item = ClassSetItem::Union(union);
+        }
+        ClassSetItem::Bracketed(class) => complement_class_set(&mut class.kind),
+        ClassSetItem::Union(union) => {
+            union.items.iter_mut().for_each(complement_class_item);
+            sort_union(union);
+        }
+        // ASCII, Perl and Unicode classes are closed under complementing.
+        _ => {}
+    }
+}
+
+// Function: sort_union
+// Sorts a class union made up only of literals (so that [AG] becomes [CT] rather
+// than [TC]); unions containing other items keep their order.
+fn sort_union(union: &mut ClassSetUnion) {
+    let mut chars = Vec::with_capacity(union.items.len());
+    for item in &union.items {
+        match item {
+            ClassSetItem::Literal(literal) => chars.push(literal.c),
+            _ => return,
+        }
+    }
+    chars.sort_unstable();
+    for (item, c) in union.items.iter_mut().zip(chars) {
+        if let ClassSetItem::Literal(literal) = item {
+            literal.c = c;
+            literal.kind = LiteralKind::Verbatim;
+        }
+    }
+}
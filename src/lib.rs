@@ -20,12 +20,22 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod amplicon;
 pub mod arg;
+pub mod header;
 pub mod initialise;
+pub mod input;
 pub mod inverted;
+pub mod iupac;
+pub mod lint;
+pub mod matcher;
 pub mod output;
+pub mod paired;
+pub mod patterns;
 pub mod quality;
+pub mod schema;
 pub mod summarise;
+pub mod translate;
 pub mod tune;
 
 #[cfg(test)]
@@ -0,0 +1,359 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module handles the lint command for grepq. It checks each regex set of a
+// patterns file for patterns that match every sequence, that duplicate or are subsumed
+// by another pattern once IUPAC codes are converted, that are the reverse complement of
+// another pattern, that have variants they cannot match, or that compile to a very
+// large automaton. Patterns are compared as the sets of bases accepted at each position,
+// so only patterns of fixed length are checked for subsumption.
+
+use crate::arg::Cli;
+use crate::initialise::{convert_iupac_to_regex, load_pattern_sets};
+use crate::iupac::reverse_complement_regex;
+use crate::matcher::fixed_positions;
+use crate::patterns::{PatternFormat, PatternSet, Strand};
+use regex::bytes::RegexBuilder;
+use serde_json::json;
+
+// The size, in bytes, of the compiled form of a regex above which it is reported. The
+// lazy DFA of larger regexes tends to exceed its cache, so that matching slows down.
+pub const COMPILED_SIZE_LIMIT: usize = 256 * 1024;
+
+// Enum: IssueKind
+// The kinds of issue found by the lint command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    // The pattern matches the empty string, and hence every sequence.
+    Empty,
+    // The pattern is the same as another once IUPAC codes are converted.
+    Duplicate,
+    // Every sequence the pattern matches is matched by another pattern.
+    Subsumed,
+    // The pattern is the reverse complement of another pattern.
+    ReverseComplement,
+    // A variant of the pattern is not matched by it, so is never reported.
+    VariantMismatch,
+    // The pattern compiles to more than COMPILED_SIZE_LIMIT bytes.
+    DfaCost,
+}
+
+impl IssueKind {
+    // Returns the name of the kind of issue, as written in the output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueKind::Empty => "empty",
+            IssueKind::Duplicate => "duplicate",
+            IssueKind::Subsumed => "subsumed",
+            IssueKind::ReverseComplement => "reverseComplement",
+            IssueKind::VariantMismatch => "variantMismatch",
+            IssueKind::DfaCost => "dfaCost",
+        }
+    }
+}
+
+// Struct: Issue
+// An issue found in a regex set. The patterns concerned are given by their index into
+// the patterns of the set, the pattern at fault first and any it was compared with next.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub patterns: Vec<usize>,
+    pub message: String,
+}
+
+// Function: lint
+// Checks the patterns of a regex set, returning the issues found. Generated reverse
+// complements are not checked themselves, but a pattern with a reverse complement is
+// only compared with patterns that have one too.
+pub fn lint(pattern_set: &PatternSet) -> Vec<Issue> {
+    let patterns = pattern_set.patterns();
+    let case_insensitive = pattern_set.case_insensitive();
+    let mut issues = Vec::new();
+
+    // The patterns as written, with whether each has a generated reverse complement.
+    let forward: Vec<(usize, bool)> = patterns
+        .iter()
+        .enumerate()
+        .filter(|(_, pattern)| pattern.strand == Strand::Forward)
+        .map(|(index, pattern)| {
+            let reversed = patterns
+                .get(index + 1)
+                .is_some_and(|next| next.strand == Strand::Reverse && next.name == pattern.name);
+            (index, reversed)
+        })
+        .collect();
+    // The bases accepted at each position of the fixed-length patterns.
+    let positions: Vec<Option<Vec<[bool; 256]>>> = patterns
+        .iter()
+        .map(|pattern| fixed_positions(&pattern.regex, case_insensitive).ok())
+        .collect();
+    // Returns true if two regexes match the same sequences, as far as can be told.
+    let same = |a: &str, a_positions: &Option<Vec<[bool; 256]>>, b: usize| match (
+        a_positions,
+        &positions[b],
+    ) {
+        (Some(a_positions), Some(b_positions)) => a_positions == b_positions,
+        _ => a == patterns[b].regex,
+    };
+
+    let mut empty = vec![false; patterns.len()];
+    for &(index, _) in &forward {
+        let pattern = &patterns[index];
+        let matches_empty = regex_syntax::Parser::new()
+            .parse(&pattern.regex)
+            .is_ok_and(|hir| hir.properties().minimum_len() == Some(0));
+        if matches_empty {
+            empty[index] = true;
+            issues.push(Issue {
+                kind: IssueKind::Empty,
+                patterns: vec![index],
+                message: if pattern.original.trim().is_empty() {
+                    "The pattern is empty, so it matches every sequence".to_string()
+                } else {
+                    "The pattern can match an empty string, so it matches every sequence"
+                        .to_string()
+                },
+            });
+        }
+    }
+
+    // Patterns are only redundant if they are searched for in the same way.
+    let comparable = |(a, a_reversed): (usize, bool), (b, b_reversed): (usize, bool)| {
+        let (a, b) = (&patterns[a], &patterns[b]);
+        a_reversed == b_reversed
+            && a.distance == b.distance
+            && a.window == b.window
+            && a.predicates == b.predicates
+            && a.match_quality == b.match_quality
+            && a.protein == b.protein
+    };
+    let mut duplicate = vec![false; patterns.len()];
+    for (j, &(b, _)) in forward.iter().enumerate() {
+        if empty[b] {
+            continue;
+        }
+        if let Some(&(a, _)) = forward[..j].iter().find(|&&(a, a_reversed)| {
+            !empty[a]
+                && comparable((a, a_reversed), forward[j])
+                && same(&patterns[a].regex, &positions[a], b)
+        }) {
+            duplicate[b] = true;
+            issues.push(Issue {
+                kind: IssueKind::Duplicate,
+                patterns: vec![b, a],
+                message: format!(
+                    "The pattern is the same as {} once IUPAC codes are converted",
+                    describe(pattern_set, a)
+                ),
+            });
+        }
+    }
+
+    for &(a, a_reversed) in &forward {
+        let Some(a_positions) = positions[a].as_ref().filter(|_| !empty[a] && !duplicate[a]) else {
+            continue;
+        };
+        if let Some(&(b, _)) = forward.iter().find(|&&(b, b_reversed)| {
+            b != a
+                && !empty[b]
+                && !duplicate[b]
+                && comparable((a, a_reversed), (b, b_reversed))
+                && positions[b]
+                    .as_ref()
+                    .is_some_and(|b_positions| subsumes(b_positions, a_positions))
+        }) {
+            issues.push(Issue {
+                kind: IssueKind::Subsumed,
+                patterns: vec![a, b],
+                message: format!(
+                    "Every sequence the pattern matches is also matched by {}",
+                    describe(pattern_set, b)
+                ),
+            });
+        }
+    }
+
+    for (j, &(b, b_reversed)) in forward.iter().enumerate() {
+        // A duplicate is reported as such, not again as a reverse complement.
+        if empty[b] || duplicate[b] {
+            continue;
+        }
+        for &(a, a_reversed) in &forward[..j] {
+            // Protein patterns are matched against both strands, and have no complement.
+            if empty[a] || duplicate[a] || patterns[a].protein || patterns[b].protein {
+                continue;
+            }
+            let Ok(reversed) = reverse_complement_regex(&patterns[a].original)
+                .and_then(|reversed| convert_iupac_to_regex(&reversed))
+            else {
+                continue;
+            };
+            let reversed_positions = fixed_positions(&reversed, case_insensitive).ok();
+            if same(&reversed, &reversed_positions, b) {
+                let searched = if a_reversed || b_reversed {
+                    ", which reverseComplement already searches for"
+                } else {
+                    ""
+                };
+                issues.push(Issue {
+                    kind: IssueKind::ReverseComplement,
+                    patterns: vec![b, a],
+                    message: format!(
+                        "The pattern is the reverse complement of {}{}",
+                        describe(pattern_set, a),
+                        searched
+                    ),
+                });
+            }
+        }
+    }
+
+    for &(index, _) in &forward {
+        let pattern = &patterns[index];
+        if pattern.variants.is_empty() {
+            continue;
+        }
+        // A variant is reported when a hit is the whole of its sequence.
+        let Ok(whole) = RegexBuilder::new(&format!("^(?:{})$", pattern.regex))
+            .case_insensitive(case_insensitive)
+            .build()
+        else {
+            continue;
+        };
+        for variant in &pattern.variants {
+            if !whole.is_match(variant.sequence.as_bytes()) {
+                issues.push(Issue {
+                    kind: IssueKind::VariantMismatch,
+                    patterns: vec![index],
+                    message: format!(
+                        "Variant \"{}\" ({}) is not matched by the pattern, so it is never reported",
+                        variant.name, variant.sequence
+                    ),
+                });
+            }
+        }
+    }
+
+    for &(index, _) in &forward {
+        let too_large = RegexBuilder::new(&patterns[index].regex)
+            .case_insensitive(case_insensitive)
+            .size_limit(COMPILED_SIZE_LIMIT)
+            .build()
+            .is_err();
+        if too_large {
+            issues.push(Issue {
+                kind: IssueKind::DfaCost,
+                patterns: vec![index],
+                message: format!(
+                    "The pattern compiles to more than {} KiB, which may slow matching; consider narrowing its repetitions",
+                    COMPILED_SIZE_LIMIT / 1024
+                ),
+            });
+        }
+    }
+
+    issues
+}
+
+// Function: subsumes
+// Returns true if, at some offset within the longer pattern b, every base accepted by b
+// is accepted by a, so that any sequence matching b also matches a.
+fn subsumes(a: &[[bool; 256]], b: &[[bool; 256]]) -> bool {
+    a.len() <= b.len()
+        && b.windows(a.len()).any(|window| {
+            window.iter().zip(a).all(|(b_position, a_position)| {
+                b_position
+                    .iter()
+                    .zip(a_position)
+                    .all(|(&b_base, &a_base)| !b_base || a_base)
+            })
+        })
+}
+
+// Function: describe
+// Describes a pattern by its regexName, or by its line for plain-text patterns files.
+fn describe(pattern_set: &PatternSet, index: usize) -> String {
+    match pattern_set.format() {
+        PatternFormat::Text => format!("line {}", index + 1),
+        _ => format!("regex \"{}\"", pattern_set.patterns()[index].name),
+    }
+}
+
+// Function: run_lint
+// Checks each regex set of the patterns file, and prints the issues found, one per line
+// or as JSON. Exits with status 1 if any issue is found.
+pub fn run_lint(cli: &Cli, json_output: bool) {
+    let pattern_sets = load_pattern_sets(cli);
+    let multiple_sets = pattern_sets.len() > 1;
+
+    let mut issue_count = 0;
+    let mut entries = Vec::new();
+    for pattern_set in &pattern_sets {
+        let patterns = pattern_set.patterns();
+        for issue in lint(pattern_set) {
+            issue_count += 1;
+            if json_output {
+                let mut entry = json!({
+                    "regexSetName": pattern_set.name(),
+                    "kind": issue.kind.as_str(),
+                    "regexNames": issue
+                        .patterns
+                        .iter()
+                        .map(|&index| patterns[index].name.as_str())
+                        .collect::<Vec<_>>(),
+                    "message": issue.message,
+                });
+                if pattern_set.format() == PatternFormat::Text {
+                    entry["lines"] = json!(issue
+                        .patterns
+                        .iter()
+                        .map(|&index| index + 1)
+                        .collect::<Vec<_>>());
+                }
+                entries.push(entry);
+            } else {
+                let set = match pattern_set.name() {
+                    Some(name) if multiple_sets => format!("{}: ", name),
+                    _ => String::new(),
+                };
+                println!(
+                    "{}{}: {}: {}",
+                    set,
+                    describe(pattern_set, issue.patterns[0]),
+                    issue.kind.as_str(),
+                    issue.message
+                );
+            }
+        }
+    }
+
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({ "issues": entries })).unwrap()
+        );
+    }
+    if issue_count > 0 {
+        std::process::exit(1);
+    }
+}
@@ -24,28 +24,53 @@ use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-use regex::bytes::Regex;
 use seq_io::fastq::Record;
-use seq_io::parallel::parallel_fastq;
-use std::io::Write;
-use clap::Parser;
-use initialise::{create_reader, create_writer, parse_patterns_file};
-use serde_json::json;
-use regex::bytes::Regex as BytesRegex; // Alias to avoid confusion
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use clap::{CommandFactory, Parser};
+use initialise::{create_file_writer, create_reader, create_writer, load_pattern_sets};
+use patterns::Strand;
 use arg::{Cli, Commands};
 
+mod amplicon;
 mod arg;
+mod header;
 mod initialise;
+mod input;
 mod inverted;
+mod iupac;
+mod lint;
+mod matcher;
 mod output;
+mod paired;
+mod patterns;
 mod quality;
+mod schema;
 mod summarise;
+mod translate;
 mod tune;
 
 // Main entry point for the grepq tool.
 // This file handles CLI argument parsing, SQL database connection (if enabled),
 // and dispatches commands to the appropriate module functions.
 
+// Function: missing_argument
+// Exits with clap's error for a required argument that was not provided, for arguments
+// that only some commands require.
+fn missing_argument(argument: &str) -> ! {
+    Cli::command()
+        .error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            format!(
+                "the following required arguments were not provided:\n  {}",
+                argument
+            ),
+        )
+        .exit()
+}
+
 fn main() {
     // Parse command line arguments using clap.
     let cli = Cli::parse();
@@ -54,25 +79,56 @@ fn main() {
     if cli.markdown_help {
         clap_markdown::print_help_markdown::<Cli>();
     }
-    // Set up SQL database connection if writing SQL output and no command is given.
-    let db_conn = if cli.write_sql && cli.command.is_none() {
-        // If pattern file is JSON, check for qualityEncoding.
-        let conn = if cli.patterns.ends_with(".json") {
-            let pattern_data: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&cli.patterns).unwrap()).unwrap();
-            if pattern_data["regexSet"]["qualityEncoding"].is_null() {
-                output::create_sqlite_db().unwrap()
-            } else {
-                output::create_sqlite_db_with_quality().unwrap()
+    // The schema command needs no files; the lint and migrate-patterns commands read the
+    // patterns file alone; every other command needs a FASTQ file too.
+    if let Some(Commands::Schema(schema)) = &cli.command {
+        schema::run_schema(schema.schema_version);
+        return;
+    }
+    if cli.patterns.is_none() {
+        missing_argument("<PATTERNS>");
+    }
+    match &cli.command {
+        Some(Commands::Lint(lint)) => {
+            lint::run_lint(&cli, lint.json);
+            return;
+        }
+        Some(Commands::MigratePatterns(migrate)) => {
+            schema::run_migrate_patterns(&cli, migrate.in_place);
+            return;
+        }
+        _ => {}
+    }
+    if cli.files.is_empty() {
+        missing_argument("<FILE>");
+    }
+    // Paired-end reads are filtered by the default and inverted commands only.
+    if cli.mate_patterns.is_some() && cli.mate_file.is_none() && !cli.interleaved {
+        eprintln!(
+            "Error: --R2-patterns is only used with paired-end reads (--R2 or --interleaved)"
+        );
+        std::process::exit(1);
+    }
+    if cli.mate_file.is_some() || cli.interleaved {
+        if cli.files.len() > 1 {
+            eprintln!(
+                "Error: Paired-end reads (--R2 or --interleaved) are read from a single FASTQ file"
+            );
+            std::process::exit(1);
+        }
+        match &cli.command {
+            None => paired::run_paired(&cli, false),
+            Some(Commands::Inverted) => paired::run_paired(&cli, true),
+            Some(_) => {
+                eprintln!(
+                    "Error: Paired-end reads (--R2 or --interleaved) are only supported by the \
+                     default and inverted commands"
+                );
+                std::process::exit(1);
             }
-        } else {
-            output::create_sqlite_db().unwrap()
-        };
-        // Write regex patterns and file info to the database.
-        output::write_regex_to_db(&conn, &cli.patterns, &cli.file).unwrap();
-        Some(conn)
-    } else {
-        None
-    };
+        }
+        return;
+    }
 
     // Dispatch commands: tune, summarise, or inverted.
     match &cli.command {
@@ -91,298 +147,236 @@ fn main() {
             inverted::run_inverted(&cli);
             return;
         }
-        None => {}
+        Some(Commands::Lint(_) | Commands::MigratePatterns(_) | Commands::Schema(_)) | None => {}
     }
 
-    // Default processing: parse patterns file and prepare for record filtering.
-    let (
-        regex_set,
-        header_regex,
-        minimum_sequence_length,
-        minimum_quality,
-        quality_encoding,
-        regex_names,
-        _,
-    ) = parse_patterns_file(&cli.patterns)
-        .map_err(std::io::Error::other)
-        .unwrap();
-
-    // Check if the pattern file is a text file.
-    let is_text_file = cli.patterns.ends_with(".txt");
+    // Default processing: parse the patterns file, which may give several regex sets, and
+    // prepare for record filtering.
+    let pattern_sets = load_pattern_sets(&cli);
+    // With several regex sets, the records matching each set are kept apart.
+    let multiple_sets = pattern_sets.len() > 1;
 
-    // Store quality encoding as a string slice for further processing.
-    let quality_encoding = quality_encoding.as_deref();
-
-    // Ensure the number of regex patterns matches the number of regex names.
-    assert_eq!(
-        regex_set.patterns().len(),
-        regex_names.len(),
-        "The number of regex patterns and regex names must match."
-    );
+    // Create the input reader, which reads the records of every input file in turn, and
+    // the record writer.
+    let reader = create_reader(&cli);
+    let paths = reader.paths().to_vec();
+    let mut record_writer = output::RecordWriter::new(&cli);
 
-    // Compile the optional header regex filter.
-    let header_regex = header_regex.map(|re: String| Regex::new(&re).unwrap());
+    // Set up SQL database connection if writing SQL output.
+    let db_conn = if cli.write_sql {
+        // Store the average quality when the pattern file gives a qualityEncoding.
+        let conn = if pattern_sets
+            .iter()
+            .any(|pattern_set| pattern_set.predicates().quality_encoding.is_some())
+        {
+            output::create_sqlite_db_with_quality().unwrap()
+        } else {
+            output::create_sqlite_db().unwrap()
+        };
+        // Write regex patterns and file info to the database, for each file queried. Every
+        // set has the whole patterns file as its source.
+        for path in &paths {
+            output::write_regex_to_db(&conn, &pattern_sets[0], path).unwrap();
+        }
+        // Record the regex set matched by each record, and the file it was read from.
+        if multiple_sets {
+            output::add_regex_set_name_column(&conn).unwrap();
+        }
+        if paths.len() > 1 {
+            output::add_queried_file_column(&conn).unwrap();
+        }
+        Some(conn)
+    } else {
+        None
+    };
 
-    // Create input reader and output writer.
-    let reader = create_reader(&cli);
-    let mut writer = create_writer(&cli);
+    // Annotate output headers with the matched strand when reverse complements are searched.
+    let annotate_strand: Vec<bool> = pattern_sets
+        .iter()
+        .map(|pattern_set| pattern_set.has_reverse_complements())
+        .collect();
 
     // Extract output mode flags from CLI options.
-    let with_id = cli.with_id;
-    let with_full_record = cli.with_full_record;
-    let with_fasta = cli.with_fasta;
     let count = cli.count;
     let bucket = cli.bucket;
 
-    // Determine which filters to apply.
-    let check_seq_len = minimum_sequence_length.is_some();
-    let check_qual = minimum_quality.is_some();
-    let check_header = header_regex.is_some();
-
-    // Initialize reusable buffers.
-    let mut seq_buffer = Vec::new();
-    let mut qual_buffer = Vec::new();
-    let mut head_buffer = Vec::new();
-
     if count {
-        // Counting mode: Count records that match filter criteria.
-        let mut match_count = 0;
-        parallel_fastq(
+        // Counting mode: Count records that match filter criteria, for each input file and
+        // regex set.
+        let mut match_counts = vec![vec![0; pattern_sets.len()]; paths.len()];
+        input::parallel_fastq_files(
             reader,
             num_cpus::get() as u32,
             num_cpus::get(),
-            |record, found| {
-                // Worker thread: Apply filter checks for sequence length, quality, header, and regex.
-                *found = false;
-                let seq_len_check = !check_seq_len
-                    || record.seq().len() >= minimum_sequence_length.unwrap() as usize;
-                let qual_check = !check_qual
-                    || average_quality(
-                        record.qual(),
-                        quality_encoding.unwrap_or("Phred+33"),
-                    ) >= minimum_quality.unwrap();
-                let header_check =
-                    !check_header || header_regex.as_ref().unwrap().is_match(record.head());
-                let regex_check = regex_set.is_match(record.seq());
-
-                if seq_len_check && qual_check && header_check && regex_check {
-                    *found = true;
-                }
+            |record, found: &mut Vec<bool>| {
+                // Worker thread: Apply the predicates (sequence length, quality, header) and
+                // regex of each set.
+                found.clear();
+                found.extend(pattern_sets.iter().map(|pattern_set| {
+                    pattern_set.is_match(record.head(), record.seq(), record.qual())
+                }));
             },
-            |_, found| {
-                // Main thread: Increment count when a record passes filters.
-                if *found {
-                    match_count += 1;
+            |_, found, file| {
+                // Main thread: Increment the count of each set the record passes, for the
+                // file it was read from.
+                for (match_count, &found) in match_counts[file].iter_mut().zip(found.iter()) {
+                    if found {
+                        *match_count += 1;
+                    }
                 }
-                None::<()>
             },
         )
         .unwrap();
-        // Write the final count to output.
-        writeln!(writer, "{}", match_count).unwrap();
+        // Write the final counts to output, naming each set when there are several.
+        let mut writer = create_writer(&cli);
+        let set_names: Vec<&str> = pattern_sets
+            .iter()
+            .map(|pattern_set| pattern_set.name().unwrap_or("Unknown"))
+            .collect();
+        output::write_counts(&mut writer, &paths, &set_names, &match_counts).unwrap();
     } else {
-        // Mode for writing records to files or stdout.
-        let mut bucket_writers = if bucket {
-            // Create separate bucket writers for each regex pattern.
-            Some(
-                regex_names
+        // Mode for writing records to files or stdout: to the output of each set or, in
+        // bucket mode, to the bucket file of each regexName or amplicon of each set.
+        let files = output::output_files(&cli, &pattern_sets);
+        let mut writers: Vec<Box<dyn Write>> = files
+            .sets
+            .iter()
+            .map(|file| match file {
+                Some(file_path) => create_file_writer(&cli, file_path),
+                None => create_writer(&cli),
+            })
+            .collect();
+        let mut bucket_writers: Vec<HashMap<String, BufWriter<File>>> = files
+            .buckets
+            .iter()
+            .map(|buckets| {
+                buckets
                     .iter()
-                    .map(|name| {
-                        let formatted_name = name.replace(' ', "-").replace('\'', "");
-                        let suffix = if with_fasta {
-                            "fasta"
-                        } else if with_full_record {
-                            "fastq"
-                        } else {
-                            ""
-                        };
-                        let file_name = if suffix.is_empty() {
-                            formatted_name
-                        } else {
-                            format!("{}.{}", formatted_name, suffix)
-                        };
-                        let file = std::fs::File::create(file_name).unwrap();
-                        (name.clone(), std::io::BufWriter::new(file))
+                    .map(|(bucket_name, file_path)| {
+                        let file = File::create(file_path).unwrap();
+                        (bucket_name.clone(), BufWriter::new(file))
                     })
-                    .collect::<std::collections::HashMap<String, std::io::BufWriter<std::fs::File>>>(),
-            )
-        } else {
-            None
-        };
+                    .collect()
+            })
+            .collect();
 
-        parallel_fastq(
+        input::parallel_fastq_files(
             reader,
             num_cpus::get() as u32,
             num_cpus::get(),
-            |record, found| {
+            |record, found: &mut Vec<bool>| {
                 // Worker thread: Check the same filter criteria as in count mode.
-                *found = false;
-                let seq_len_check = !check_seq_len
-                    || record.seq().len() >= minimum_sequence_length.unwrap() as usize;
-                let qual_check = !check_qual
-                    || quality::average_quality(
-                        record.qual(),
-                        quality_encoding.unwrap_or("Phred+33"),
-                    ) >= minimum_quality.unwrap();
-                let header_check =
-                    !check_header || header_regex.as_ref().unwrap().is_match(record.head());
-                let regex_check = regex_set.is_match(record.seq());
-
-                if seq_len_check && qual_check && header_check && regex_check {
-                    *found = true;
-                }
+                found.clear();
+                found.extend(pattern_sets.iter().map(|pattern_set| {
+                    pattern_set.is_match(record.head(), record.seq(), record.qual())
+                }));
             },
-            |record, found| {
-                // Main thread: Depending on flags, write the record in various formats.
-                if *found {
+            |record, found, file| {
+                // Main thread: Depending on flags, write the record in various formats, once
+                // for each set it passes.
+                for (index, pattern_set) in pattern_sets.iter().enumerate() {
+                    if !found[index] {
+                        continue;
+                    }
+                    let patterns = pattern_set.patterns();
+                    // The patterns found in the record, each having passed its own predicates.
+                    let matches = if cli.write_sql || bucket || annotate_strand[index] {
+                        pattern_set.matches(record.head(), record.seq(), record.qual())
+                    } else {
+                        Vec::new()
+                    };
                     if cli.write_sql && cli.command.is_none() {
                         // Process SQL write: extract match details and record quality statistics.
-                        let mut matches_info = vec![];
-                        for pattern in regex_set.patterns() {
-                            let regex = BytesRegex::new(pattern).unwrap();
-                            for matched in regex.find_iter(record.seq()) {
-                                matches_info.push(json!({
-                                    "pattern": pattern,
-                                    "match": String::from_utf8_lossy(&record.seq()[matched.start()..matched.end()]).to_string(),
-                                    "start": matched.start(),
-                                    "end": matched.end()
-                                }));
-                            }
-                        }
                         if let Some(ref db) = db_conn {
-                            let avg_quality = quality_encoding
-                                .map(|encoding| quality::average_quality(record.qual(), encoding))
-                                .unwrap_or(0.0);
-                            let (tnf, ntn) = quality::tetranucleotide_frequencies(record.seq(), cli.num_tetranucleotides);
-                            let (ctnf, nctn) = quality::canonical_tetranucleotide_frequencies(record.seq(), cli.num_tetranucleotides);
-                            let gc = quality::gc_content(record.seq());
-                            let gc_int = gc.round() as i64;
-                            let matches_json = serde_json::to_string(&matches_info).unwrap_or_else(|_| "[]".to_string());
-
-                            // Insert record details into the SQLite database.
-                            let insert_stmt = if quality_encoding.is_some() && !is_text_file {
-                                "INSERT INTO fastq_data (header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF, average_quality, variants) 
-                                 VALUES (?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10, ROUND(?11, 2), ?12)"
-                            } else {
-                                "INSERT INTO fastq_data (header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF, variants) 
-                                 VALUES (?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10, ?11)"
-                            };
-                            
-                            // Execute the appropriate SQL statement with the correct number of parameters
-                            if quality_encoding.is_some() && !is_text_file {
-                                db.execute(
-                                    insert_stmt,
-                                    rusqlite::params![
-                                        String::from_utf8_lossy(record.head()),
-                                        String::from_utf8_lossy(record.seq()),
-                                        String::from_utf8_lossy(record.qual()),
-                                        record.seq().len() as i64,
-                                        gc,
-                                        gc_int,
-                                        ntn as i64,
-                                        nctn as i64,
-                                        tnf,
-                                        ctnf,
-                                        avg_quality,
-                                        matches_json,
-                                    ],
-                                ).unwrap();
-                            } else {
-                                db.execute(
-                                    insert_stmt,
-                                    rusqlite::params![
-                                        String::from_utf8_lossy(record.head()),
-                                        String::from_utf8_lossy(record.seq()),
-                                        String::from_utf8_lossy(record.qual()),
-                                        record.seq().len() as i64,
-                                        gc,
-                                        gc_int,
-                                        ntn as i64,
-                                        nctn as i64,
-                                        tnf,
-                                        ctnf,
-                                        matches_json,
-                                    ],
-                                ).unwrap();
-                            }
+                            let matches_info =
+                                output::match_details(pattern_set, &matches, &record);
+                            output::write_record_to_db(
+                                db,
+                                &record,
+                                &matches_info,
+                                pattern_set,
+                                cli.num_tetranucleotides,
+                                output::RecordColumns {
+                                    regex_set_name: pattern_set.name().filter(|_| multiple_sets),
+                                    queried_file: Some(paths[file].as_str())
+                                        .filter(|_| paths.len() > 1),
+                                    ..Default::default()
+                                },
+                            )
+                            .unwrap();
                         }
                     }
-                    
-                    if let Some(ref mut bucket_writers) = bucket_writers {
-                        // Write to separate bucket files based on matching regex patterns.
-                        for (i, pattern) in regex_set.patterns().iter().enumerate() {
-                            let regex = Regex::new(pattern).unwrap();
-                            if regex.is_match(record.seq()) {
-                                let writer = bucket_writers.get_mut(&regex_names[i]).unwrap();
-                                if with_id {
-                                    output::write_record_with_id(
-                                        writer,
-                                        &record,
-                                        &mut head_buffer,
-                                        &mut seq_buffer,
-                                    );
-                                } else if with_full_record {
-                                    output::write_full_record(
-                                        writer,
-                                        &record,
-                                        &mut head_buffer,
-                                        &mut seq_buffer,
-                                        &mut qual_buffer,
-                                    );
-                                } else if with_fasta {
-                                    output::write_record_with_fasta(
-                                        writer,
-                                        &record,
-                                        &mut head_buffer,
-                                        &mut seq_buffer,
-                                    );
-                                } else {
-                                    writer.write_all(record.seq()).unwrap();
-                                    writer.write_all(b"\n").unwrap();
+
+                    // The amplicons found in the record, for a set giving amplicons.
+                    let amplicon_hits = if pattern_set.amplicons().is_empty() {
+                        Vec::new()
+                    } else {
+                        pattern_set.find_amplicons(record.seq(), record.qual())
+                    };
+
+                    if bucket && !amplicon_hits.is_empty() {
+                        // Write to the bucket file of each amplicon found, annotating the
+                        // header with its insert length and strand.
+                        for hit in &amplicon_hits {
+                            let name = &pattern_set.amplicons()[hit.0].name;
+                            let annotation =
+                                output::amplicon_annotation(pattern_set, std::slice::from_ref(hit));
+                            let writer = bucket_writers[index].get_mut(name).unwrap();
+                            record_writer.write(writer, &record, &annotation);
+                        }
+                    } else if bucket {
+                        // Write to separate bucket files based on matching regex patterns. A
+                        // regex and its reverse complement share a name, and hence a bucket.
+                        let mut matched_names: Vec<(&String, bool, bool)> = Vec::new();
+                        for &i in &matches {
+                            let forward = patterns[i].strand == Strand::Forward;
+                            match matched_names
+                                .iter_mut()
+                                .find(|(name, _, _)| *name == &patterns[i].name)
+                            {
+                                Some(entry) => {
+                                    entry.1 |= forward;
+                                    entry.2 |= !forward;
                                 }
+                                None => matched_names.push((&patterns[i].name, forward, !forward)),
                             }
                         }
-                    } else if with_id {
-                        output::write_record_with_id(
-                            &mut writer,
-                            &record,
-                            &mut head_buffer,
-                            &mut seq_buffer,
-                        );
-                    } else if with_full_record {
-                        output::write_full_record(
-                            &mut writer,
-                            &record,
-                            &mut head_buffer,
-                            &mut seq_buffer,
-                            &mut qual_buffer,
-                        );
-                    } else if with_fasta {
-                        output::write_record_with_fasta(
-                            &mut writer,
-                            &record,
-                            &mut head_buffer,
-                            &mut seq_buffer,
-                        );
+                        for (name, forward, reverse) in matched_names {
+                            let annotation = if annotate_strand[index] {
+                                output::strand_annotation(forward, reverse)
+                            } else {
+                                b""
+                            };
+                            let writer = bucket_writers[index].get_mut(name).unwrap();
+                            record_writer.write(writer, &record, annotation);
+                        }
                     } else {
-                        writer.write_all(record.seq()).unwrap();
-                        writer.write_all(b"\n").unwrap();
+                        // Write to the output of the set, annotating the header with the
+                        // amplicons found or the matched strand(s).
+                        let annotation = if !amplicon_hits.is_empty() {
+                            Cow::Owned(output::amplicon_annotation(pattern_set, &amplicon_hits))
+                        } else if annotate_strand[index] {
+                            Cow::Borrowed(output::strand_annotation(
+                                matches
+                                    .iter()
+                                    .any(|&i| patterns[i].strand == Strand::Forward),
+                                matches
+                                    .iter()
+                                    .any(|&i| patterns[i].strand == Strand::Reverse),
+                            ))
+                        } else {
+                            Cow::Borrowed(&b""[..])
+                        };
+                        record_writer.write(&mut writers[index], &record, &annotation);
                     }
                 }
-                None::<()>
             },
         )
         .unwrap();
     }
-    
+
     // Ensure the database connection is properly closed, if one was established.
     if let Some(conn) = db_conn {
         conn.close().unwrap();
     }
 }
-
-// Inline function: average_quality calculates the average quality of a sequence.
-#[inline(always)]
-fn average_quality(qual: &[u8], encoding: &str) -> f32 {
-    quality::average_quality(qual, encoding)
-}
@@ -0,0 +1,643 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module matches compiled patterns against sequences. Exact patterns are matched
+// with the regex engine; patterns given a mismatch (Hamming) or edit distance are
+// matched with a dynamic programming engine that tolerates sequencing errors. A pattern
+// may be confined to a window of the sequence, such as its first or last bases.
+// Patterns that expand to a bounded set of literals (such as plain DNA and IUPAC
+// patterns) are searched for with an Aho-Corasick automaton rather than the regex engine.
+// Protein patterns are matched against the six-frame translation of the sequence.
+
+use crate::translate::{nucleotide_range, six_frames, FRAMES};
+use aho_corasick::{AhoCorasick, AhoCorasickKind};
+use regex::bytes::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use regex_syntax::hir::literal::Extractor;
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+// Enum: Distance
+// The number of differences tolerated when matching a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distance {
+    // Substitutions only (Hamming distance).
+    Mismatches(usize),
+    // Substitutions, insertions and deletions (edit distance).
+    Edits(usize),
+}
+
+// Enum: Window
+// The part of a sequence in which a pattern must match. A match counts only if it lies
+// entirely within the window. Positions are 0-based, and the end is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    // Within the first N bases.
+    First(usize),
+    // Within the last N bases.
+    Last(usize),
+    // Between the start and the (optional) end positions.
+    Range { start: usize, end: Option<usize> },
+}
+
+impl Window {
+    // Returns the start and end of the window within a sequence of the given length.
+    #[inline(always)]
+    pub fn bounds(&self, len: usize) -> (usize, usize) {
+        match *self {
+            Window::First(n) => (0, n.min(len)),
+            Window::Last(n) => (len.saturating_sub(n), len),
+            Window::Range { start, end } => {
+                let end = end.map_or(len, |end| end.min(len));
+                (start.min(end), end)
+            }
+        }
+    }
+}
+
+// Struct: Hit
+// The location of a pattern match within a sequence and the number of differences
+// between the matched substring and the pattern (0 for exact matches).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub start: usize,
+    pub end: usize,
+    pub distance: usize,
+    // The reading frame (+1 to +3, or -1 to -3) of the hit of a protein pattern, whose
+    // start and end are those of the bases encoding it.
+    pub frame: Option<i8>,
+}
+
+// Struct: PatternMatcher
+// Matches a set of patterns against sequences. Exact matching is delegated to a
+// LiteralSet for the patterns that expand to a bounded set of literals, and to a
+// RegexSet for the rest; patterns with a tolerated distance are additionally matched
+// approximately. Protein patterns are matched by a RegexSet of their own against the
+// six-frame translation of the sequence.
+pub struct PatternMatcher {
+    patterns: Vec<String>,
+    literal_set: Option<LiteralSet>,
+    regex_set: RegexSet,
+    // The index of the pattern of each regex of the regex set.
+    regex_set_patterns: Vec<usize>,
+    regexes: Vec<Regex>,
+    fuzzy: Vec<Option<FuzzyPattern>>,
+    // The window of each pattern, empty when no pattern is confined to one.
+    windows: Vec<Option<Window>>,
+    // Whether each pattern is a protein pattern, empty when none is.
+    protein: Vec<bool>,
+    // The protein patterns, and the index of the pattern of each of its regexes.
+    protein_set: Option<RegexSet>,
+    protein_set_patterns: Vec<usize>,
+    case_insensitive: bool,
+}
+
+impl PatternMatcher {
+    // Compiles the given regex patterns, each with an optional tolerated distance. When
+    // case_insensitive is set, the patterns also match lowercase (soft-masked) bases.
+    pub fn new(
+        patterns: &[String],
+        distances: &[Option<Distance>],
+        case_insensitive: bool,
+    ) -> Result<Self, String> {
+        Self::with_protein(patterns, distances, &[], case_insensitive)
+    }
+
+    // Function: with_protein
+    // Compiles the given regex patterns as for new, where the patterns flagged in protein
+    // are protein patterns, matched against the six-frame translation of the sequence.
+    // Protein patterns tolerate no distance.
+    pub fn with_protein(
+        patterns: &[String],
+        distances: &[Option<Distance>],
+        protein: &[bool],
+        case_insensitive: bool,
+    ) -> Result<Self, String> {
+        let is_protein = |index: usize| protein.get(index).copied().unwrap_or(false);
+        let literal_set = LiteralSet::new(patterns, &is_protein, case_insensitive);
+        let regex_set_patterns: Vec<usize> = (0..patterns.len())
+            .filter(|&index| {
+                !is_protein(index)
+                    && !literal_set
+                        .as_ref()
+                        .is_some_and(|literal_set| literal_set.contains(index))
+            })
+            .collect();
+        let protein_set_patterns: Vec<usize> = (0..patterns.len())
+            .filter(|&index| is_protein(index))
+            .collect();
+        let protein_set = if protein_set_patterns.is_empty() {
+            None
+        } else {
+            Some(
+                RegexSetBuilder::new(protein_set_patterns.iter().map(|&index| &patterns[index]))
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .map_err(|e| format!("Failed to compile protein patterns: {}", e))?,
+            )
+        };
+        let regex_set =
+            RegexSetBuilder::new(regex_set_patterns.iter().map(|&index| &patterns[index]))
+                .case_insensitive(case_insensitive)
+                .build()
+                .map_err(|e| format!("Failed to compile regex patterns: {}", e))?;
+        let regexes = patterns
+            .iter()
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .map_err(|e| format!("Failed to compile regex pattern: {}", e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let fuzzy = patterns
+            .iter()
+            .zip(distances.iter().chain(std::iter::repeat(&None)))
+            .enumerate()
+            .map(|(index, (pattern, distance))| match distance {
+                Some(_) if is_protein(index) => {
+                    Err("Protein patterns do not support maxMismatches or maxEdits".to_string())
+                }
+                Some(distance) => FuzzyPattern::new(pattern, *distance, case_insensitive).map(Some),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            patterns: patterns.to_vec(),
+            literal_set,
+            regex_set,
+            regex_set_patterns,
+            regexes,
+            fuzzy,
+            windows: Vec::new(),
+            protein: if protein_set.is_some() {
+                (0..patterns.len()).map(is_protein).collect()
+            } else {
+                Vec::new()
+            },
+            protein_set,
+            protein_set_patterns,
+            case_insensitive,
+        })
+    }
+
+    // Confines each pattern to the given window, if any.
+    pub fn with_windows(mut self, windows: Vec<Option<Window>>) -> Self {
+        self.windows = if windows.iter().any(Option::is_some) {
+            windows
+        } else {
+            Vec::new()
+        };
+        self
+    }
+
+    // Returns the compiled regex patterns.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    // Returns true if any pattern matches the sequence.
+    #[inline(always)]
+    pub fn is_match(&self, seq: &[u8]) -> bool {
+        if !self.windows.is_empty() {
+            return !self.matches(seq).is_empty();
+        }
+        self.literal_set
+            .as_ref()
+            .is_some_and(|literal_set| literal_set.is_match(seq))
+            || self.regex_set.is_match(seq)
+            || self.fuzzy.iter().flatten().any(|f| f.is_match(seq))
+            || self.protein_set.as_ref().is_some_and(|protein_set| {
+                six_frames(seq, self.case_insensitive)
+                    .iter()
+                    .any(|peptide| protein_set.is_match(peptide))
+            })
+    }
+
+    // Returns the indices, in ascending order, of the patterns matching the sequence.
+    pub fn matches(&self, seq: &[u8]) -> Vec<usize> {
+        let mut exact = vec![false; self.patterns.len()];
+        if let Some(literal_set) = &self.literal_set {
+            literal_set.mark_matches(seq, &mut exact);
+        }
+        for index in self.regex_set.matches(seq).iter() {
+            exact[self.regex_set_patterns[index]] = true;
+        }
+        if let Some(protein_set) = &self.protein_set {
+            for peptide in six_frames(seq, self.case_insensitive) {
+                for index in protein_set.matches(&peptide).iter() {
+                    exact[self.protein_set_patterns[index]] = true;
+                }
+            }
+        }
+        (0..self.regexes.len())
+            .filter(|&i| match self.window(i) {
+                // A windowed pattern is matched against its window alone.
+                Some(window) => {
+                    let (start, end) = window.bounds(seq.len());
+                    self.is_match_at(i, &seq[start..end])
+                }
+                None => exact[i] || self.fuzzy[i].as_ref().is_some_and(|f| f.is_match(seq)),
+            })
+            .collect()
+    }
+
+    // Function: find_translated
+    // Returns the matches of the protein pattern at the given index in each frame of the
+    // translation of the sequence, ordered by the position of the bases encoding them.
+    fn find_translated(&self, index: usize, seq: &[u8]) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = FRAMES
+            .iter()
+            .zip(six_frames(seq, self.case_insensitive))
+            .flat_map(|(&frame, peptide)| {
+                self.regexes[index]
+                    .find_iter(&peptide)
+                    .map(|m| {
+                        let (start, end) = nucleotide_range(frame, m.start(), m.end(), seq.len());
+                        Hit {
+                            start,
+                            end,
+                            distance: 0,
+                            frame: Some(frame),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        hits.sort_by_key(|hit| (hit.start, hit.end));
+        hits
+    }
+
+    // Returns true if the pattern at the given index matches the sequence.
+    fn is_match_at(&self, index: usize, seq: &[u8]) -> bool {
+        match &self.fuzzy[index] {
+            Some(fuzzy) => self.regexes[index].is_match(seq) || fuzzy.is_match(seq),
+            None => self.regexes[index].is_match(seq),
+        }
+    }
+
+    // Returns the window of the pattern at the given index, if any.
+    #[inline(always)]
+    fn window(&self, index: usize) -> Option<Window> {
+        self.windows.get(index).copied().flatten()
+    }
+
+    // Returns the first match of the pattern at the given index.
+    pub fn find(&self, index: usize, seq: &[u8]) -> Option<Hit> {
+        self.find_iter(index, seq).into_iter().next()
+    }
+
+    // Returns all non-overlapping matches of the pattern at the given index.
+    // Hits are reported in the coordinates of the whole sequence, even for a pattern
+    // confined to a window.
+    pub fn find_iter(&self, index: usize, seq: &[u8]) -> Vec<Hit> {
+        if self.protein.get(index).copied().unwrap_or(false) {
+            return self.find_translated(index, seq);
+        }
+        let (offset, end) = match self.window(index) {
+            Some(window) => window.bounds(seq.len()),
+            None => (0, seq.len()),
+        };
+        let seq = &seq[offset..end];
+        let hits = match &self.fuzzy[index] {
+            Some(fuzzy) => fuzzy.find_iter(seq),
+            None => self.regexes[index]
+                .find_iter(seq)
+                .map(|m| Hit {
+                    start: m.start(),
+                    end: m.end(),
+                    distance: 0,
+                    frame: None,
+                })
+                .collect(),
+        };
+        hits.into_iter()
+            .map(|hit| Hit {
+                start: hit.start + offset,
+                end: hit.end + offset,
+                ..hit
+            })
+            .collect()
+    }
+}
+
+// The total length of the literals of a LiteralSet up to which they are compiled into a
+// DFA, rather than into a smaller but slower NFA.
+const DFA_LITERAL_BYTES: usize = 100_000;
+
+// Struct: LiteralSet
+// The patterns that expand to a bounded set of literals, each of which is a literal of
+// an Aho-Corasick automaton. A pattern matches wherever one of its literals is found.
+struct LiteralSet {
+    automaton: AhoCorasick,
+    // The index of the pattern of each literal.
+    literal_patterns: Vec<usize>,
+    // Whether each pattern is matched by the literal set.
+    covered: Vec<bool>,
+}
+
+impl LiteralSet {
+    // Builds the literal set of those of the given patterns (other than protein patterns)
+    // that expand to a bounded set of literals, or returns None if there are none. When
+    // case_insensitive is set, the literals are matched ignoring ASCII case, so only
+    // literals for which that is the same as the regex engine's Unicode case folding are
+    // accepted.
+    fn new(
+        patterns: &[String],
+        is_protein: &dyn Fn(usize) -> bool,
+        case_insensitive: bool,
+    ) -> Option<Self> {
+        let mut literals = Vec::new();
+        let mut literal_patterns = Vec::new();
+        let mut covered = vec![false; patterns.len()];
+        for (index, pattern) in patterns.iter().enumerate() {
+            if is_protein(index) {
+                continue;
+            }
+            let Some(expansion) = expand_literals(pattern) else {
+                continue;
+            };
+            let folds_like_ascii = |literal: &Vec<u8>| {
+                literal
+                    .iter()
+                    .all(|&b| b.is_ascii() && !matches!(b.to_ascii_lowercase(), b'k' | b's'))
+            };
+            if case_insensitive && !expansion.iter().all(folds_like_ascii) {
+                continue;
+            }
+            covered[index] = true;
+            literal_patterns.extend(std::iter::repeat_n(index, expansion.len()));
+            literals.extend(expansion);
+        }
+        if literals.is_empty() {
+            return None;
+        }
+        // A DFA is fastest, but its size grows with the total length of the literals.
+        let total_length: usize = literals.iter().map(Vec::len).sum();
+        let kind = (total_length <= DFA_LITERAL_BYTES).then_some(AhoCorasickKind::DFA);
+        let automaton = AhoCorasick::builder()
+            .kind(kind)
+            .ascii_case_insensitive(case_insensitive)
+            .build(&literals)
+            .ok()?;
+        Some(Self {
+            automaton,
+            literal_patterns,
+            covered,
+        })
+    }
+
+    // Returns true if the pattern at the given index is matched by the literal set.
+    fn contains(&self, index: usize) -> bool {
+        self.covered[index]
+    }
+
+    // Returns true if any literal is found in the sequence.
+    #[inline(always)]
+    fn is_match(&self, seq: &[u8]) -> bool {
+        self.automaton.is_match(seq)
+    }
+
+    // Marks each pattern with a literal found in the sequence.
+    fn mark_matches(&self, seq: &[u8], matched: &mut [bool]) {
+        for found in self.automaton.find_overlapping_iter(seq) {
+            matched[self.literal_patterns[found.pattern().as_usize()]] = true;
+        }
+    }
+}
+
+// Function: expand_literals
+// Returns the literals a regex is equivalent to, if it expands to a bounded set of
+// non-empty literals without anchors or word boundaries, e.g. GG[AG]T to GGAT and GGGT.
+fn expand_literals(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    let hir = regex_syntax::Parser::new().parse(pattern).ok()?;
+    if !hir.properties().look_set().is_empty() {
+        return None;
+    }
+    let expansion = Extractor::new().extract(&hir);
+    if !expansion.is_exact() {
+        return None;
+    }
+    let literals: Vec<Vec<u8>> = expansion
+        .literals()?
+        .iter()
+        .map(|literal| literal.as_bytes().to_vec())
+        .collect();
+    if literals.is_empty() || literals.iter().any(Vec::is_empty) {
+        return None;
+    }
+    Some(literals)
+}
+
+// Struct: FuzzyPattern
+// A pattern of fixed length, stored as the set of bytes accepted at each position.
+struct FuzzyPattern {
+    positions: Vec<[bool; 256]>,
+    distance: Distance,
+}
+
+impl FuzzyPattern {
+    // Builds a fuzzy pattern from a regex made up of literals, character classes and
+    // fixed repetitions of these. Other regex constructs cannot be matched approximately.
+    // When case_insensitive is set, each position also accepts the other case of a letter.
+    fn new(pattern: &str, distance: Distance, case_insensitive: bool) -> Result<Self, String> {
+        let positions = fixed_positions(pattern, case_insensitive).map_err(|reason| {
+            format!(
+                "Pattern {} cannot be matched with maxMismatches or maxEdits: {}",
+                pattern, reason
+            )
+        })?;
+        if positions.is_empty() {
+            return Err(format!(
+                "Pattern {} cannot be matched with maxMismatches or maxEdits: pattern is empty",
+                pattern
+            ));
+        }
+        Ok(Self {
+            positions,
+            distance,
+        })
+    }
+
+    // Returns true if the pattern matches the sequence within the tolerated distance.
+    fn is_match(&self, seq: &[u8]) -> bool {
+        match self.distance {
+            Distance::Mismatches(k) => seq
+                .windows(self.positions.len())
+                .any(|window| self.mismatches(window, k) <= k),
+            Distance::Edits(k) => self.edit_candidates(seq, k).next().is_some(),
+        }
+    }
+
+    // Returns non-overlapping matches, choosing the match with the fewest differences
+    // (and then the leftmost) among overlapping candidates.
+    fn find_iter(&self, seq: &[u8]) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = Vec::new();
+        let mut consider = |candidate: Hit| match hits.last_mut() {
+            Some(last) if candidate.start < last.end => {
+                if candidate.distance < last.distance {
+                    *last = candidate;
+                }
+            }
+            _ => hits.push(candidate),
+        };
+        match self.distance {
+            Distance::Mismatches(k) => {
+                let len = self.positions.len();
+                for (start, window) in seq.windows(len).enumerate() {
+                    let distance = self.mismatches(window, k);
+                    if distance <= k {
+                        consider(Hit {
+                            start,
+                            end: start + len,
+                            distance,
+                            frame: None,
+                        });
+                    }
+                }
+            }
+            Distance::Edits(k) => self.edit_candidates(seq, k).for_each(&mut consider),
+        }
+        hits
+    }
+
+    // Counts the mismatches between the pattern and a window of equal length, stopping
+    // early once the count exceeds the limit.
+    #[inline(always)]
+    fn mismatches(&self, window: &[u8], limit: usize) -> usize {
+        let mut count = 0;
+        for (allowed, &base) in self.positions.iter().zip(window) {
+            if !allowed[base as usize] {
+                count += 1;
+                if count > limit {
+                    break;
+                }
+            }
+        }
+        count
+    }
+
+    // Yields, for each end position in the sequence, the best approximate match ending
+    // there with an edit distance within the limit (semi-global alignment, Sellers'
+    // algorithm). The start of each match is tracked alongside its cost.
+    fn edit_candidates<'a>(
+        &'a self,
+        seq: &'a [u8],
+        limit: usize,
+    ) -> impl Iterator<Item = Hit> + 'a {
+        let m = self.positions.len();
+        // Each cell holds (cost, start of the alignment in the sequence).
+        let mut previous: Vec<(usize, usize)> = (0..=m).map(|i| (i, 0)).collect();
+        let mut current = previous.clone();
+        seq.iter().enumerate().filter_map(move |(j, &base)| {
+            current[0] = (0, j + 1);
+            for i in 1..=m {
+                let substitution = (
+                    previous[i - 1].0 + usize::from(!self.positions[i - 1][base as usize]),
+                    previous[i - 1].1,
+                );
+                let insertion = (previous[i].0 + 1, previous[i].1);
+                let deletion = (current[i - 1].0 + 1, current[i - 1].1);
+                // Prefer the lowest cost, then the shortest alignment.
+                current[i] = [substitution, insertion, deletion]
+                    .into_iter()
+                    .min_by_key(|&(cost, start)| (cost, std::cmp::Reverse(start)))
+                    .unwrap();
+            }
+            std::mem::swap(&mut previous, &mut current);
+            let (cost, start) = previous[m];
+            (cost <= limit).then_some(Hit {
+                start,
+                end: j + 1,
+                distance: cost,
+                frame: None,
+            })
+        })
+    }
+}
+
+// Function: fixed_positions
+// Parses a regex made up of literals, character classes and fixed repetitions of these
+// into the set of bytes accepted at each position, each letter in both cases when
+// case_insensitive is set. Returns the reason if the regex cannot be parsed or has
+// other constructs.
+pub fn fixed_positions(pattern: &str, case_insensitive: bool) -> Result<Vec<[bool; 256]>, String> {
+    let hir = regex_syntax::ParserBuilder::new()
+        .case_insensitive(case_insensitive)
+        .build()
+        .parse(pattern)
+        .map_err(|e| format!("Failed to parse regex pattern {}: {}", pattern, e))?;
+    let mut positions = Vec::new();
+    collect_positions(&hir, &mut positions)?;
+    Ok(positions)
+}
+
+// Function: collect_positions
+// Flattens a regex syntax tree into the set of bytes accepted at each position.
+fn collect_positions(hir: &Hir, positions: &mut Vec<[bool; 256]>) -> Result<(), String> {
+    match hir.kind() {
+        HirKind::Empty => Ok(()),
+        HirKind::Literal(literal) => {
+            for &byte in literal.0.iter() {
+                let mut allowed = [false; 256];
+                allowed[byte as usize] = true;
+                positions.push(allowed);
+            }
+            Ok(())
+        }
+        HirKind::Class(class) => {
+            let mut allowed = [false; 256];
+            match class {
+                Class::Unicode(class) => {
+                    for range in class.iter() {
+                        let end = (range.end() as u32).min(255);
+                        for byte in (range.start() as u32)..=end {
+                            allowed[byte as usize] = true;
+                        }
+                    }
+                }
+                Class::Bytes(class) => {
+                    for range in class.iter() {
+                        for byte in range.start()..=range.end() {
+                            allowed[byte as usize] = true;
+                        }
+                    }
+                }
+            }
+            positions.push(allowed);
+            Ok(())
+        }
+        HirKind::Repetition(repetition) if Some(repetition.min) == repetition.max => {
+            let mut repeated = Vec::new();
+            collect_positions(&repetition.sub, &mut repeated)?;
+            for _ in 0..repetition.min {
+                positions.extend_from_slice(&repeated);
+            }
+            Ok(())
+        }
+        HirKind::Capture(capture) => collect_positions(&capture.sub, positions),
+        HirKind::Concat(subs) => subs
+            .iter()
+            .try_for_each(|sub| collect_positions(sub, positions)),
+        HirKind::Repetition(_) => Err("variable-length repetitions are not supported".to_string()),
+        HirKind::Alternation(_) => Err("alternations are not supported".to_string()),
+        HirKind::Look(_) => Err("anchors and word boundaries are not supported".to_string()),
+    }
+}
@@ -20,9 +20,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::amplicon::AmpliconHit;
+use crate::arg::Cli;
+use crate::matcher::Hit;
+use crate::patterns::{PatternSet, Strand};
+use crate::quality;
+use crate::translate;
 use rusqlite::{Connection, Result as SqlResult};
-use seq_io::fastq::{Record, RefRecord};
-use std::fs::read_to_string;
+use seq_io::fastq::Record;
+use serde_json::{json, Value};
+use std::borrow::Cow;
 use std::io::Write;
 
 // Write record with ID
@@ -33,12 +40,14 @@ use std::io::Write;
 // - record: Reference to the current FASTQ record.
 // - head_buffer: Buffer used to temporarily hold the header.
 // - seq_buffer: Buffer used to temporarily hold the sequence.
+// - annotation: Bytes appended to the header (e.g. the matched strand), may be empty.
 #[inline(always)]
-pub fn write_record_with_id<W: Write>(
+pub fn write_record_with_id<W: Write, R: Record>(
     writer: &mut W,
-    record: &RefRecord,
+    record: &R,
     head_buffer: &mut Vec<u8>,
     seq_buffer: &mut Vec<u8>,
+    annotation: &[u8],
 ) {
     head_buffer.clear(); // Ensure header buffer is empty.
     seq_buffer.clear(); // Ensure sequence buffer is empty.
@@ -46,6 +55,7 @@ pub fn write_record_with_id<W: Write>(
     seq_buffer.extend_from_slice(record.seq()); // Cache sequence from record.
     writer.write_all(b"@").unwrap(); // FASTQ header prefix.
     writer.write_all(head_buffer).unwrap(); // Write header.
+    writer.write_all(annotation).unwrap(); // Write header annotation.
     writer.write_all(b"\n").unwrap(); // Newline separator.
     writer.write_all(seq_buffer).unwrap(); // Write sequence.
     writer.write_all(b"\n").unwrap(); // Newline after sequence.
@@ -60,13 +70,15 @@ pub fn write_record_with_id<W: Write>(
 // - head_buffer: Buffer used to store the header temporarily.
 // - seq_buffer: Buffer used to store the sequence temporarily.
 // - qual_buffer: Buffer used to store the quality scores temporarily.
+// - annotation: Bytes appended to the header (e.g. the matched strand), may be empty.
 #[inline(always)]
-pub fn write_full_record<W: Write>(
+pub fn write_full_record<W: Write, R: Record>(
     writer: &mut W,
-    record: &RefRecord,
+    record: &R,
     head_buffer: &mut Vec<u8>,
     seq_buffer: &mut Vec<u8>,
     qual_buffer: &mut Vec<u8>,
+    annotation: &[u8],
 ) {
     head_buffer.clear(); // Clear header buffer.
     seq_buffer.clear(); // Clear sequence buffer.
@@ -76,6 +88,7 @@ pub fn write_full_record<W: Write>(
     qual_buffer.extend_from_slice(record.qual()); // Cache quality scores.
     writer.write_all(b"@").unwrap(); // Begin FASTQ record with '@'.
     writer.write_all(head_buffer).unwrap(); // Write header.
+    writer.write_all(annotation).unwrap(); // Write header annotation.
     writer.write_all(b"\n").unwrap(); // Newline.
     writer.write_all(seq_buffer).unwrap(); // Write sequence.
     writer.write_all(b"\n").unwrap(); // Newline.
@@ -93,12 +106,14 @@ pub fn write_full_record<W: Write>(
 // - record: Reference to the current FASTQ record.
 // - head_buffer: Buffer to store the header temporarily.
 // - seq_buffer: Buffer to store the sequence temporarily.
+// - annotation: Bytes appended to the header (e.g. the matched strand), may be empty.
 #[inline(always)]
-pub fn write_record_with_fasta<W: Write>(
+pub fn write_record_with_fasta<W: Write, R: Record>(
     writer: &mut W,
-    record: &RefRecord,
+    record: &R,
     head_buffer: &mut Vec<u8>,
     seq_buffer: &mut Vec<u8>,
+    annotation: &[u8],
 ) {
     head_buffer.clear(); // Clear header buffer.
     seq_buffer.clear(); // Clear sequence buffer.
@@ -106,11 +121,244 @@ pub fn write_record_with_fasta<W: Write>(
     seq_buffer.extend_from_slice(record.seq()); // Cache sequence.
     writer.write_all(b">").unwrap(); // FASTA header prefix.
     writer.write_all(head_buffer).unwrap(); // Write header.
+    writer.write_all(annotation).unwrap(); // Write header annotation.
     writer.write_all(b"\n").unwrap(); // Newline.
     writer.write_all(seq_buffer).unwrap(); // Write sequence.
     writer.write_all(b"\n").unwrap(); // Newline.
 }
 
+// Struct: RecordWriter
+// Writes records in the output format given by the CLI flags, reusing its buffers.
+pub struct RecordWriter {
+    with_id: bool,
+    with_full_record: bool,
+    with_fasta: bool,
+    head_buffer: Vec<u8>,
+    seq_buffer: Vec<u8>,
+    qual_buffer: Vec<u8>,
+}
+
+impl RecordWriter {
+    pub fn new(cli: &Cli) -> Self {
+        Self {
+            with_id: cli.with_id,
+            with_full_record: cli.with_full_record,
+            with_fasta: cli.with_fasta,
+            head_buffer: Vec::new(),
+            seq_buffer: Vec::new(),
+            qual_buffer: Vec::new(),
+        }
+    }
+
+    // Function: write
+    // Writes a record, appending the annotation (which may be empty) to its header.
+    #[inline(always)]
+    pub fn write<W: Write, R: Record>(&mut self, writer: &mut W, record: &R, annotation: &[u8]) {
+        if self.with_id {
+            write_record_with_id(
+                writer,
+                record,
+                &mut self.head_buffer,
+                &mut self.seq_buffer,
+                annotation,
+            );
+        } else if self.with_full_record {
+            write_full_record(
+                writer,
+                record,
+                &mut self.head_buffer,
+                &mut self.seq_buffer,
+                &mut self.qual_buffer,
+                annotation,
+            );
+        } else if self.with_fasta {
+            write_record_with_fasta(
+                writer,
+                record,
+                &mut self.head_buffer,
+                &mut self.seq_buffer,
+                annotation,
+            );
+        } else {
+            writer.write_all(record.seq()).unwrap();
+            writer.write_all(b"\n").unwrap();
+        }
+    }
+}
+
+// Function: output_file_name
+// Description: Returns the name of the file written for the given name (e.g. of a regex
+// for bucket mode, or of a regex set), with spaces replaced and quotes removed, and an
+// extension for the output format.
+pub fn output_file_name(cli: &Cli, name: &str) -> String {
+    let formatted_name = name.replace(' ', "-").replace('\'', "");
+    if cli.with_fasta {
+        format!("{}.fasta", formatted_name)
+    } else if cli.with_full_record {
+        format!("{}.fastq", formatted_name)
+    } else {
+        formatted_name
+    }
+}
+
+// Struct: OutputFiles
+// The files the records matching each regex set are written to (see output_files).
+pub struct OutputFiles {
+    // The output file of each set, or None for stdout. Empty in bucket mode.
+    pub sets: Vec<Option<String>>,
+    // The bucket name (regexName or ampliconName) and file of each bucket of each set.
+    // Empty unless in bucket mode.
+    pub buckets: Vec<Vec<(String, String)>>,
+}
+
+// Function: output_files
+// Description: Returns the files written by the default command. A set is written to the
+// output file it gives or, when there are several sets, to a file named after it;
+// otherwise to stdout. In bucket mode, records are only written to the bucket file of
+// each regexName of each set, or of each amplicon of a set giving amplicons, prefixed
+// with the regexSetName when there are several sets.
+// Parameters:
+// - cli: Command-line arguments, giving bucket mode and the output format.
+// - pattern_sets: The regex sets, in order.
+pub fn output_files(cli: &Cli, pattern_sets: &[PatternSet]) -> OutputFiles {
+    let multiple_sets = pattern_sets.len() > 1;
+    let set_name = |pattern_set: &PatternSet| pattern_set.name().unwrap_or("Unknown").to_string();
+    if !cli.bucket {
+        let sets = pattern_sets
+            .iter()
+            .map(|pattern_set| match pattern_set.output() {
+                Some(file_path) => Some(file_path.to_string()),
+                None if multiple_sets => Some(output_file_name(cli, &set_name(pattern_set))),
+                None => None,
+            })
+            .collect();
+        return OutputFiles {
+            sets,
+            buckets: Vec::new(),
+        };
+    }
+    let buckets = pattern_sets
+        .iter()
+        .map(|pattern_set| {
+            let mut names: Vec<&String> = if pattern_set.amplicons().is_empty() {
+                pattern_set
+                    .patterns()
+                    .iter()
+                    .map(|pattern| &pattern.name)
+                    .collect()
+            } else {
+                pattern_set
+                    .amplicons()
+                    .iter()
+                    .map(|amplicon| &amplicon.name)
+                    .collect()
+            };
+            // A regex and its reverse complement share a name, and hence a bucket.
+            names.dedup();
+            names
+                .into_iter()
+                .map(|bucket_name| {
+                    let name = if multiple_sets {
+                        format!("{}_{}", set_name(pattern_set), bucket_name)
+                    } else {
+                        bucket_name.clone()
+                    };
+                    (bucket_name.clone(), output_file_name(cli, &name))
+                })
+                .collect()
+        })
+        .collect();
+    OutputFiles {
+        sets: Vec::new(),
+        buckets,
+    }
+}
+
+// Function: write_counts
+// Description: Writes the number of records passing each regex set, naming each set when
+// there are several. With several input files, the counts of each file are written on
+// lines labelled with its path, followed by their totals, e.g. "a.fastq: 27".
+// Parameters:
+// - writer: Output handler to write the counts.
+// - paths: The input files, in order.
+// - set_names: The names of the regex sets.
+// - counts: The count of each set, for each input file.
+pub fn write_counts<W: Write>(
+    writer: &mut W,
+    paths: &[String],
+    set_names: &[&str],
+    counts: &[Vec<usize>],
+) -> std::io::Result<()> {
+    let mut totals = vec![0; set_names.len()];
+    let mut labelled: Vec<(&str, &[usize])> = Vec::new();
+    for (path, file_counts) in paths.iter().zip(counts) {
+        for (total, count) in totals.iter_mut().zip(file_counts) {
+            *total += count;
+        }
+        labelled.push((path, file_counts));
+    }
+    if paths.len() > 1 {
+        labelled.push(("Total", &totals));
+    }
+    for (label, counts) in labelled {
+        let label = if paths.len() > 1 {
+            format!("{}: ", label)
+        } else {
+            String::new()
+        };
+        if set_names.len() > 1 {
+            for (name, count) in set_names.iter().zip(counts) {
+                writeln!(writer, "{}{}: {}", label, name, count)?;
+            }
+        } else {
+            writeln!(writer, "{}{}", label, counts[0])?;
+        }
+    }
+    Ok(())
+}
+
+// Function: strand_annotation
+// Description: Returns the header annotation reporting the strand(s) on which a record matched.
+// Parameters:
+// - forward: Whether a forward strand pattern matched.
+// - reverse: Whether a reverse complement pattern matched.
+pub fn strand_annotation(forward: bool, reverse: bool) -> &'static [u8] {
+    match (forward, reverse) {
+        (true, true) => b" strand=+/-",
+        (true, false) => b" strand=+",
+        (false, true) => b" strand=-",
+        (false, false) => b"",
+    }
+}
+
+// Function: amplicon_annotation
+// Description: Returns the annotation appended to the header of a record containing
+// amplicons, giving the name, insert length and strand of each, e.g.
+// " amplicon=V4 insert=253 strand=+". Several amplicons are listed in order, separated
+// by commas.
+// Parameters:
+// - pattern_set: The regex set giving the amplicons.
+// - hits: The index and location of each amplicon found (see PatternSet::find_amplicons).
+pub fn amplicon_annotation(pattern_set: &PatternSet, hits: &[(usize, AmpliconHit)]) -> Vec<u8> {
+    let amplicons = pattern_set.amplicons();
+    let names: Vec<&str> = hits
+        .iter()
+        .map(|(index, _)| amplicons[*index].name.as_str())
+        .collect();
+    let inserts: Vec<String> = hits
+        .iter()
+        .map(|(_, hit)| hit.insert_length().to_string())
+        .collect();
+    let strands: Vec<&str> = hits.iter().map(|(_, hit)| hit.strand.as_str()).collect();
+    format!(
+        " amplicon={} insert={} strand={}",
+        names.join(","),
+        inserts.join(","),
+        strands.join(",")
+    )
+    .into_bytes()
+}
+
 // Function: create_sqlite_db
 // Description: Creates a SQLite database file for storing FASTQ records without quality metrics.
 // Returns: A rusqlite::Connection wrapped in a Result on success.
@@ -192,22 +440,21 @@ pub fn create_sqlite_db_with_quality() -> SqlResult<Connection> {
 // Description: Inserts regex pattern data and query information into the SQLite database.
 // Parameters:
 // - conn: Reference to the open SQLite connection.
-// - patterns_file: Path to the file containing regex patterns (JSON or text).
+// - pattern_set: The patterns (JSON, YAML or text) used for the query.
 // - queried_file: The file that was queried using these patterns.
 pub fn write_regex_to_db(
     conn: &Connection,
-    patterns_file: &str,
+    pattern_set: &PatternSet,
     queried_file: &str,
 ) -> SqlResult<()> {
-    // Read entire contents of the patterns file.
-    let file_content = read_to_string(patterns_file)
-        .map_err(|e| rusqlite::Error::InvalidParameterName(e.to_string()))?;
+    // The patterns as written, read once when the pattern set was created.
+    let file_content = pattern_set.source();
 
-    if patterns_file.ends_with(".json") {
-        // For JSON files, store the full content as a single entry.
+    if pattern_set.format().is_structured() {
+        // For JSON and YAML files, store the full content as a single entry.
         conn.execute(
             "INSERT INTO query (query, queried_file) VALUES (?1, ?2)",
-            [&file_content, queried_file],
+            [file_content, queried_file],
         )?;
     } else {
         // For text files, split each non-empty line into separate rows.
@@ -231,3 +478,188 @@ pub fn write_regex_to_db(
     }
     Ok(())
 }
+
+// Function: match_details
+// Description: Describes each match of the given patterns in a record, for the variants
+// JSON written to the SQLite database. Hits rejected for low quality are left out.
+// Parameters:
+// - pattern_set: The patterns used for the query.
+// - matches: The indices of the patterns found in the record.
+// - record: The FASTQ record.
+pub fn match_details<R: Record>(
+    pattern_set: &PatternSet,
+    matches: &[usize],
+    record: &R,
+) -> Vec<Value> {
+    let seq = record.seq();
+    let mut matches_info = vec![];
+    for &i in matches {
+        for matched in pattern_set.find_iter(i, seq, record.qual()) {
+            matches_info.push(hit_details(
+                pattern_set,
+                i,
+                &matched,
+                seq,
+                &pattern_set.matcher().patterns()[i],
+            ));
+        }
+    }
+    matches_info
+}
+
+// Function: hit_details
+// Description: Returns the details of a hit of the pattern at the given index, as written
+// to the variants column of the fastq_data table. The hit of a protein pattern is given
+// as the amino acids matched, with the frame of the translation and the start and end of
+// the bases encoding them; its strand is that of the frame.
+// Parameters:
+// - pattern_set: The regex set of the pattern.
+// - index: The index of the pattern.
+// - matched: The hit.
+// - seq: The sequence of the record.
+// - pattern: The pattern, as written to the pattern field.
+pub fn hit_details(
+    pattern_set: &PatternSet,
+    index: usize,
+    matched: &Hit,
+    seq: &[u8],
+    pattern: &str,
+) -> Value {
+    let matched_string = translate::peptide(seq, matched, pattern_set.case_insensitive())
+        .unwrap_or_else(|| seq[matched.start..matched.end].to_vec());
+    let strand = match matched.frame {
+        Some(frame) if frame < 0 => Strand::Reverse,
+        Some(_) => Strand::Forward,
+        None => pattern_set.patterns()[index].strand,
+    };
+    let mut details = json!({
+        "pattern": pattern,
+        "match": String::from_utf8_lossy(&matched_string).to_string(),
+        "start": matched.start,
+        "end": matched.end,
+        "strand": strand.as_str(),
+        "distance": matched.distance
+    });
+    if let Some(frame) = matched.frame {
+        details["frame"] = json!(frame);
+    }
+    details
+}
+
+// Function: add_mate_column
+// Description: Adds a column to the fastq_data table recording which mate (1 for R1, 2 for
+// R2) of a paired-end read each record is.
+pub fn add_mate_column(conn: &Connection) -> SqlResult<()> {
+    conn.execute("ALTER TABLE fastq_data ADD COLUMN mate INTEGER", [])?;
+    Ok(())
+}
+
+// Function: add_regex_set_name_column
+// Description: Adds a column to the fastq_data table recording the regexSetName of the
+// regex set each record matched, for patterns files giving several regexSets.
+pub fn add_regex_set_name_column(conn: &Connection) -> SqlResult<()> {
+    conn.execute("ALTER TABLE fastq_data ADD COLUMN regexSetName TEXT", [])?;
+    Ok(())
+}
+
+// Function: add_queried_file_column
+// Description: Adds a column to the fastq_data table recording the FASTQ file each record
+// was read from, when several files are queried.
+pub fn add_queried_file_column(conn: &Connection) -> SqlResult<()> {
+    conn.execute("ALTER TABLE fastq_data ADD COLUMN queried_file TEXT", [])?;
+    Ok(())
+}
+
+// Struct: RecordColumns
+// The optional columns of the fastq_data table written for a record, each given only if
+// the table has the column.
+#[derive(Clone, Copy, Default)]
+pub struct RecordColumns<'a> {
+    // The mate of a paired-end read.
+    pub mate: Option<u8>,
+    // The regexSetName of the matching set.
+    pub regex_set_name: Option<&'a str>,
+    // The FASTQ file the record was read from.
+    pub queried_file: Option<&'a str>,
+}
+
+// Function: write_record_to_db
+// Description: Inserts a FASTQ record, its quality statistics and its matches into the
+// fastq_data table.
+// Parameters:
+// - conn: Reference to the open SQLite connection.
+// - record: The FASTQ record.
+// - matches_info: The matches found in the record (see match_details).
+// - pattern_set: The regex set the record matches, giving the quality encoding (if the
+//   average quality is stored) and whether soft-masked bases count towards the statistics.
+// - num_tetranucleotides: The number of tetranucleotides written to TNF and CTNF.
+// - columns: The mate, regexSetName and queried_file columns of the record, for a table
+//   having them.
+pub fn write_record_to_db<R: Record>(
+    conn: &Connection,
+    record: &R,
+    matches_info: &[Value],
+    pattern_set: &PatternSet,
+    num_tetranucleotides: Option<usize>,
+    columns: RecordColumns,
+) -> SqlResult<()> {
+    let quality_encoding = pattern_set.predicates().quality_encoding.as_deref();
+    let seq = if pattern_set.case_insensitive() {
+        quality::unmask(record.seq())
+    } else {
+        Cow::Borrowed(record.seq())
+    };
+    let (tnf, ntn) = quality::tetranucleotide_frequencies(&seq, num_tetranucleotides);
+    let (ctnf, nctn) = quality::canonical_tetranucleotide_frequencies(&seq, num_tetranucleotides);
+    let gc = quality::gc_content(&seq);
+    let gc_int = gc.round() as i64;
+    let matches_json = serde_json::to_string(matches_info).unwrap_or_else(|_| "[]".to_string());
+
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+        Box::new(String::from_utf8_lossy(record.head()).to_string()),
+        Box::new(String::from_utf8_lossy(record.seq()).to_string()),
+        Box::new(String::from_utf8_lossy(record.qual()).to_string()),
+        Box::new(record.seq().len() as i64),
+        Box::new(gc),
+        Box::new(gc_int),
+        Box::new(ntn as i64),
+        Box::new(nctn as i64),
+        Box::new(tnf),
+        Box::new(ctnf),
+    ];
+    let optional = columns;
+    let mut columns =
+        "header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF".to_string();
+    let mut values = "?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10".to_string();
+
+    // Store the average quality when the pattern file gives a qualityEncoding.
+    if let Some(encoding) = quality_encoding {
+        params.push(Box::new(quality::average_quality(record.qual(), encoding)));
+        columns.push_str(", average_quality");
+        values.push_str(&format!(", ROUND(?{}, 2)", params.len()));
+    }
+    params.push(Box::new(matches_json));
+    columns.push_str(", variants");
+    values.push_str(&format!(", ?{}", params.len()));
+    if let Some(mate) = optional.mate {
+        params.push(Box::new(mate));
+        columns.push_str(", mate");
+        values.push_str(&format!(", ?{}", params.len()));
+    }
+    if let Some(regex_set_name) = optional.regex_set_name {
+        params.push(Box::new(regex_set_name.to_string()));
+        columns.push_str(", regexSetName");
+        values.push_str(&format!(", ?{}", params.len()));
+    }
+    if let Some(queried_file) = optional.queried_file {
+        params.push(Box::new(queried_file.to_string()));
+        columns.push_str(", queried_file");
+        values.push_str(&format!(", ?{}", params.len()));
+    }
+
+    conn.execute(
+        &format!("INSERT INTO fastq_data ({}) VALUES ({})", columns, values),
+        rusqlite::params_from_iter(params),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,471 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Paired-end filtering for the default and inverted commands.
+// Mates are read in lockstep, from two FASTQ files (R1 and R2) or from one interleaved
+// file, and the patterns are applied to each pair according to a pair rule. With
+// --R2-patterns, R1 and R2 are each searched with their own patterns. Both mates of a
+// matching pair are written, to paired outputs, bucket files or the SQLite database.
+use crate::arg::Cli;
+use crate::initialise::{
+    create_file_reader, create_file_writer, create_writer, load_patterns, load_patterns_file,
+};
+use crate::output;
+use crate::patterns::{PatternSet, Strand};
+use seq_io::fastq::{OwnedRecord, Reader, Record};
+use std::collections::HashMap;
+use std::io::{BufRead, BufWriter, Write};
+
+// The number of pairs read before they are matched in parallel.
+const BATCH_SIZE: usize = 16 * 1024;
+
+// Enum: PairRule
+// Which mates of a pair the patterns are applied to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PairRule {
+    // The pair matches if either mate matches.
+    Either,
+    // The pair matches if both mates match.
+    Both,
+    // Only R1 is searched.
+    R1,
+    // Only R2 is searched.
+    R2,
+}
+
+impl PairRule {
+    // Returns which mates (R1, R2) the patterns are applied to.
+    pub(crate) fn searched(self) -> (bool, bool) {
+        match self {
+            PairRule::Either | PairRule::Both => (true, true),
+            PairRule::R1 => (true, false),
+            PairRule::R2 => (false, true),
+        }
+    }
+
+    // Combines the results of matching each searched mate.
+    pub(crate) fn holds(self, r1: bool, r2: bool) -> bool {
+        match self {
+            PairRule::Either => r1 || r2,
+            PairRule::Both => r1 && r2,
+            PairRule::R1 => r1,
+            PairRule::R2 => r2,
+        }
+    }
+}
+
+// Struct: PairReader
+// Reads the mates of paired-end reads in lockstep, checking that they stay in sync.
+struct PairReader {
+    r1: Reader<Box<dyn BufRead + Send>>,
+    // The R2 reader, or None for an interleaved file.
+    r2: Option<Reader<Box<dyn BufRead + Send>>>,
+    pairs_read: usize,
+}
+
+impl PairReader {
+    fn new(cli: &Cli) -> Self {
+        if cli.file() == "-" && cli.mate_file.as_deref() == Some("-") {
+            eprintln!("Error: Only one of the R1 and R2 files can be read from standard input");
+            std::process::exit(1);
+        }
+        Self {
+            r1: create_file_reader(cli, cli.file()),
+            r2: cli
+                .mate_file
+                .as_ref()
+                .map(|file| create_file_reader(cli, file)),
+            pairs_read: 0,
+        }
+    }
+
+    // Returns the next pair, or an error if the input is not FASTQ or the mates are out
+    // of sync.
+    fn next_pair(&mut self) -> Option<Result<(OwnedRecord, OwnedRecord), String>> {
+        let r1 = next_record(&mut self.r1);
+        let r2 = match self.r2.as_mut() {
+            Some(reader) => next_record(reader),
+            None => next_record(&mut self.r1),
+        };
+        self.pairs_read += 1;
+        match (r1, r2) {
+            (None, None) => None,
+            (Some(Err(e)), _) | (_, Some(Err(e))) => Some(Err(e)),
+            (Some(Ok(r1)), Some(Ok(r2))) => {
+                if mate_id(r1.head()) != mate_id(r2.head()) {
+                    return Some(Err(format!(
+                        "Mates are out of sync at pair {}: {} and {}",
+                        self.pairs_read,
+                        String::from_utf8_lossy(r1.head()),
+                        String::from_utf8_lossy(r2.head())
+                    )));
+                }
+                Some(Ok((r1, r2)))
+            }
+            _ => Some(Err(format!(
+                "Mates are out of sync: no mate for pair {} ({})",
+                self.pairs_read,
+                if self.r2.is_some() {
+                    "the R1 and R2 files hold different numbers of records"
+                } else {
+                    "the interleaved file holds an odd number of records"
+                }
+            ))),
+        }
+    }
+}
+
+// Function: next_record
+// Reads the next record as an owned record.
+fn next_record(
+    reader: &mut Reader<Box<dyn BufRead + Send>>,
+) -> Option<Result<OwnedRecord, String>> {
+    reader.next().map(|result| {
+        result.map(|record| record.to_owned_record()).map_err(|e| {
+            format!(
+                "grepq only supports the fastq format. Check your input file.: {}",
+                e
+            )
+        })
+    })
+}
+
+// Function: mate_id
+// Returns the read ID shared by both mates: the header up to the first whitespace,
+// without a trailing /1 or /2.
+pub(crate) fn mate_id(head: &[u8]) -> &[u8] {
+    let id = head
+        .split(|b| b.is_ascii_whitespace())
+        .next()
+        .unwrap_or(head);
+    match id {
+        [rest @ .., b'/', b'1' | b'2'] => rest,
+        _ => id,
+    }
+}
+
+// Struct: MatePatterns
+// The pattern sets R1 and R2 are searched with: the same set for both mates, unless a
+// separate set is given for R2 with --R2-patterns.
+struct MatePatterns {
+    r1: PatternSet,
+    r2: Option<PatternSet>,
+}
+
+impl MatePatterns {
+    fn r1(&self) -> &PatternSet {
+        &self.r1
+    }
+
+    fn r2(&self) -> &PatternSet {
+        self.r2.as_ref().unwrap_or(&self.r1)
+    }
+
+    // Returns the set the given mate (1 or 2) is searched with.
+    fn mate(&self, mate: u8) -> &PatternSet {
+        if mate == 1 {
+            self.r1()
+        } else {
+            self.r2()
+        }
+    }
+
+    // Returns the sets of both mates, without repeating a set shared by them.
+    fn sets(&self) -> impl Iterator<Item = &PatternSet> {
+        std::iter::once(&self.r1).chain(&self.r2)
+    }
+}
+
+// Struct: PairMatches
+// The patterns found in each mate of a matching pair, as indices into the set the mate
+// was searched with.
+struct PairMatches {
+    r1: Vec<usize>,
+    r2: Vec<usize>,
+}
+
+// Function: evaluate
+// Applies the patterns to a pair according to the rule, returning the patterns found in
+// each searched mate if the pair is kept. For the inverted command, a pair is kept if
+// the searched mates pass the set-wide predicates and the rule does not hold.
+fn evaluate(
+    sets: &MatePatterns,
+    rule: PairRule,
+    inverted: bool,
+    with_matches: bool,
+    r1: &OwnedRecord,
+    r2: &OwnedRecord,
+) -> Option<PairMatches> {
+    let (search_r1, search_r2) = rule.searched();
+    let is_match =
+        |set: &PatternSet, mate: &OwnedRecord| set.is_match(mate.head(), mate.seq(), mate.qual());
+    let holds = rule.holds(
+        search_r1 && is_match(sets.r1(), r1),
+        search_r2 && is_match(sets.r2(), r2),
+    );
+    if inverted {
+        let passes = |set: &PatternSet, mate: &OwnedRecord| {
+            set.passes_predicates(mate.head(), mate.seq(), mate.qual())
+        };
+        let kept = !holds
+            && (!search_r1 || passes(sets.r1(), r1))
+            && (!search_r2 || passes(sets.r2(), r2));
+        return kept.then(|| PairMatches {
+            r1: Vec::new(),
+            r2: Vec::new(),
+        });
+    }
+    if !holds {
+        return None;
+    }
+    let matches = |search: bool, set: &PatternSet, mate: &OwnedRecord| {
+        if search && with_matches {
+            set.matches(mate.head(), mate.seq(), mate.qual())
+        } else {
+            Vec::new()
+        }
+    };
+    Some(PairMatches {
+        r1: matches(search_r1, sets.r1(), r1),
+        r2: matches(search_r2, sets.r2(), r2),
+    })
+}
+
+// Function: strands
+// Returns whether the patterns found, optionally only those with the given name, matched
+// the forward and the reverse strand.
+fn strands(pattern_set: &PatternSet, matches: &[usize], name: Option<&str>) -> (bool, bool) {
+    let patterns = pattern_set.patterns();
+    let found = || {
+        matches
+            .iter()
+            .filter(move |&&i| name.is_none_or(|name| patterns[i].name == name))
+    };
+    (
+        found().any(|&i| patterns[i].strand == Strand::Forward),
+        found().any(|&i| patterns[i].strand == Strand::Reverse),
+    )
+}
+
+// Function: bucket_file_name
+// Returns the name of the bucket file for the given regexName and mate (R1 or R2).
+fn bucket_file_name(cli: &Cli, name: &str, mate: &str) -> String {
+    output::output_file_name(cli, &format!("{}_{}", name, mate))
+}
+
+// Main function to filter paired-end reads, for the default command or, if inverted is
+// true, the inverted command.
+pub fn run_paired(cli: &Cli, inverted: bool) {
+    let sets = MatePatterns {
+        r1: load_patterns(cli),
+        r2: cli
+            .mate_patterns
+            .as_ref()
+            .map(|path| load_patterns_file(cli, path)),
+    };
+    let rule = cli.pair_rule;
+
+    // Bucket mode and SQL output are only available for the default command.
+    let bucket = cli.bucket && !inverted;
+    let write_sql = cli.write_sql && !inverted;
+    let annotate_strand = sets.sets().any(|set| set.has_reverse_complements()) && !inverted;
+    let with_matches = bucket || write_sql || annotate_strand;
+
+    // Set up the SQL database, recording the mate of each record.
+    let db_conn = if write_sql {
+        let with_quality = sets
+            .sets()
+            .any(|set| set.predicates().quality_encoding.is_some());
+        let conn = if with_quality {
+            output::create_sqlite_db_with_quality().unwrap()
+        } else {
+            output::create_sqlite_db().unwrap()
+        };
+        output::add_mate_column(&conn).unwrap();
+        let r2_file = cli.mate_file.as_deref().unwrap_or(cli.file());
+        match &sets.r2 {
+            // Each set is recorded with the file of the mates it is applied to.
+            Some(r2_set) => {
+                output::write_regex_to_db(&conn, &sets.r1, cli.file()).unwrap();
+                output::write_regex_to_db(&conn, r2_set, r2_file).unwrap();
+            }
+            None => {
+                let queried_files = match &cli.mate_file {
+                    Some(mate_file) => format!("{} {}", cli.file(), mate_file),
+                    None => cli.file().to_string(),
+                };
+                output::write_regex_to_db(&conn, &sets.r1, &queried_files).unwrap();
+            }
+        }
+        Some(conn)
+    } else {
+        None
+    };
+
+    // Write R1 and R2 to their own files, or both interleaved to stdout. In count and
+    // bucket mode no pairs are written there, so no writers are created.
+    let mut mate_writers = if cli.count || bucket {
+        None
+    } else {
+        Some(match (&cli.output_r1, &cli.output_r2) {
+            (Some(r1), Some(r2)) => (
+                create_file_writer(cli, r1),
+                Some(create_file_writer(cli, r2)),
+            ),
+            _ => (create_writer(cli), None),
+        })
+    };
+
+    // Each regexName has a bucket file for each mate.
+    let mut bucket_writers: HashMap<&str, (BufWriter<std::fs::File>, BufWriter<std::fs::File>)> =
+        HashMap::new();
+    if bucket {
+        for pattern in sets.sets().flat_map(|set| set.patterns()) {
+            bucket_writers
+                .entry(pattern.name.as_str())
+                .or_insert_with(|| {
+                    let create = |mate| {
+                        let file =
+                            std::fs::File::create(bucket_file_name(cli, &pattern.name, mate))
+                                .unwrap();
+                        BufWriter::new(file)
+                    };
+                    (create("R1"), create("R2"))
+                });
+        }
+    }
+
+    let mut reader = PairReader::new(cli);
+    let mut mate_writer = output::RecordWriter::new(cli);
+    let mut match_count = 0;
+    let num_threads = num_cpus::get();
+
+    loop {
+        // Read a batch of pairs, then match them in parallel.
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE {
+            match reader.next_pair() {
+                Some(Ok(pair)) => batch.push(pair),
+                Some(Err(e)) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let chunk_size = batch.len().div_ceil(num_threads);
+        let results: Vec<Option<PairMatches>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = batch
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let sets = &sets;
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(r1, r2)| evaluate(sets, rule, inverted, with_matches, r1, r2))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        // Write the kept pairs, in input order.
+        for ((r1, r2), found) in batch.iter().zip(results) {
+            let Some(found) = found else {
+                continue;
+            };
+            match_count += 1;
+            if cli.count {
+                continue;
+            }
+
+            if let Some(ref db) = db_conn {
+                for (mate, record, matches) in [(1, r1, &found.r1), (2, r2, &found.r2)] {
+                    let pattern_set = sets.mate(mate);
+                    let matches_info = output::match_details(pattern_set, matches, record);
+                    output::write_record_to_db(
+                        db,
+                        record,
+                        &matches_info,
+                        pattern_set,
+                        cli.num_tetranucleotides,
+                        output::RecordColumns {
+                            mate: Some(mate),
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+                }
+            }
+
+            // Annotate each mate with the strand(s) matched in it.
+            let annotation = |mate: u8, matches: &[usize], name: Option<&str>| {
+                if annotate_strand {
+                    let (forward, reverse) = strands(sets.mate(mate), matches, name);
+                    output::strand_annotation(forward, reverse)
+                } else {
+                    b""
+                }
+            };
+
+            if bucket {
+                // A pair is written to the bucket of each regexName found in either mate.
+                let name = |mate: u8, &i: &usize| sets.mate(mate).patterns()[i].name.as_str();
+                let mut names: Vec<&str> = found
+                    .r1
+                    .iter()
+                    .map(|i| name(1, i))
+                    .chain(found.r2.iter().map(|i| name(2, i)))
+                    .collect();
+                names.sort_unstable();
+                names.dedup();
+                for name in names {
+                    let (r1_bucket, r2_bucket) = bucket_writers.get_mut(name).unwrap();
+                    mate_writer.write(r1_bucket, r1, annotation(1, &found.r1, Some(name)));
+                    mate_writer.write(r2_bucket, r2, annotation(2, &found.r2, Some(name)));
+                }
+            } else if let Some((r1_writer, r2_writer)) = mate_writers.as_mut() {
+                mate_writer.write(r1_writer, r1, annotation(1, &found.r1, None));
+                let r2_output = r2_writer.as_mut().unwrap_or(r1_writer);
+                mate_writer.write(r2_output, r2, annotation(2, &found.r2, None));
+            }
+        }
+    }
+
+    if cli.count {
+        // The number of matching pairs, written to stdout through the only writer, like
+        // the counts of every other command.
+        let mut writer = create_writer(cli);
+        writeln!(writer, "{}", match_count).unwrap();
+    }
+
+    if let Some(conn) = db_conn {
+        conn.close().unwrap();
+    }
+}
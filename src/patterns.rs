@@ -0,0 +1,1534 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module holds the typed representation of a patterns file. A PatternSet
+// contains the compiled patterns together with the name, original string, converted
+// regex and variants of each pattern, and the predicates applied to every record.
+// It can be built from a patterns file, from the contents of one, or from a serde
+// value, so that grepq can be embedded as a library.
+
+use crate::amplicon::{Amplicon, AmpliconHit};
+use crate::header::{HeaderCondition, HeaderFilter, HeaderFormat};
+use crate::initialise::convert_iupac_to_regex;
+use crate::iupac::{reverse_complement_regex, reverse_complement_sequence};
+use crate::matcher::{Distance, Hit, PatternMatcher, Window};
+use crate::quality::{average_quality, quality_offset};
+use crate::schema;
+use regex::bytes::Regex;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+// Enum: PatternFormat
+// The format of a patterns file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PatternFormat {
+    // One regex pattern per line.
+    Text,
+    Json,
+    Yaml,
+    // One regex per record, named by the header; see PatternSet::from_fasta.
+    Fasta,
+}
+
+impl PatternFormat {
+    // Function: detect
+    // Determines the format of a patterns file from its content, so that files without
+    // a recognised extension (e.g. from process substitution) are read correctly. A
+    // document starting with '{' is JSON, a YAML mapping is YAML, and anything else is
+    // plain text with one regex pattern per line. A document starting with '>' or ';'
+    // is FASTA.
+    pub fn detect(contents: &str) -> Self {
+        let contents = contents.trim_start();
+        if contents.starts_with('{') {
+            PatternFormat::Json
+        } else if contents.starts_with('>') || contents.starts_with(';') {
+            PatternFormat::Fasta
+        } else if matches!(
+            serde_yaml_ng::from_str::<Value>(contents),
+            Ok(Value::Object(_))
+        ) {
+            PatternFormat::Yaml
+        } else {
+            PatternFormat::Text
+        }
+    }
+
+    // Returns true for the structured (JSON, YAML or FASTA) formats, whose regexes are
+    // named.
+    pub fn is_structured(&self) -> bool {
+        !matches!(self, PatternFormat::Text)
+    }
+}
+
+// Enum: ErrorLocation
+// Where in a patterns file an error was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorLocation {
+    // A regex entry of a JSON or YAML patterns file, identified by its regexName.
+    Regex(String),
+    // A line of a plain-text patterns file, counting from 1.
+    Line(usize),
+    // A set-wide field of a JSON or YAML patterns file, such as headerRegex.
+    Field(String),
+}
+
+// Struct: PatternError
+// An error in a patterns file, giving the regex entry (or line) at fault, the offset
+// of the offending character within the pattern (counting from 0), and the reason.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternError {
+    pub location: Option<ErrorLocation>,
+    pub offset: Option<usize>,
+    pub reason: String,
+}
+
+impl PatternError {
+    // Creates an error with the given reason and no location.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            location: None,
+            offset: None,
+            reason: reason.into(),
+        }
+    }
+
+    // Sets the offset of the offending character within the pattern.
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    // Sets the location of the error, unless already known.
+    pub fn at(mut self, location: ErrorLocation) -> Self {
+        self.location.get_or_insert(location);
+        self
+    }
+}
+
+impl From<String> for PatternError {
+    fn from(reason: String) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl From<&str> for PatternError {
+    fn from(reason: &str) -> Self {
+        Self::new(reason)
+    }
+}
+
+impl std::fmt::Display for PatternError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Error in patterns file")?;
+        match &self.location {
+            Some(ErrorLocation::Regex(name)) => write!(f, " at regex \"{}\"", name)?,
+            Some(ErrorLocation::Line(line)) => write!(f, " at line {}", line)?,
+            Some(ErrorLocation::Field(field)) => write!(f, " at {}", field)?,
+            None => {}
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", offset {}", offset)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+// Enum: Strand
+// The strand on which a compiled regex pattern matches. Patterns taken verbatim from
+// the patterns file are on the forward strand; generated reverse complements are not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+impl Strand {
+    // Returns the strand as "+" or "-".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Strand::Forward => "+",
+            Strand::Reverse => "-",
+        }
+    }
+}
+
+// Struct: Variant
+// A named variant of a regex pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub name: String,
+    pub sequence: String,
+}
+
+// Struct: Pattern
+// A single compiled pattern. The reverse complement of a regex is a pattern of its
+// own, sharing the regex name, with its original string and variants reverse
+// complemented.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    // The regexName, or the pattern itself for plain-text pattern files.
+    pub name: String,
+    // The pattern as written in the patterns file.
+    pub original: String,
+    // The pattern after IUPAC-to-regex conversion, as compiled.
+    pub regex: String,
+    pub strand: Strand,
+    pub distance: Option<Distance>,
+    // The part of the sequence the pattern must match within, if any. The reverse
+    // complement of a regex shares its window, in the coordinates of the read.
+    pub window: Option<Window>,
+    pub variants: Vec<Variant>,
+    // The predicates a record must pass for this pattern to be found in it: those of
+    // the regex entry, with any not given taken from the regexSet.
+    pub predicates: Predicates,
+    // The quality the bases of a hit must meet for it to be accepted.
+    pub match_quality: MatchQuality,
+    // Whether the pattern is a protein pattern, matched against the six-frame translation
+    // of the sequence rather than the sequence itself.
+    pub protein: bool,
+}
+
+// Struct: MatchQuality
+// The minimum Phred score of each base, and the minimum mean Phred score, of the bases
+// within a hit. Hits that fall short are rejected. Given for the whole regexSet, and may
+// be overridden by each regex entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchQuality {
+    pub minimum_base_quality: Option<u8>,
+    pub minimum_average_quality: Option<f32>,
+}
+
+impl MatchQuality {
+    // Function: from_value
+    // Reads the match quality given in a regexSet or regex entry, with any not given
+    // taken from the defaults.
+    fn from_value(value: &Value, defaults: MatchQuality) -> Self {
+        Self {
+            minimum_base_quality: value["minimumMatchQuality"]
+                .as_u64()
+                .map(|q| q.min(u8::MAX as u64) as u8)
+                .or(defaults.minimum_base_quality),
+            minimum_average_quality: value["minimumMatchAverageQuality"]
+                .as_f64()
+                .map(|q| q as f32)
+                .or(defaults.minimum_average_quality),
+        }
+    }
+
+    // Returns true if any minimum is given.
+    pub fn is_set(&self) -> bool {
+        self.minimum_base_quality.is_some() || self.minimum_average_quality.is_some()
+    }
+
+    // Returns true if the quality scores of the bases within a hit meet the minimums.
+    pub fn accepts(&self, qual: &[u8], quality_encoding: &str) -> bool {
+        let offset = quality_offset(quality_encoding);
+        self.minimum_base_quality
+            .is_none_or(|min_q| qual.iter().all(|&q| q as i32 - offset >= min_q as i32))
+            && self
+                .minimum_average_quality
+                .is_none_or(|min_q| average_quality(qual, quality_encoding) >= min_q)
+    }
+}
+
+// Struct: Predicates
+// The filters applied to each record before its sequence is matched. They are given
+// for the whole regexSet, and may be overridden by each regex entry.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Predicates {
+    pub header_regex: Option<String>,
+    // The include and exclude conditions on the fields of the header.
+    pub header_conditions: Option<Vec<HeaderCondition>>,
+    // The layout of the headers the header conditions refer to.
+    pub header_format: Option<HeaderFormat>,
+    pub minimum_sequence_length: Option<u64>,
+    pub minimum_average_quality: Option<f32>,
+    pub quality_encoding: Option<String>,
+}
+
+impl Predicates {
+    // Function: from_value
+    // Reads the predicates given in a regexSet or regex entry.
+    fn from_value(value: &Value) -> Self {
+        Self {
+            header_regex: value["headerRegex"].as_str().map(|s| s.to_string()),
+            header_conditions: value["headerConditions"].as_array().map(|conditions| {
+                conditions
+                    .iter()
+                    .map(|condition| HeaderCondition {
+                        expression: condition["exclude"]
+                            .as_str()
+                            .or_else(|| condition["include"].as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                        exclude: condition.get("exclude").is_some(),
+                    })
+                    .collect()
+            }),
+            header_format: value["headerFormat"]
+                .as_str()
+                .and_then(HeaderFormat::from_name),
+            minimum_sequence_length: value["minimumSequenceLength"].as_u64(),
+            minimum_average_quality: value["minimumAverageQuality"].as_f64().map(|q| q as f32),
+            quality_encoding: value["qualityEncoding"].as_str().map(|s| s.to_string()),
+        }
+    }
+
+    // Function: or
+    // Returns these predicates, with any that are not given taken from the defaults.
+    fn or(self, defaults: &Predicates) -> Self {
+        Self {
+            header_regex: self.header_regex.or_else(|| defaults.header_regex.clone()),
+            header_conditions: self
+                .header_conditions
+                .or_else(|| defaults.header_conditions.clone()),
+            header_format: self.header_format.or(defaults.header_format),
+            minimum_sequence_length: self
+                .minimum_sequence_length
+                .or(defaults.minimum_sequence_length),
+            minimum_average_quality: self
+                .minimum_average_quality
+                .or(defaults.minimum_average_quality),
+            quality_encoding: self
+                .quality_encoding
+                .or_else(|| defaults.quality_encoding.clone()),
+        }
+    }
+}
+
+// Struct: RecordFilter
+// Predicates compiled for checking records.
+struct RecordFilter {
+    header_regex: Option<Regex>,
+    header_filter: Option<HeaderFilter>,
+    minimum_sequence_length: Option<u64>,
+    minimum_average_quality: Option<f32>,
+    quality_encoding: String,
+}
+
+impl RecordFilter {
+    // Compiles the header regex and header conditions of the given predicates. Errors are
+    // located at the regex entry with the given name or, for the set-wide predicates, at
+    // the field at fault.
+    fn new(predicates: &Predicates, regex_name: Option<&str>) -> Result<Self, PatternError> {
+        let location = |field: &str| match regex_name {
+            Some(name) => ErrorLocation::Regex(name.to_string()),
+            None => ErrorLocation::Field(field.to_string()),
+        };
+        let header_regex = predicates
+            .header_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| {
+                PatternError::new(format!("Invalid headerRegex: {}", e)).at(location("headerRegex"))
+            })?;
+        let header_filter = predicates
+            .header_conditions
+            .as_deref()
+            .map(|conditions| HeaderFilter::new(conditions, predicates.header_format))
+            .transpose()
+            .map_err(|e| PatternError::new(e).at(location("headerConditions")))?;
+        Ok(Self {
+            header_regex,
+            header_filter,
+            minimum_sequence_length: predicates.minimum_sequence_length,
+            minimum_average_quality: predicates.minimum_average_quality,
+            quality_encoding: predicates
+                .quality_encoding
+                .clone()
+                .unwrap_or_else(|| "Phred+33".to_string()),
+        })
+    }
+
+    // Returns true if a record (given by its header, sequence and quality) passes.
+    #[inline(always)]
+    fn passes(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> bool {
+        self.minimum_sequence_length
+            .is_none_or(|len| seq.len() >= len as usize)
+            && self
+                .header_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(head))
+            && self
+                .header_filter
+                .as_ref()
+                .is_none_or(|filter| filter.passes(head))
+            && self
+                .minimum_average_quality
+                .is_none_or(|min_q| average_quality(qual, &self.quality_encoding) >= min_q)
+    }
+}
+
+// Enum: Combination
+// A boolean expression over the regexes of a set, referring to each by name. A record
+// is kept if the expression holds for the patterns found in it. A name stands for all
+// the patterns with that name, so a regex with its reverse complement is found if
+// either strand is.
+#[derive(Debug)]
+enum Combination {
+    Regex(Vec<usize>),
+    All(Vec<Combination>),
+    Any(Vec<Combination>),
+    AtLeast(usize, Vec<Combination>),
+    Not(Box<Combination>),
+}
+
+impl Combination {
+    // Function: from_value
+    // Reads a combination (already validated against the schema), resolving regex names
+    // to pattern indices.
+    fn from_value(value: &Value, patterns: &[Pattern]) -> Result<Self, PatternError> {
+        let list = |items: &Value| -> Result<Vec<Combination>, PatternError> {
+            items
+                .as_array()
+                .ok_or("Invalid combination")?
+                .iter()
+                .map(|item| Combination::from_value(item, patterns))
+                .collect()
+        };
+        if let Some(name) = value.as_str() {
+            let indices: Vec<usize> = patterns
+                .iter()
+                .enumerate()
+                .filter(|(_, pattern)| pattern.name == name)
+                .map(|(index, _)| index)
+                .collect();
+            if indices.is_empty() {
+                return Err(format!("Combination refers to unknown regex \"{}\"", name).into());
+            }
+            Ok(Self::Regex(indices))
+        } else if let Some(items) = value.get("all") {
+            Ok(Self::All(list(items)?))
+        } else if let Some(items) = value.get("any") {
+            Ok(Self::Any(list(items)?))
+        } else if let Some(count) = value.get("atLeast").and_then(Value::as_u64) {
+            let items = list(&value["of"])?;
+            if count as usize > items.len() {
+                return Err(format!(
+                    "Combination requires at least {} of {} regexes",
+                    count,
+                    items.len()
+                )
+                .into());
+            }
+            Ok(Self::AtLeast(count as usize, items))
+        } else if let Some(item) = value.get("not") {
+            Ok(Self::Not(Box::new(Combination::from_value(
+                item, patterns,
+            )?)))
+        } else {
+            Err("Invalid combination".into())
+        }
+    }
+
+    // Returns true if the expression holds, given the indices of the patterns found.
+    fn evaluate(&self, found: &[usize]) -> bool {
+        match self {
+            Self::Regex(indices) => indices.iter().any(|index| found.contains(index)),
+            Self::All(items) => items.iter().all(|item| item.evaluate(found)),
+            Self::Any(items) => items.iter().any(|item| item.evaluate(found)),
+            Self::AtLeast(count, items) => {
+                items.iter().filter(|item| item.evaluate(found)).count() >= *count
+            }
+            Self::Not(item) => !item.evaluate(found),
+        }
+    }
+}
+
+// Struct: PatternSet
+// The patterns and predicates of a patterns file, with the patterns compiled into a
+// PatternMatcher. Pattern indices returned by the matcher index into patterns().
+pub struct PatternSet {
+    name: Option<String>,
+    // The file the records matching the set are written to, if given.
+    output: Option<String>,
+    format: PatternFormat,
+    source: String,
+    patterns: Vec<Pattern>,
+    predicates: Predicates,
+    matcher: PatternMatcher,
+    // The set-wide predicates, compiled.
+    filter: RecordFilter,
+    // The predicates of each pattern, compiled, when any regex entry overrides the
+    // set-wide predicates.
+    pattern_filters: Option<Vec<RecordFilter>>,
+    // The boolean combination of regexes a record must satisfy, in place of any regex.
+    combination: Option<Combination>,
+    // The amplicons a record must contain one of, in place of any regex.
+    amplicons: Vec<Amplicon>,
+    // Whether any pattern gives a match quality, so hits must be checked.
+    checks_match_quality: bool,
+    // Whether the patterns also match lowercase (soft-masked) bases.
+    case_insensitive: bool,
+}
+
+impl PatternSet {
+    // Function: from_file
+    // Reads and compiles a patterns file, which may be JSON, YAML, FASTA or plain text.
+    // The format is detected from the content of the file unless given. The file must
+    // give a single regex set.
+    pub fn from_file(
+        patterns_path: &str,
+        format: Option<PatternFormat>,
+    ) -> Result<Self, PatternError> {
+        Self::single(Self::sets_from_file(patterns_path, format)?)
+    }
+
+    // Function: sets_from_file
+    // Reads and compiles each regex set of a patterns file, as for from_file. Files
+    // included by the patterns file are found relative to it.
+    pub fn sets_from_file(
+        patterns_path: &str,
+        format: Option<PatternFormat>,
+    ) -> Result<Vec<Self>, PatternError> {
+        let (contents, format) = read_patterns_file(patterns_path, format)?;
+        Self::sets_from_contents(&contents, format, Some(Path::new(patterns_path)))
+    }
+
+    // Function: from_str
+    // Compiles the contents of a patterns file given in the specified format, which must
+    // give a single regex set.
+    pub fn from_str(contents: &str, format: PatternFormat) -> Result<Self, PatternError> {
+        Self::single(Self::sets_from_str(contents, format)?)
+    }
+
+    // Function: sets_from_str
+    // Compiles each regex set of the contents of a patterns file, as for from_str. Files
+    // included by the patterns are found relative to the current directory.
+    pub fn sets_from_str(contents: &str, format: PatternFormat) -> Result<Vec<Self>, PatternError> {
+        Self::sets_from_contents(contents, format, None)
+    }
+
+    // Function: sets_from_contents
+    // Compiles each regex set of the contents of the given patterns file, if any. A FASTA
+    // file that does not give a regexSetName is named after the file. The included files
+    // of a JSON or YAML document are resolved before it is validated, and the source of
+    // each set is then the resolved document, so that the query can be reproduced.
+    fn sets_from_contents(
+        contents: &str,
+        format: PatternFormat,
+        patterns_path: Option<&Path>,
+    ) -> Result<Vec<Self>, PatternError> {
+        let mut json = match format {
+            PatternFormat::Text => return Ok(vec![Self::from_lines(contents)?]),
+            PatternFormat::Fasta => {
+                let name = patterns_path.and_then(file_stem);
+                return Ok(vec![Self::from_fasta(contents, name.as_deref())?]);
+            }
+            _ => parse_document(contents, format)?,
+        };
+        let base_dir = patterns_path
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""));
+        let mut chain: Vec<PathBuf> = patterns_path
+            .and_then(|path| path.canonicalize().ok())
+            .into_iter()
+            .collect();
+        let included = resolve_includes(&mut json, base_dir, &mut chain)?;
+        let mut pattern_sets = Self::sets_from_value(&json, format)?;
+        // Keep the document as written, rather than as re-serialised, unless resolved.
+        let source = if included {
+            serde_json::to_string_pretty(&json).unwrap_or_else(|_| json.to_string())
+        } else {
+            contents.to_string()
+        };
+        for pattern_set in &mut pattern_sets {
+            pattern_set.source = source.clone();
+        }
+        Ok(pattern_sets)
+    }
+
+    // Function: from_value
+    // Validates a JSON (or YAML) patterns document against the schema and compiles it.
+    // The format records where the document came from. The document must give a single
+    // regex set.
+    pub fn from_value(json: &Value, format: PatternFormat) -> Result<Self, PatternError> {
+        Self::single(Self::sets_from_value(json, format)?)
+    }
+
+    // Function: single
+    // Returns the only regex set of a patterns file.
+    fn single(mut pattern_sets: Vec<Self>) -> Result<Self, PatternError> {
+        if pattern_sets.len() == 1 {
+            Ok(pattern_sets.remove(0))
+        } else {
+            Err(format!(
+                "Found {} regexSets, but only the default and summarise commands support more than one",
+                pattern_sets.len()
+            )
+            .into())
+        }
+    }
+
+    // Function: sets_from_value
+    // Validates a JSON (or YAML) patterns document against the schema of its version and
+    // compiles its regexSet, or each of its regexSets, which must be named uniquely. A
+    // document of an older version is migrated to the current schema first. The source
+    // of each set is the whole document.
+    pub fn sets_from_value(json: &Value, format: PatternFormat) -> Result<Vec<Self>, PatternError> {
+        let (_, json) = schema::upgrade(json)?;
+        let json = json.as_ref();
+
+        let regex_sets = match json.get("regexSets").and_then(Value::as_array) {
+            Some(regex_sets) => regex_sets.iter().collect(),
+            None => vec![&json["regexSet"]],
+        };
+        let mut names = std::collections::HashSet::new();
+        for regex_set in &regex_sets {
+            let name = regex_set["regexSetName"].as_str().unwrap_or_default();
+            if !names.insert(name) {
+                return Err(
+                    PatternError::new(format!("Duplicate regexSetName \"{}\"", name))
+                        .at(ErrorLocation::Field("regexSets".to_string())),
+                );
+            }
+        }
+        regex_sets
+            .into_iter()
+            .map(|regex_set| Self::from_regex_set(regex_set, format, json.to_string()))
+            .collect()
+    }
+
+    // Function: from_regex_set
+    // Compiles a regexSet of a validated patterns document.
+    fn from_regex_set(
+        regex_set: &Value,
+        format: PatternFormat,
+        source: String,
+    ) -> Result<Self, PatternError> {
+        // Convert patterns using IUPAC-to-regex conversion, adding the reverse
+        // complement of each pattern where requested.
+        let set_predicates = Predicates::from_value(regex_set);
+        let set_match_quality = MatchQuality::from_value(regex_set, MatchQuality::default());
+        let mut patterns = Vec::new();
+        for r in regex_set["regex"]
+            .as_array()
+            .ok_or("Invalid JSON structure")?
+        {
+            let regex_string = r["regexString"].as_str().ok_or("Invalid regexString")?;
+            let regex_name = r["regexName"].as_str().unwrap_or(regex_string);
+            let location = ErrorLocation::Regex(regex_name.to_string());
+            let distance = parse_distance(r).map_err(|e| e.at(location.clone()))?;
+            let window = parse_window(r).map_err(|e| e.at(location.clone()))?;
+            let match_quality = MatchQuality::from_value(r, set_match_quality);
+            let protein = protein_enabled(regex_set, r);
+            let variants = parse_variants(r, protein).map_err(|e| e.at(location.clone()))?;
+            // The quality encoding and header format are those of the whole file, so are
+            // only given for the set.
+            let predicates = Predicates {
+                quality_encoding: None,
+                header_format: None,
+                ..Predicates::from_value(r)
+            }
+            .or(&set_predicates);
+            if protein {
+                // A protein pattern is matched against the translation of both strands, so
+                // it has no reverse complement, and its letters are amino acids rather
+                // than IUPAC codes.
+                if distance.is_some() || window.is_some() {
+                    return Err(PatternError::new(
+                        "Protein patterns do not support maxMismatches, maxEdits or window",
+                    )
+                    .at(location));
+                }
+                patterns.push(Pattern {
+                    name: regex_name.to_string(),
+                    original: regex_string.to_string(),
+                    regex: regex_string.to_string(),
+                    strand: Strand::Forward,
+                    distance,
+                    window,
+                    variants,
+                    predicates,
+                    match_quality,
+                    protein,
+                });
+                continue;
+            }
+            let regex = convert_iupac_to_regex(regex_string).map_err(|e| e.at(location.clone()))?;
+            if reverse_complement_enabled(regex_set, r) {
+                let reversed =
+                    reverse_complement_regex(regex_string).map_err(|e| e.at(location.clone()))?;
+                let reversed_variants = variants
+                    .iter()
+                    .map(|variant| Variant {
+                        name: variant.name.clone(),
+                        sequence: reverse_complement_sequence(&variant.sequence),
+                    })
+                    .collect();
+                patterns.push(Pattern {
+                    name: regex_name.to_string(),
+                    original: regex_string.to_string(),
+                    regex,
+                    strand: Strand::Forward,
+                    distance,
+                    window,
+                    variants,
+                    predicates: predicates.clone(),
+                    match_quality,
+                    protein,
+                });
+                patterns.push(Pattern {
+                    name: regex_name.to_string(),
+                    regex: convert_iupac_to_regex(&reversed).map_err(|e| e.at(location))?,
+                    original: reversed,
+                    strand: Strand::Reverse,
+                    distance,
+                    window,
+                    variants: reversed_variants,
+                    predicates,
+                    match_quality,
+                    protein,
+                });
+            } else {
+                patterns.push(Pattern {
+                    name: regex_name.to_string(),
+                    original: regex_string.to_string(),
+                    regex,
+                    strand: Strand::Forward,
+                    distance,
+                    window,
+                    variants,
+                    predicates,
+                    match_quality,
+                    protein,
+                });
+            }
+        }
+
+        let combination = regex_set
+            .get("combination")
+            .map(|value| Combination::from_value(value, &patterns))
+            .transpose()
+            .map_err(|e| e.at(ErrorLocation::Field("combination".to_string())))?;
+
+        let mut pattern_set = Self::compile(
+            regex_set["regexSetName"].as_str().map(|s| s.to_string()),
+            format,
+            source,
+            patterns,
+            set_predicates,
+            regex_set["caseInsensitive"].as_bool().unwrap_or(false),
+        )?;
+        pattern_set.output = regex_set["output"].as_str().map(|s| s.to_string());
+        pattern_set.combination = combination;
+        pattern_set.amplicons = parse_amplicons(
+            regex_set,
+            &pattern_set.patterns,
+            pattern_set.case_insensitive,
+        )
+        .map_err(|e| e.at(ErrorLocation::Field("amplicons".to_string())))?;
+        Ok(pattern_set)
+    }
+
+    // Function: from_fasta
+    // Compiles a FASTA file of sequences, such as a catalogue of primers or adapters. The
+    // first word of each header is the regexName, and its sequence (which may span lines,
+    // and is read in upper case) the regexString, so IUPAC codes are converted as usual.
+    // The rest of the header may give key=value tags for the regex entry, e.g.
+    //
+    //   >16S_F maxMismatches=1 reverseComplement=true
+    //
+    // Comment lines starting with ';' before the first header give tags for the regexSet,
+    // e.g. "; regexSetName=Primers minimumSequenceLength=100". Values may be double
+    // quoted; unquoted numbers and booleans are read as such. The document built from the
+    // file is validated as for JSON, and the regexSet is given the default name, if any,
+    // unless named by a tag.
+    pub fn from_fasta(contents: &str, default_name: Option<&str>) -> Result<Self, PatternError> {
+        let json = fasta_document(contents, default_name)?;
+        let mut pattern_set = Self::from_value(&json, PatternFormat::Fasta)?;
+        pattern_set.source = contents.to_string();
+        Ok(pattern_set)
+    }
+
+    // Function: from_lines
+    // Compiles plain-text patterns, one regex pattern per line. Each pattern is its own name.
+    fn from_lines(contents: &str) -> Result<Self, PatternError> {
+        let patterns = contents
+            .lines()
+            .enumerate()
+            .map(|(index, line)| {
+                let regex = convert_iupac_to_regex(line)
+                    .map_err(|e| e.at(ErrorLocation::Line(index + 1)))?;
+                Ok(Pattern {
+                    name: regex.clone(),
+                    original: line.to_string(),
+                    regex,
+                    strand: Strand::Forward,
+                    distance: None,
+                    window: None,
+                    variants: Vec::new(),
+                    predicates: Predicates::default(),
+                    match_quality: MatchQuality::default(),
+                    protein: false,
+                })
+            })
+            .collect::<Result<Vec<_>, PatternError>>()?;
+        Self::compile(
+            None,
+            PatternFormat::Text,
+            contents.to_string(),
+            patterns,
+            Predicates::default(),
+            false,
+        )
+    }
+
+    // Function: compile
+    // Compiles the converted regex of each pattern into a PatternMatcher, matching
+    // lowercase bases too if case_insensitive is set.
+    fn compile(
+        name: Option<String>,
+        format: PatternFormat,
+        source: String,
+        patterns: Vec<Pattern>,
+        predicates: Predicates,
+        case_insensitive: bool,
+    ) -> Result<Self, PatternError> {
+        let regexes: Vec<String> = patterns.iter().map(|p| p.regex.clone()).collect();
+        let distances: Vec<Option<Distance>> = patterns.iter().map(|p| p.distance).collect();
+        let protein: Vec<bool> = patterns.iter().map(|p| p.protein).collect();
+        let matcher =
+            PatternMatcher::with_protein(&regexes, &distances, &protein, case_insensitive)
+                .map_err(|reason| {
+                    // Compile the patterns one at a time to find the one at fault.
+                    patterns
+                        .iter()
+                        .enumerate()
+                        .find_map(|(index, pattern)| {
+                            let error = PatternMatcher::with_protein(
+                                std::slice::from_ref(&pattern.regex),
+                                &[pattern.distance],
+                                &[pattern.protein],
+                                case_insensitive,
+                            )
+                            .err()?;
+                            let location = match format {
+                                PatternFormat::Text => ErrorLocation::Line(index + 1),
+                                _ => ErrorLocation::Regex(pattern.name.clone()),
+                            };
+                            Some(PatternError::new(error).at(location))
+                        })
+                        .unwrap_or_else(|| PatternError::new(reason))
+                })?
+                .with_windows(patterns.iter().map(|p| p.window).collect());
+        let filter = RecordFilter::new(&predicates, None)?;
+        let pattern_filters = if patterns.iter().any(|p| p.predicates != predicates) {
+            Some(
+                patterns
+                    .iter()
+                    .map(|p| RecordFilter::new(&p.predicates, Some(&p.name)))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        } else {
+            None
+        };
+        let checks_match_quality = patterns.iter().any(|p| p.match_quality.is_set());
+        Ok(Self {
+            name,
+            output: None,
+            format,
+            source,
+            patterns,
+            predicates,
+            matcher,
+            filter,
+            pattern_filters,
+            combination: None,
+            amplicons: Vec::new(),
+            checks_match_quality,
+            case_insensitive,
+        })
+    }
+
+    // Returns the regexSetName, if given.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Returns the file the records matching the set are written to, if given.
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    // Returns true if the patterns also match lowercase (soft-masked) bases, which then
+    // count towards the quality statistics of the records.
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    // Returns the format the patterns were read from.
+    pub fn format(&self) -> PatternFormat {
+        self.format
+    }
+
+    // Returns the text the patterns were read from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Returns the patterns, in the order in which they were compiled.
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    // Returns the amplicons, in the order in which they were given.
+    pub fn amplicons(&self) -> &[Amplicon] {
+        &self.amplicons
+    }
+
+    // Returns the set-wide predicates.
+    pub fn predicates(&self) -> &Predicates {
+        &self.predicates
+    }
+
+    // Returns the compiled patterns.
+    pub fn matcher(&self) -> &PatternMatcher {
+        &self.matcher
+    }
+
+    // Function: passes_predicates
+    // Returns true if a record (given by its header, sequence and quality) passes the
+    // set-wide predicates.
+    #[inline(always)]
+    pub fn passes_predicates(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> bool {
+        self.filter.passes(head, seq, qual)
+    }
+
+    // Function: is_match
+    // Returns true if any pattern is found in a record: the pattern matches the sequence
+    // and the record passes the predicates of the pattern.
+    #[inline(always)]
+    pub fn is_match(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> bool {
+        match (&self.pattern_filters, &self.combination) {
+            (None, None) if !self.checks_match_quality && self.amplicons.is_empty() => {
+                self.filter.passes(head, seq, qual) && self.matcher.is_match(seq)
+            }
+            _ => self.found(head, seq, qual).is_some(),
+        }
+    }
+
+    // Function: matches
+    // Returns the indices of the patterns found in a record, as for is_match. When the
+    // set gives a combination, no patterns are returned for a record that does not
+    // satisfy it.
+    pub fn matches(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> Vec<usize> {
+        self.found(head, seq, qual).unwrap_or_default()
+    }
+
+    // Function: found
+    // Returns the indices of the patterns found in a record, or None if the record is not
+    // kept: no pattern is found or, with a combination, the combination does not hold.
+    // A combination that holds without any pattern found (e.g. a lone "not") keeps a
+    // record only if it passes the set-wide predicates. When the set gives amplicons, a
+    // record is kept only if it passes the set-wide predicates and contains one of them.
+    fn found(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> Option<Vec<usize>> {
+        let mut found = self.matching(head, seq, qual)?;
+        if self.checks_match_quality {
+            found.retain(|&i| self.accepts(i, seq, qual));
+        }
+        let kept = match &self.combination {
+            None => !found.is_empty() || !self.amplicons.is_empty(),
+            Some(combination) => {
+                combination.evaluate(&found)
+                    && (!found.is_empty() || self.filter.passes(head, seq, qual))
+            }
+        };
+        let kept = kept
+            && (self.amplicons.is_empty()
+                || self.filter.passes(head, seq, qual)
+                    && self.amplicons.iter().any(|amplicon| {
+                        amplicon.find(seq, qual, self.quality_encoding()).is_some()
+                    }));
+        kept.then_some(found)
+    }
+
+    // Function: find_amplicons
+    // Returns the index and location of each amplicon found in a record (given by its
+    // sequence and quality).
+    pub fn find_amplicons(&self, seq: &[u8], qual: &[u8]) -> Vec<(usize, AmpliconHit)> {
+        let quality_encoding = self.quality_encoding();
+        self.amplicons
+            .iter()
+            .enumerate()
+            .filter_map(|(index, amplicon)| {
+                Some((index, amplicon.find(seq, qual, quality_encoding)?))
+            })
+            .collect()
+    }
+
+    // Function: matching
+    // Returns the indices of the patterns matching the sequence of a record that passes
+    // their predicates, or None if the record fails the set-wide predicates (when no
+    // regex overrides them).
+    fn matching(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> Option<Vec<usize>> {
+        match &self.pattern_filters {
+            None if !self.filter.passes(head, seq, qual) => None,
+            None => Some(self.matcher.matches(seq)),
+            Some(filters) => Some(
+                self.matcher
+                    .matches(seq)
+                    .into_iter()
+                    .filter(|&i| filters[i].passes(head, seq, qual))
+                    .collect(),
+            ),
+        }
+    }
+
+    // Function: find_iter
+    // Returns the hits of the pattern at the given index that meet its match quality.
+    pub fn find_iter(&self, index: usize, seq: &[u8], qual: &[u8]) -> Vec<Hit> {
+        let mut hits = self.matcher.find_iter(index, seq);
+        let match_quality = &self.patterns[index].match_quality;
+        if match_quality.is_set() {
+            hits.retain(|hit| {
+                match_quality.accepts(&qual[hit.start..hit.end], self.quality_encoding())
+            });
+        }
+        hits
+    }
+
+    // Function: find
+    // Returns the first hit of the pattern at the given index that meets its match quality.
+    pub fn find(&self, index: usize, seq: &[u8], qual: &[u8]) -> Option<Hit> {
+        if self.patterns[index].match_quality.is_set() {
+            self.find_iter(index, seq, qual).into_iter().next()
+        } else {
+            self.matcher.find(index, seq)
+        }
+    }
+
+    // Function: rejected
+    // Returns the indices of the patterns that match a record passing their predicates,
+    // but only with hits whose bases fall short of the pattern's match quality.
+    pub fn rejected(&self, head: &[u8], seq: &[u8], qual: &[u8]) -> Vec<usize> {
+        if !self.checks_match_quality {
+            return Vec::new();
+        }
+        let mut rejected = self.matching(head, seq, qual).unwrap_or_default();
+        rejected.retain(|&i| !self.accepts(i, seq, qual));
+        rejected
+    }
+
+    // Returns true if the pattern at the given index has a hit meeting its match quality.
+    fn accepts(&self, index: usize, seq: &[u8], qual: &[u8]) -> bool {
+        !self.patterns[index].match_quality.is_set() || !self.find_iter(index, seq, qual).is_empty()
+    }
+
+    // Returns the quality encoding of the set, Phred+33 if not given.
+    fn quality_encoding(&self) -> &str {
+        self.predicates
+            .quality_encoding
+            .as_deref()
+            .unwrap_or("Phred+33")
+    }
+
+    // Returns true if any pattern matches the reverse strand.
+    pub fn has_reverse_complements(&self) -> bool {
+        self.patterns.iter().any(|p| p.strand == Strand::Reverse)
+    }
+
+    // Returns true if the pattern at the given index is one of a forward and reverse
+    // complement pair. The reverse complement always follows its forward pattern.
+    pub fn is_stranded(&self, index: usize) -> bool {
+        self.patterns[index].strand == Strand::Reverse
+            || self
+                .patterns
+                .get(index + 1)
+                .is_some_and(|next| next.strand == Strand::Reverse)
+    }
+}
+
+// Function: reverse_complement_enabled
+// Determines whether the reverse complement of a regex should also be matched. A
+// per-regex reverseComplement setting overrides the set-wide setting.
+fn reverse_complement_enabled(regex_set: &Value, regex: &Value) -> bool {
+    regex["reverseComplement"]
+        .as_bool()
+        .or_else(|| regex_set["reverseComplement"].as_bool())
+        .unwrap_or(false)
+}
+
+// Function: protein_enabled
+// Determines whether a regex is a protein pattern. A per-regex patternType overrides the
+// set-wide patternType; patterns are nucleotide patterns by default.
+fn protein_enabled(regex_set: &Value, regex: &Value) -> bool {
+    regex["patternType"]
+        .as_str()
+        .or_else(|| regex_set["patternType"].as_str())
+        == Some("protein")
+}
+
+// Function: fasta_document
+// Builds the patterns document given by a FASTA file (see PatternSet::from_fasta).
+fn fasta_document(contents: &str, default_name: Option<&str>) -> Result<Value, PatternError> {
+    let mut regex_set = serde_json::Map::new();
+    if let Some(name) = default_name {
+        regex_set.insert("regexSetName".to_string(), Value::from(name));
+    }
+    let mut regexes: Vec<serde_json::Map<String, Value>> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let location = ErrorLocation::Line(index + 1);
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('>') {
+            let mut words = header.splitn(2, char::is_whitespace);
+            let name = words.next().unwrap_or("");
+            if name.is_empty() {
+                return Err(PatternError::new("Missing regex name in FASTA header").at(location));
+            }
+            let mut regex = parse_fasta_tags(words.next().unwrap_or(""), FASTA_REGEX_TAGS)
+                .map_err(|e| e.at(location))?;
+            regex.insert("regexName".to_string(), Value::from(name));
+            regex.insert("regexString".to_string(), Value::from(""));
+            regexes.push(regex);
+        } else if let Some(comment) = line.strip_prefix(';') {
+            if regexes.is_empty() {
+                let tags = parse_fasta_tags(comment, FASTA_SET_TAGS).map_err(|e| e.at(location))?;
+                regex_set.extend(tags);
+            }
+        } else if !line.is_empty() {
+            let regex = regexes.last_mut().ok_or_else(|| {
+                PatternError::new("Sequence given before the first FASTA header").at(location)
+            })?;
+            if let Some(Value::String(sequence)) = regex.get_mut("regexString") {
+                sequence.push_str(&line.to_ascii_uppercase());
+            }
+        }
+    }
+    if let Some(empty) = regexes.iter().find(|r| r["regexString"] == "") {
+        let name = empty["regexName"].as_str().unwrap_or_default().to_string();
+        return Err(PatternError::new("Missing sequence").at(ErrorLocation::Regex(name)));
+    }
+    regex_set.insert(
+        "regex".to_string(),
+        Value::Array(regexes.into_iter().map(Value::Object).collect()),
+    );
+    Ok(serde_json::json!({ "regexSet": regex_set }))
+}
+
+// Function: parse_document
+// Parses a JSON or YAML patterns document.
+pub fn parse_document(contents: &str, format: PatternFormat) -> Result<Value, PatternError> {
+    match format {
+        PatternFormat::Yaml => serde_yaml_ng::from_str(contents)
+            .map_err(|e| format!("Failed to parse YAML file: {}", e).into()),
+        _ => serde_json::from_str(contents)
+            .map_err(|e| format!("Failed to parse JSON file: {}", e).into()),
+    }
+}
+
+// Function: resolve_includes
+// Replaces the include list of each regexSet of a patterns document with the regex
+// entries of the files it names, found relative to base_dir, after the set's own
+// entries. An included regex may not share the name of a regex already in the set. The
+// chain holds the files being included, so that a file including itself, directly or
+// not, is reported. Returns true if any file was included.
+fn resolve_includes(
+    json: &mut Value,
+    base_dir: &Path,
+    chain: &mut Vec<PathBuf>,
+) -> Result<bool, PatternError> {
+    let regex_sets: Vec<&mut Value> = match json.get_mut("regexSets") {
+        Some(Value::Array(regex_sets)) => regex_sets.iter_mut().collect(),
+        _ => json.get_mut("regexSet").into_iter().collect(),
+    };
+    let mut included = false;
+    for regex_set in regex_sets {
+        let Some(include) = regex_set
+            .as_object_mut()
+            .and_then(|regex_set| regex_set.remove("include"))
+        else {
+            continue;
+        };
+        let location = ErrorLocation::Field("include".to_string());
+        let paths = include
+            .as_array()
+            .and_then(|paths| paths.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| {
+                PatternError::new("include must be a list of file paths").at(location.clone())
+            })?;
+        let mut regexes = regex_set["regex"].as_array().cloned().unwrap_or_default();
+        for path in paths {
+            let path = base_dir.join(path);
+            for regex in include_regexes(&path, chain).map_err(|e| e.at(location.clone()))? {
+                if regexes.iter().any(|r| r["regexName"] == regex["regexName"]) {
+                    return Err(PatternError::new(format!(
+                        "Regex {} included from {} has the same name as another regex",
+                        regex["regexName"],
+                        path.display()
+                    ))
+                    .at(location));
+                }
+                regexes.push(regex);
+            }
+        }
+        regex_set["regex"] = Value::Array(regexes);
+        included = true;
+    }
+    Ok(included)
+}
+
+// Function: include_regexes
+// Reads the regex entries of each regexSet of an included patterns file, which may be
+// JSON, YAML, FASTA or plain text, with its own includes resolved.
+fn include_regexes(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Vec<Value>, PatternError> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format!("Failed to open included file {}: {}", path.display(), e))?;
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(format!("Include cycle: {}", cycle.join(" -> ")).into());
+    }
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to open included file {}: {}", path.display(), e))?;
+    let mut json = match PatternFormat::detect(&contents) {
+        // Plain-text patterns are each named after themselves.
+        PatternFormat::Text => {
+            let regexes: Vec<Value> = PatternSet::from_str(&contents, PatternFormat::Text)?
+                .patterns()
+                .iter()
+                .filter(|pattern| !pattern.original.is_empty())
+                .map(|pattern| {
+                    serde_json::json!({
+                        "regexName": pattern.original,
+                        "regexString": pattern.original
+                    })
+                })
+                .collect();
+            return Ok(regexes);
+        }
+        PatternFormat::Fasta => fasta_document(&contents, None)?,
+        // Regexes included from a file of an older version are migrated.
+        format => schema::upgrade(&parse_document(&contents, format)?)?
+            .1
+            .into_owned(),
+    };
+    chain.push(canonical);
+    resolve_includes(&mut json, path.parent().unwrap_or(Path::new("")), chain)?;
+    chain.pop();
+    let regex_sets = match &json["regexSets"] {
+        Value::Array(regex_sets) => regex_sets.iter().collect(),
+        _ => vec![&json["regexSet"]],
+    };
+    Ok(regex_sets
+        .into_iter()
+        .flat_map(|regex_set| regex_set["regex"].as_array().cloned().unwrap_or_default())
+        .collect())
+}
+
+// Function: read_patterns_file
+// Reads a patterns file, detecting its format from its content unless given.
+pub fn read_patterns_file(
+    patterns_path: &str,
+    format: Option<PatternFormat>,
+) -> Result<(String, PatternFormat), PatternError> {
+    let contents = std::fs::read_to_string(patterns_path)
+        .map_err(|e| format!("Failed to open patterns file: {}", e))?;
+    let format = format.unwrap_or_else(|| PatternFormat::detect(&contents));
+    Ok((contents, format))
+}
+
+// Function: file_stem
+// Returns the name of a patterns file without its extension, naming a FASTA regex set
+// that does not give a regexSetName.
+fn file_stem(patterns_path: &Path) -> Option<String> {
+    patterns_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+}
+
+// Function: validate_document
+// Validates a JSON (or YAML) patterns document against the given schema, reporting the
+// first schema violation, located at the regex entry or field at fault.
+pub fn validate_document(json: &Value, schema: &str) -> Result<(), PatternError> {
+    let schema: Value = serde_json::from_str(schema)
+        .map_err(|e| format!("Failed to parse embedded schema: {}", e))?;
+
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| format!("Failed to compile schema: {}", e))?;
+
+    let errors: Vec<_> = validator.iter_errors(json).collect();
+    if let Some(error) = errors.first() {
+        let mut reason = format!("Pattern file validation error: {}", error);
+        if errors.len() > 1 {
+            reason.push_str(&format!(" (and {} more)", errors.len() - 1));
+        }
+        let mut pattern_error = PatternError::new(reason);
+        if let Some(location) = schema_error_location(json, &error.instance_path().to_string()) {
+            pattern_error = pattern_error.at(location);
+        }
+        return Err(pattern_error);
+    }
+    Ok(())
+}
+
+// Function: schema_error_location
+// Locates a schema violation from its JSON pointer: a regex entry by its regexName
+// (or position, when unnamed), otherwise the regexSet field at fault.
+fn schema_error_location(json: &Value, instance_path: &str) -> Option<ErrorLocation> {
+    let mut parts = instance_path.trim_start_matches('/').split('/');
+    let regex_set = match parts.next()? {
+        "regexSet" => &json["regexSet"],
+        "regexSets" => &json["regexSets"][parts.next()?.parse::<usize>().ok()?],
+        _ => return None,
+    };
+    match parts.next()? {
+        "regex" => {
+            let index = parts.next()?;
+            let name = regex_set["regex"][index.parse::<usize>().ok()?]["regexName"]
+                .as_str()
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("#{}", index));
+            Some(ErrorLocation::Regex(name))
+        }
+        field => Some(ErrorLocation::Field(field.to_string())),
+    }
+}
+
+// Function: parse_distance
+// Reads the optional maxMismatches or maxEdits setting of a regex entry.
+fn parse_distance(regex: &Value) -> Result<Option<Distance>, PatternError> {
+    let max_mismatches = regex["maxMismatches"].as_u64();
+    let max_edits = regex["maxEdits"].as_u64();
+    match (max_mismatches, max_edits) {
+        (Some(_), Some(_)) => Err(PatternError::new(
+            "Cannot set both maxMismatches and maxEdits",
+        )),
+        (Some(k), None) => Ok(Some(Distance::Mismatches(k as usize))),
+        (None, Some(k)) => Ok(Some(Distance::Edits(k as usize))),
+        (None, None) => Ok(None),
+    }
+}
+
+// The tags that may be given in the header of a FASTA record, for its regex entry.
+const FASTA_REGEX_TAGS: &[&str] = &[
+    "reverseComplement",
+    "patternType",
+    "maxMismatches",
+    "maxEdits",
+    "headerRegex",
+    "minimumSequenceLength",
+    "minimumAverageQuality",
+    "minimumMatchQuality",
+    "minimumMatchAverageQuality",
+];
+
+// The tags that may be given in the comment lines of a FASTA file, for the regexSet.
+const FASTA_SET_TAGS: &[&str] = &[
+    "regexSetName",
+    "reverseComplement",
+    "patternType",
+    "headerRegex",
+    "minimumSequenceLength",
+    "minimumAverageQuality",
+    "qualityEncoding",
+    "headerFormat",
+    "caseInsensitive",
+    "minimumMatchQuality",
+    "minimumMatchAverageQuality",
+];
+
+// Function: parse_fasta_tags
+// Reads the whitespace-separated key=value tags of a FASTA header or comment, allowing
+// only the given keys. A double-quoted value is a string, and may contain whitespace;
+// otherwise a value that reads as a number or boolean is one.
+fn parse_fasta_tags(
+    text: &str,
+    allowed: &[&str],
+) -> Result<serde_json::Map<String, Value>, PatternError> {
+    let mut tags = serde_json::Map::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let (key, after) = rest
+            .split_once('=')
+            .filter(|(key, _)| !key.contains(char::is_whitespace))
+            .ok_or_else(|| {
+                let word = rest.split_whitespace().next().unwrap_or(rest);
+                PatternError::new(format!("Expected a key=value tag, found \"{}\"", word))
+            })?;
+        if !allowed.contains(&key) {
+            return Err(format!("Unknown tag \"{}\"", key).into());
+        }
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted
+                .find('"')
+                .ok_or_else(|| format!("Unterminated value of tag \"{}\"", key))?;
+            (Value::from(&quoted[..end]), &quoted[end + 1..])
+        } else {
+            let end = after.find(char::is_whitespace).unwrap_or(after.len());
+            let word = &after[..end];
+            let value = if let Ok(number) = word.parse::<u64>() {
+                Value::from(number)
+            } else if let Ok(number) = word.parse::<f64>() {
+                Value::from(number)
+            } else if let Ok(boolean) = word.parse::<bool>() {
+                Value::from(boolean)
+            } else {
+                Value::from(word)
+            };
+            (value, &after[end..])
+        };
+        tags.insert(key.to_string(), value);
+        rest = after.trim_start();
+    }
+    Ok(tags)
+}
+
+// Function: parse_window
+// Reads the window a regex must match within: its first or last N bases, or an explicit
+// start and end position.
+fn parse_window(regex: &Value) -> Result<Option<Window>, PatternError> {
+    let window = &regex["window"];
+    if window.is_null() {
+        return Ok(None);
+    }
+    let first = window["first"].as_u64().map(|n| n as usize);
+    let last = window["last"].as_u64().map(|n| n as usize);
+    let start = window["start"].as_u64().map(|n| n as usize);
+    let end = window["end"].as_u64().map(|n| n as usize);
+    match (first, last, start, end) {
+        (Some(n), None, None, None) => Ok(Some(Window::First(n))),
+        (None, Some(n), None, None) => Ok(Some(Window::Last(n))),
+        (None, None, Some(start), Some(end)) if start >= end => Err(format!(
+            "Window start ({}) must be less than its end ({})",
+            start, end
+        )
+        .into()),
+        (None, None, start, end) if start.is_some() || end.is_some() => Ok(Some(Window::Range {
+            start: start.unwrap_or(0),
+            end,
+        })),
+        _ => Err("Window must give one of first, last, or start and end".into()),
+    }
+}
+
+// Function: parse_amplicons
+// Reads the amplicons of a regexSet, resolving the regexName of each primer to its
+// forward pattern, which must be a nucleotide pattern. Amplicons must be named
+// uniquely, and the minimum insert may not exceed the maximum.
+fn parse_amplicons(
+    regex_set: &Value,
+    patterns: &[Pattern],
+    case_insensitive: bool,
+) -> Result<Vec<Amplicon>, PatternError> {
+    let primer = |name: &str| {
+        patterns
+            .iter()
+            .find(|pattern| pattern.name == name && pattern.strand == Strand::Forward)
+            .ok_or_else(|| {
+                PatternError::new(format!("Amplicon refers to unknown regex \"{}\"", name))
+            })
+            .and_then(|pattern| {
+                if pattern.protein {
+                    Err(format!("Amplicon primer \"{}\" is a protein pattern", name).into())
+                } else {
+                    Ok(pattern)
+                }
+            })
+    };
+    let mut amplicons: Vec<Amplicon> = Vec::new();
+    for amplicon in regex_set["amplicons"].as_array().into_iter().flatten() {
+        let name = amplicon["ampliconName"].as_str().unwrap_or_default();
+        if amplicons.iter().any(|a| a.name == name) {
+            return Err(format!("Duplicate ampliconName \"{}\"", name).into());
+        }
+        let minimum = amplicon["minimumInsert"].as_u64().unwrap_or(0) as usize;
+        let maximum = amplicon["maximumInsert"].as_u64().map(|max| max as usize);
+        if maximum.is_some_and(|max| max < minimum) {
+            return Err(format!(
+                "Amplicon \"{}\" has a minimumInsert greater than its maximumInsert",
+                name
+            )
+            .into());
+        }
+        amplicons.push(Amplicon::new(
+            name,
+            primer(amplicon["forward"].as_str().unwrap_or_default())?,
+            primer(amplicon["reverse"].as_str().unwrap_or_default())?,
+            (minimum, maximum),
+            case_insensitive,
+        )?);
+    }
+    Ok(amplicons)
+}
+
+// Function: parse_variants
+// Reads the variants of a regex entry, with additional DNA (or, for a protein pattern,
+// amino acid) validation.
+fn parse_variants(regex: &Value, protein: bool) -> Result<Vec<Variant>, PatternError> {
+    regex["variants"]
+        .as_array()
+        .map(|variants| variants.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|variant| {
+            let name = variant["variantName"]
+                .as_str()
+                .ok_or("Invalid variantName")?
+                .to_string();
+            let sequence = variant["variantString"]
+                .as_str()
+                .ok_or("Invalid variantString")?
+                .to_string();
+            if protein {
+                validate_protein_sequence(&sequence)?;
+            } else {
+                validate_dna_sequence(&sequence)?;
+            }
+            Ok(Variant { name, sequence })
+        })
+        .collect()
+}
+
+// Function: validate_dna_sequence
+// Validates that a given sequence contains only valid DNA nucleotides.
+fn validate_dna_sequence(sequence: &str) -> Result<(), PatternError> {
+    match sequence.chars().position(|c| !"ACTG".contains(c)) {
+        None => Ok(()),
+        Some(offset) => Err(
+            PatternError::new(format!("Invalid DNA sequence: {}", sequence)).with_offset(offset),
+        ),
+    }
+}
+
+// Function: validate_protein_sequence
+// Validates that a given sequence contains only amino acids, in the one-letter code of
+// the translation ('*' for a stop codon, 'X' for an unknown amino acid).
+fn validate_protein_sequence(sequence: &str) -> Result<(), PatternError> {
+    match sequence
+        .chars()
+        .position(|c| !"ACDEFGHIKLMNPQRSTVWY*X".contains(c))
+    {
+        None => Ok(()),
+        Some(offset) => Err(
+            PatternError::new(format!("Invalid protein sequence: {}", sequence))
+                .with_offset(offset),
+        ),
+    }
+}
@@ -21,21 +21,28 @@
 // SOFTWARE.
 
 use serde::Serialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 // Include the generated canonical k-mers lookup table
 include!(concat!(env!("OUT_DIR"), "/canonical_kmers.rs"));
 
-// Function: average_quality
-// Calculates the average quality score for a sequence using the specified quality encoding.
-pub fn average_quality(quality: &[u8], quality_encoding: &str) -> f32 {
-    // Determine the offset based on the quality encoding.
+// Function: quality_offset
+// Returns the ASCII offset of the specified quality encoding.
+pub fn quality_offset(quality_encoding: &str) -> i32 {
     // Phred+33: ASCII offset 33, Phred+64: ASCII offset 64.
-    let offset = match quality_encoding {
+    match quality_encoding {
         "Phred+33" => 33,
         "Phred+64" => 64,
         _ => 33, // Default to Phred+33 if unknown encoding
-    };
+    }
+}
+
+// Function: average_quality
+// Calculates the average quality score for a sequence using the specified quality encoding.
+pub fn average_quality(quality: &[u8], quality_encoding: &str) -> f32 {
+    // Determine the offset based on the quality encoding.
+    let offset = quality_offset(quality_encoding);
 
     // Initialize sum and count.
     let mut sum = 0;
@@ -54,6 +61,18 @@ pub fn average_quality(quality: &[u8], quality_encoding: &str) -> f32 {
     }
 }
 
+// Function: unmask
+// Returns the sequence with any soft-masked (lowercase) bases in upper case, so that they
+// count towards the GC content and tetranucleotide frequencies, which only count
+// uppercase bases. The sequence is only copied if it has lowercase bases.
+pub fn unmask(sequence: &[u8]) -> Cow<'_, [u8]> {
+    if sequence.iter().any(u8::is_ascii_lowercase) {
+        Cow::Owned(sequence.to_ascii_uppercase())
+    } else {
+        Cow::Borrowed(sequence)
+    }
+}
+
 /// Function: gc_content
 /// Calculates the GC content percentage of a DNA sequence.
 /// Only counts unambiguous bases (A, C, T, G); ambiguous bases are ignored.
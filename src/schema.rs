@@ -0,0 +1,333 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module versions the schema of JSON and YAML patterns files. A patterns file may
+// declare the version of the schema it was written for with a top-level schemaVersion;
+// one that does not is taken to be of the newest version it validates against. The
+// versions are:
+//   1: variants are given as plain sequences;
+//   2: variants are named, with variantName and variantString;
+//   3: the current schema (see initialise::SCHEMA), adding schemaVersion and the
+//      optional fields introduced since, such as regexSets, include and caseInsensitive.
+// Files of an older version are validated against the schema of their version and
+// migrated to the current schema, so they continue to work unchanged.
+
+use std::borrow::Cow;
+
+use serde_json::Value;
+
+use crate::arg::Cli;
+use crate::initialise::SCHEMA;
+use crate::patterns::{
+    parse_document, read_patterns_file, validate_document, ErrorLocation, PatternError,
+    PatternFormat,
+};
+
+// The version of the current schema.
+pub const SCHEMA_VERSION: u64 = 3;
+
+// The schema of version 1 patterns files.
+static SCHEMA_V1: &str = r#"
+{
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": "grepq",
+    "version": 1,
+    "type": "object",
+    "properties": {
+        "regexSet": {
+            "type": "object",
+            "properties": {
+                "regexSetName": {
+                    "type": "string"
+                },
+                "regex": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "regexName": {
+                                "type": "string"
+                            },
+                            "regexString": {
+                                "type": "string"
+                            },
+                            "variants": {
+                                "type": "array",
+                                "items": {
+                                    "type": "string"
+                                }
+                            }
+                        },
+                        "required": [
+                            "regexName",
+                            "regexString"
+                        ]
+                    }
+                },
+                "headerRegex": {
+                    "type": "string"
+                },
+                "minimumSequenceLength": {
+                    "type": "number"
+                },
+                "minimumAverageQuality": {
+                    "type": "number"
+                },
+                "qualityEncoding": {
+                    "type": "string"
+                }
+            },
+            "required": [
+                "regexSetName",
+                "regex"
+            ]
+        }
+    },
+    "required": [
+        "regexSet"
+    ]
+}
+"#;
+
+// The schema of version 2 patterns files.
+static SCHEMA_V2: &str = r#"
+{
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": "grepq",
+    "version": 2,
+    "type": "object",
+    "properties": {
+        "regexSet": {
+            "type": "object",
+            "properties": {
+                "regexSetName": {
+                    "type": "string"
+                },
+                "regex": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "regexName": {
+                                "type": "string"
+                            },
+                            "regexString": {
+                                "type": "string"
+                            },
+                            "variants": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "variantName": {
+                                            "type": "string"
+                                        },
+                                        "variantString": {
+                                            "type": "string"
+                                        }
+                                    },
+                                    "required": [
+                                        "variantName",
+                                        "variantString"
+                                    ]
+                                }
+                            }
+                        },
+                        "required": [
+                            "regexName",
+                            "regexString"
+                        ]
+                    }
+                },
+                "headerRegex": {
+                    "type": "string"
+                },
+                "minimumSequenceLength": {
+                    "type": "number"
+                },
+                "minimumAverageQuality": {
+                    "type": "number"
+                },
+                "qualityEncoding": {
+                    "type": "string"
+                }
+            },
+            "required": [
+                "regexSetName",
+                "regex"
+            ]
+        }
+    },
+    "required": [
+        "regexSet"
+    ]
+}
+"#;
+
+// Function: schema
+// Returns the schema of the given version, if there is one.
+pub fn schema(version: u64) -> Option<&'static str> {
+    match version {
+        1 => Some(SCHEMA_V1),
+        2 => Some(SCHEMA_V2),
+        SCHEMA_VERSION => Some(SCHEMA),
+        _ => None,
+    }
+}
+
+// Function: document_version
+// Returns the schema version of a patterns document: the version it declares or, if it
+// declares none, the newest version it validates against (the current version if none).
+fn document_version(json: &Value) -> Result<u64, PatternError> {
+    match json.get("schemaVersion") {
+        Some(declared) => declared
+            .as_u64()
+            .filter(|version| schema(*version).is_some())
+            .ok_or_else(|| {
+                PatternError::new(format!(
+                    "Unsupported schemaVersion {}: expected 1 to {}",
+                    declared, SCHEMA_VERSION
+                ))
+                .at(ErrorLocation::Field("schemaVersion".to_string()))
+            }),
+        None => Ok((1..=SCHEMA_VERSION)
+            .rev()
+            .find(|version| {
+                schema(*version).is_some_and(|schema| validate_document(json, schema).is_ok())
+            })
+            .unwrap_or(SCHEMA_VERSION)),
+    }
+}
+
+// Function: upgrade
+// Validates a patterns document against the schema of its version and migrates it to
+// the current schema, returning its version and the migrated document.
+pub fn upgrade(json: &Value) -> Result<(u64, Cow<'_, Value>), PatternError> {
+    let version = document_version(json)?;
+    validate_document(json, schema(version).unwrap_or(SCHEMA))?;
+    if version == SCHEMA_VERSION {
+        return Ok((version, Cow::Borrowed(json)));
+    }
+
+    let mut migrated = json.clone();
+    if version < 2 {
+        // Version 1 variants are plain sequences, named after themselves.
+        let regexes = migrated
+            .get_mut("regexSet")
+            .and_then(|set| set.get_mut("regex"))
+            .and_then(Value::as_array_mut);
+        for variants in regexes
+            .into_iter()
+            .flatten()
+            .filter_map(|regex| regex.get_mut("variants").and_then(Value::as_array_mut))
+        {
+            for variant in variants.iter_mut() {
+                if let Value::String(sequence) = variant {
+                    *variant = serde_json::json!({
+                        "variantName": sequence,
+                        "variantString": sequence,
+                    });
+                }
+            }
+        }
+    }
+    if let Value::Object(fields) = &mut migrated {
+        fields.insert("schemaVersion".to_string(), Value::from(SCHEMA_VERSION));
+    }
+    validate_document(&migrated, SCHEMA)?;
+    Ok((version, Cow::Owned(migrated)))
+}
+
+// Function: migrate_str
+// Migrates the contents of a JSON or YAML patterns file to the current schema, returning
+// the version it was written for and the migrated contents, in the same format, with
+// schemaVersion set.
+pub fn migrate_str(contents: &str, format: PatternFormat) -> Result<(u64, String), PatternError> {
+    if matches!(format, PatternFormat::Text | PatternFormat::Fasta) {
+        return Err("Only JSON and YAML patterns files have a schema to migrate".into());
+    }
+    let json = parse_document(contents, format)?;
+    let (version, migrated) = upgrade(&json)?;
+    let mut migrated = migrated.into_owned();
+    if let Value::Object(fields) = &mut migrated {
+        fields.insert("schemaVersion".to_string(), Value::from(SCHEMA_VERSION));
+    }
+    let migrated = match format {
+        PatternFormat::Yaml => serde_yaml_ng::to_string(&migrated)
+            .map_err(|e| format!("Failed to write YAML: {}", e))?,
+        _ => {
+            serde_json::to_string_pretty(&migrated)
+                .map_err(|e| format!("Failed to write JSON: {}", e))?
+                + "\n"
+        }
+    };
+    Ok((version, migrated))
+}
+
+// Function: run_migrate_patterns
+// Migrates the patterns file given on the command line to the current schema, printing
+// the migrated file or, with --in-place, rewriting the file.
+pub fn run_migrate_patterns(cli: &Cli, in_place: bool) {
+    let migrated = read_patterns_file(cli.patterns(), cli.patterns_format)
+        .and_then(|(contents, format)| migrate_str(&contents, format));
+    let (version, migrated) = match migrated {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if !in_place {
+        print!("{}", migrated);
+        return;
+    }
+    if let Err(e) = std::fs::write(cli.patterns(), migrated) {
+        eprintln!("Error: Failed to write patterns file: {}", e);
+        std::process::exit(1);
+    }
+    eprintln!(
+        "Migrated {} from schema version {} to {}",
+        cli.patterns(),
+        version,
+        SCHEMA_VERSION
+    );
+}
+
+// Function: run_schema
+// Prints the schema of the given version of patterns files, or of the current version.
+// The current schema is the one embedded in grepq, so examples/grepq-schema.json can be
+// regenerated from it.
+pub fn run_schema(version: Option<u64>) {
+    let version = version.unwrap_or(SCHEMA_VERSION);
+    match schema(version) {
+        Some(schema) => println!("{}", schema.trim()),
+        None => {
+            eprintln!(
+                "Error: Unknown schema version {}: expected 1 to {}",
+                version, SCHEMA_VERSION
+            );
+            std::process::exit(1);
+        }
+    }
+}
@@ -25,248 +25,382 @@
 // collects match statistics, writes to SQL if enabled, and prints summary output.
 
 use crate::arg::Cli;
-use crate::initialise::{create_reader, parse_patterns_file};
+use crate::initialise::{create_file_reader, input_paths, load_pattern_sets};
 use crate::output;
-use crate::quality;
-use regex::bytes::Regex;
+use crate::patterns::PatternSet;
+use crate::translate;
 use seq_io::fastq::Record;
-use serde_json::{self, json};
-use std::collections::HashMap;
+use serde_json::{self, json, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{self};
 
-pub fn run_summarise(cli: &Cli, include_count: bool) -> io::Result<()> {
-    let patterns_path = &cli.patterns;
+// Struct: SetSummary
+// The match statistics of a regex set, indexed by pattern.
+#[derive(Clone)]
+struct SetSummary {
+    // Records in which each pattern was found.
+    match_counts: Vec<usize>,
+    // Sub-matches are stored with their count and their distance from the pattern.
+    match_strings: Vec<HashMap<String, (usize, usize)>>,
+    // Records in which a pattern matched only with hits rejected for low match quality.
+    rejected_counts: Vec<usize>,
+    // Records in which a protein pattern was found, by the frame of its first hit.
+    frame_counts: Vec<BTreeMap<i8, usize>>,
+    // The insert lengths of the records in which each amplicon was found.
+    amplicon_inserts: Vec<InsertLengths>,
+}
 
-    // Parse the patterns file and extract settings.
-    let (regex_set, header_regex, minimum_sequence_length, minimum_quality, quality_encoding, _, _) =
-        parse_patterns_file(patterns_path).map_err(|e| io::Error::other(e.to_string()))?;
+impl SetSummary {
+    fn new(num_patterns: usize, num_amplicons: usize) -> Self {
+        Self {
+            match_counts: vec![0; num_patterns],
+            match_strings: vec![HashMap::new(); num_patterns],
+            rejected_counts: vec![0; num_patterns],
+            frame_counts: vec![BTreeMap::new(); num_patterns],
+            amplicon_inserts: vec![InsertLengths::default(); num_amplicons],
+        }
+    }
 
-    // Compile header regex if provided.
-    let header_regex = header_regex.map(|re| Regex::new(&re).unwrap());
+    // Adds the statistics of another summary of the same regex set, e.g. of another file.
+    fn merge(&mut self, other: &SetSummary) {
+        for (count, other) in self.match_counts.iter_mut().zip(&other.match_counts) {
+            *count += other;
+        }
+        for (strings, other) in self.match_strings.iter_mut().zip(&other.match_strings) {
+            for (string, &(count, distance)) in other {
+                strings.entry(string.clone()).or_insert((0, distance)).0 += count;
+            }
+        }
+        for (count, other) in self.rejected_counts.iter_mut().zip(&other.rejected_counts) {
+            *count += other;
+        }
+        for (frames, other) in self.frame_counts.iter_mut().zip(&other.frame_counts) {
+            for (&frame, count) in other {
+                *frames.entry(frame).or_insert(0) += count;
+            }
+        }
+        for (inserts, other) in self
+            .amplicon_inserts
+            .iter_mut()
+            .zip(&other.amplicon_inserts)
+        {
+            inserts.merge(other);
+        }
+    }
+}
 
-    // Create a reader to stream input FASTQ records.
-    let mut reader = create_reader(cli);
+// Struct: InsertLengths
+// The number of records in which an amplicon was found, and the range and total of the
+// lengths of its inserts.
+#[derive(Clone, Default)]
+struct InsertLengths {
+    count: usize,
+    minimum: usize,
+    maximum: usize,
+    total: usize,
+}
 
-    // Initialize counters to store match counts and sub-match frequencies.
-    let mut match_counts: HashMap<String, usize> = HashMap::new();
-    let mut match_strings: HashMap<String, HashMap<String, usize>> = HashMap::new();
+impl InsertLengths {
+    // Adds the insert length of a record.
+    fn add(&mut self, length: usize) {
+        self.minimum = if self.count == 0 {
+            length
+        } else {
+            self.minimum.min(length)
+        };
+        self.maximum = self.maximum.max(length);
+        self.total += length;
+        self.count += 1;
+    }
+
+    // Adds the insert lengths of another record of the same amplicon.
+    fn merge(&mut self, other: &InsertLengths) {
+        if other.count == 0 {
+            return;
+        }
+        self.minimum = if self.count == 0 {
+            other.minimum
+        } else {
+            self.minimum.min(other.minimum)
+        };
+        self.maximum = self.maximum.max(other.maximum);
+        self.total += other.total;
+        self.count += other.count;
+    }
+
+    // Returns the mean insert length, if any record was added.
+    fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.total as f64 / self.count as f64)
+    }
+}
+
+pub fn run_summarise(cli: &Cli, include_count: bool) -> io::Result<()> {
+    // Parse the patterns file, which may give several regex sets, each summarised in a
+    // section of its own.
+    let pattern_sets = load_pattern_sets(cli);
+    let multiple_sets = pattern_sets.len() > 1;
+
+    // The input files, which are read in turn.
+    let paths = input_paths(cli);
+
+    // Initialize counters to store match counts and sub-match frequencies for each input
+    // file and set.
+    let file_summaries: Vec<SetSummary> = pattern_sets
+        .iter()
+        .map(|pattern_set| {
+            SetSummary::new(pattern_set.patterns().len(), pattern_set.amplicons().len())
+        })
+        .collect();
+    let mut summaries = vec![file_summaries; paths.len()];
 
     // Initialize database connection if SQL output is enabled.
     let db_conn = if cli.write_sql {
-        let conn = if cli.patterns.ends_with(".json") {
-            let pattern_data: serde_json::Value =
-                serde_json::from_str(&std::fs::read_to_string(&cli.patterns).unwrap()).unwrap();
-            if pattern_data["regexSet"]["qualityEncoding"].is_null() {
-                output::create_sqlite_db().unwrap()
-            } else {
-                output::create_sqlite_db_with_quality().unwrap()
-            }
+        let conn = if pattern_sets
+            .iter()
+            .any(|pattern_set| pattern_set.predicates().quality_encoding.is_some())
+        {
+            output::create_sqlite_db_with_quality().unwrap()
         } else {
             output::create_sqlite_db().unwrap()
         };
-        output::write_regex_to_db(&conn, &cli.patterns, &cli.file).unwrap();
+        for path in &paths {
+            output::write_regex_to_db(&conn, &pattern_sets[0], path).unwrap();
+        }
+        // Record the regex set each record is summarised for, and the file it was read from.
+        if multiple_sets {
+            output::add_regex_set_name_column(&conn).unwrap();
+        }
+        if paths.len() > 1 {
+            output::add_queried_file_column(&conn).unwrap();
+        }
         Some(conn)
     } else {
         None
     };
 
-    // Process each FASTQ record in a loop.
-    while let Some(result) = reader.next() {
-        // Attempt to read a FASTQ record.
-        let record = match result {
-            Ok(record) => record,
-            Err(e) => {
-                // Return error if file format is not supported.
-                return Err(io::Error::other(format!(
-                    "grepq only supports the fastq format. Check your input file.: {}",
-                    e
-                )));
-            }
-        };
+    // Process each FASTQ record of each input file in a loop.
+    for (file, path) in paths.iter().enumerate() {
+        let mut reader = create_file_reader(cli, path);
+        while let Some(result) = reader.next() {
+            // Attempt to read a FASTQ record.
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    // Return error if file format is not supported.
+                    return Err(io::Error::other(format!(
+                        "grepq only supports the fastq format. Check your input file.: {}",
+                        e
+                    )));
+                }
+            };
+
+            // Summarise the record for each regex set, for the file it was read from.
+            for (pattern_set, summary) in pattern_sets.iter().zip(&mut summaries[file]) {
+                let patterns = pattern_set.patterns();
+                // Match the sequence against the regex set. A pattern is only found if the record
+                // passes its predicates: sequence length, header pattern, and quality.
+                let matches = pattern_set.matches(record.head(), record.seq(), record.qual());
+                for rejected in pattern_set.rejected(record.head(), record.seq(), record.qual()) {
+                    summary.rejected_counts[rejected] += 1;
+                }
 
-        // Apply filters: sequence length, header pattern, and quality.
-        let seq_len_check = minimum_sequence_length
-            .map(|len| record.seq().len() >= len as usize)
-            .unwrap_or(true);
-        let header_check = header_regex
-            .as_ref()
-            .map(|re| re.is_match(record.head()))
-            .unwrap_or(true);
-        let qual_check = match minimum_quality {
-            Some(min_q) => {
-                quality::average_quality(
-                    record.qual(),
-                    quality_encoding.as_deref().unwrap_or("Phred+33"),
-                ) >= min_q
-            }
-            None => true,
-        };
+                // Summarise the record if it passes the set-wide filters, or a pattern is found.
+                if !matches.is_empty()
+                    || pattern_set.passes_predicates(record.head(), record.seq(), record.qual())
+                {
+                    // Count the amplicons found in a record passing the set-wide filters.
+                    if !pattern_set.amplicons().is_empty()
+                        && pattern_set.passes_predicates(record.head(), record.seq(), record.qual())
+                    {
+                        for (amplicon, hit) in
+                            pattern_set.find_amplicons(record.seq(), record.qual())
+                        {
+                            summary.amplicon_inserts[amplicon].add(hit.insert_length());
+                        }
+                    }
 
-        // If the record passes all filters, match the sequence against the regex set.
-        if seq_len_check && header_check && qual_check {
-            let mut matches_info = vec![];
-            // Iterate over all regex matches for the sequence.
-            for mat in regex_set.matches(record.seq()).into_iter() {
-                // Obtain the pattern string and convert it if needed.
-                let matched_pattern = regex_set.patterns()[mat].to_string();
-                let converted_pattern = crate::initialise::convert_iupac_to_regex(&matched_pattern);
-                *match_counts.entry(converted_pattern.clone()).or_insert(0) += 1;
-
-                // Track sub-match frequencies for each pattern.
-                let entry = match_strings.entry(converted_pattern.clone()).or_default();
-                let matched = Regex::new(&regex_set.patterns()[mat])
-                    .unwrap()
-                    .find_iter(record.seq())
-                    .next()
-                    .unwrap();
-                let matched_substring = &record.seq()[matched.start()..matched.end()];
-                *entry
-                    .entry(String::from_utf8_lossy(matched_substring).to_string())
-                    .or_insert(0) += 1;
-
-                // If SQL write is enabled, collect match info in JSON format.
-                if cli.write_sql {
-                    matches_info.push(json!({
-                        "pattern": matched_pattern,
-                        "start": matched.start(),
-                        "end": matched.end(),
-                        "match": String::from_utf8_lossy(matched_substring).to_string()
-                    }));
-                }
-            }
+                    let mut matches_info = vec![];
+                    // Iterate over all regex matches for the sequence.
+                    for mat in matches {
+                        summary.match_counts[mat] += 1;
+
+                        // Track sub-match frequencies for each pattern. The sub-match of a
+                        // protein pattern is the amino acids matched, counted by frame.
+                        let matched = pattern_set.find(mat, record.seq(), record.qual()).unwrap();
+                        let matched_substring = translate::peptide(
+                            record.seq(),
+                            &matched,
+                            pattern_set.case_insensitive(),
+                        )
+                        .unwrap_or_else(|| record.seq()[matched.start..matched.end].to_vec());
+                        summary.match_strings[mat]
+                            .entry(String::from_utf8_lossy(&matched_substring).to_string())
+                            .or_insert((0, matched.distance))
+                            .0 += 1;
+                        if let Some(frame) = matched.frame {
+                            *summary.frame_counts[mat].entry(frame).or_insert(0) += 1;
+                        }
 
-            // Write record data and match details to the database if enabled.
-            if cli.write_sql {
-                let avg_quality = quality_encoding
-                    .as_ref()
-                    .map(|encoding| quality::average_quality(record.qual(), encoding.as_str()))
-                    .unwrap_or(0.0);
-                let (tnf, ntn) =
-                    quality::tetranucleotide_frequencies(record.seq(), cli.num_tetranucleotides);
-                let (ctnf, nctn) = quality::canonical_tetranucleotide_frequencies(
-                    record.seq(),
-                    cli.num_tetranucleotides,
-                );
-                let gc = quality::gc_content(record.seq());
-                let gc_int = gc.round() as i64;
-
-                if let Some(ref db) = db_conn {
-                    let matches_json =
-                        serde_json::to_string(&matches_info).unwrap_or_else(|_| "[]".to_string());
-
-                    // Use different SQL statements based on pattern file type and quality encoding
-                    if cli.patterns.ends_with(".json") {
-                        let pattern_data: serde_json::Value =
-                            serde_json::from_str(&std::fs::read_to_string(&cli.patterns).unwrap())
-                                .unwrap();
-
-                        if !pattern_data["regexSet"]["qualityEncoding"].is_null() {
-                            // With quality encoding
-                            db.execute(
-                                "INSERT INTO fastq_data (header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF, average_quality, variants) 
-                                 VALUES (?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10, ROUND(?11, 2), ?12)",
-                                rusqlite::params![
-                                    String::from_utf8_lossy(record.head()),
-                                    String::from_utf8_lossy(record.seq()),
-                                    String::from_utf8_lossy(record.qual()),
-                                    record.seq().len() as i64,
-                                    gc,
-                                    gc_int,
-                                    ntn as i64,
-                                    nctn as i64,
-                                    tnf,
-                                    ctnf,
-                                    avg_quality,
-                                    matches_json,
-                                ],
-                            ).unwrap();
-                        } else {
-                            // JSON without quality encoding
-                            db.execute(
-                                "INSERT INTO fastq_data (header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF, variants) 
-                                 VALUES (?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10, ?11)",
-                                rusqlite::params![
-                                    String::from_utf8_lossy(record.head()),
-                                    String::from_utf8_lossy(record.seq()),
-                                    String::from_utf8_lossy(record.qual()),
-                                    record.seq().len() as i64,
-                                    gc,
-                                    gc_int,
-                                    ntn as i64,
-                                    nctn as i64,
-                                    tnf,
-                                    ctnf,
-                                    matches_json,
-                                ],
-                            ).unwrap();
+                        // If SQL write is enabled, collect match info in JSON format.
+                        if cli.write_sql {
+                            matches_info.push(output::hit_details(
+                                pattern_set,
+                                mat,
+                                &matched,
+                                record.seq(),
+                                &patterns[mat].regex,
+                            ));
                         }
-                    } else {
-                        // Text file patterns - no quality encoding
-                        db.execute(
-                            "INSERT INTO fastq_data (header, sequence, quality, length, GC, GC_int, nTN, nCTN, TNF, CTNF, variants) 
-                             VALUES (?1, ?2, ?3, ?4, ROUND(?5, 2), ?6, ?7, ?8, ?9, ?10, ?11)",
-                            rusqlite::params![
-                                String::from_utf8_lossy(record.head()),
-                                String::from_utf8_lossy(record.seq()),
-                                String::from_utf8_lossy(record.qual()),
-                                record.seq().len() as i64,
-                                gc,
-                                gc_int,
-                                ntn as i64,
-                                nctn as i64,
-                                tnf,
-                                ctnf,
-                                matches_json,
-                            ],
-                        ).unwrap();
+                    }
+
+                    // Write record data and match details to the database if enabled.
+                    if let Some(ref db) = db_conn {
+                        output::write_record_to_db(
+                            db,
+                            &record,
+                            &matches_info,
+                            pattern_set,
+                            cli.num_tetranucleotides,
+                            output::RecordColumns {
+                                regex_set_name: pattern_set.name().filter(|_| multiple_sets),
+                                queried_file: Some(paths[file].as_str())
+                                    .filter(|_| paths.len() > 1),
+                                ..Default::default()
+                            },
+                        )
+                        .unwrap();
                     }
                 }
             }
         }
     }
 
-    // Sort the collected match counts by descending order.
-    let mut match_counts: Vec<_> = match_counts.into_iter().collect();
-    match_counts.sort_by(|a, b| b.1.cmp(&a.1));
+    // Report each regex set in turn: for each input file when there are several, under
+    // its path, and then for all of them.
+    let mut file_regex_sets = vec![];
+    if paths.len() > 1 {
+        for (path, file_summaries) in paths.iter().zip(&summaries) {
+            println!("File: {}", path);
+            let regex_sets = report_sets(cli, include_count, &pattern_sets, file_summaries);
+            file_regex_sets.push((path, regex_sets));
+        }
+        println!("Total");
+    }
+    let mut totals = summaries.remove(0);
+    for file_summaries in &summaries {
+        for (total, summary) in totals.iter_mut().zip(file_summaries) {
+            total.merge(summary);
+        }
+    }
+    let regex_sets = report_sets(cli, include_count, &pattern_sets, &totals);
+
+    // If JSON output is desired, write detailed match information to "matches.json".
+    if let Some(crate::arg::Commands::Summarise(summarise)) = &cli.command {
+        if summarise.json_matches
+            && summarise.include_names
+            && include_count
+            && !regex_sets.is_empty()
+        {
+            let mut json_output = regex_sets_json(regex_sets, multiple_sets);
+            // The summaries of several input files are listed with their path.
+            if !file_regex_sets.is_empty() {
+                json_output["files"] = file_regex_sets
+                    .into_iter()
+                    .map(|(path, regex_sets)| {
+                        let mut file = regex_sets_json(regex_sets, multiple_sets);
+                        file["file"] = json!(path);
+                        file
+                    })
+                    .collect();
+            }
+            let file = File::create("matches.json")?;
+            serde_json::to_writer(file, &json_output)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Function: report_sets
+// Prints the summary of each regex set in turn (see report_set), returning the regexSets
+// written to matches.json.
+fn report_sets(
+    cli: &Cli,
+    include_count: bool,
+    pattern_sets: &[PatternSet],
+    summaries: &[SetSummary],
+) -> Vec<Value> {
+    let multiple_sets = pattern_sets.len() > 1;
+    let mut regex_sets = vec![];
+    for (pattern_set, summary) in pattern_sets.iter().zip(summaries) {
+        regex_sets.extend(report_set(
+            cli,
+            include_count,
+            pattern_set,
+            summary,
+            multiple_sets,
+        ));
+    }
+    regex_sets
+}
+
+// Function: regex_sets_json
+// Returns the regexSets written to matches.json: as "regexSets" when the patterns file
+// gives several sets, or as "regexSet".
+fn regex_sets_json(mut regex_sets: Vec<Value>, multiple_sets: bool) -> Value {
+    if multiple_sets {
+        json!({ "regexSets": regex_sets })
+    } else {
+        json!({ "regexSet": regex_sets.remove(0) })
+    }
+}
+
+// Function: report_set
+// Prints the summary of a regex set and, for JSON, YAML and FASTA patterns files, returns
+// it as the regexSet written to matches.json. The name of the set is printed with --names,
+// or when the patterns file gives several sets.
+fn report_set(
+    cli: &Cli,
+    include_count: bool,
+    pattern_set: &PatternSet,
+    summary: &SetSummary,
+    multiple_sets: bool,
+) -> Option<Value> {
+    let patterns = pattern_set.patterns();
+    let match_counts = &summary.match_counts;
+    let match_strings = &summary.match_strings;
+    let rejected_counts = &summary.rejected_counts;
 
     // Process output based on the type of patterns file.
-    if patterns_path.ends_with(".json") {
-        // For JSON patterns, read the regex set name.
-        let json: serde_json::Value = serde_json::from_reader(File::open(patterns_path)?)?;
-        let regex_set_name = json["regexSet"]["regexSetName"]
-            .as_str()
-            .unwrap_or("Unknown");
-
-        // JSON-specific processing for regex patterns with names and variants
-        if matches!(cli.command.as_ref(), Some(crate::arg::Commands::Summarise(s)) if s.include_names)
+    if pattern_set.format().is_structured() {
+        // For JSON, YAML and FASTA patterns, read the regex set name.
+        let regex_set_name = pattern_set.name().unwrap_or("Unknown");
+
+        // Processing for regex patterns with names and variants
+        if multiple_sets
+            || matches!(cli.command.as_ref(), Some(crate::arg::Commands::Summarise(s)) if s.include_names)
         {
             println!("Regex Set Name: {}", regex_set_name);
         }
 
-        let regex_array = json["regexSet"]["regex"].as_array().unwrap();
         let mut regex_matches = vec![];
 
-        // Iterate over all regex definitions in the JSON.
-        for regex in regex_array {
-            let regex_string = regex["regexString"].as_str().unwrap();
-            let converted_regex_string = crate::initialise::convert_iupac_to_regex(regex_string);
-            let regex_name = regex["regexName"].as_str().unwrap_or("Unknown");
-            let count = match_counts
-                .iter()
-                .find(|(pattern, _)| pattern == &converted_regex_string)
-                .map(|(_, count)| count)
-                .unwrap_or(&0);
+        // Iterate over all patterns, reporting the reverse complement of a regex on its
+        // own row, where enabled.
+        for (index, pattern) in patterns.iter().enumerate() {
+            let strand_label = if pattern_set.is_stranded(index) {
+                format!(" [{}]", pattern.strand.as_str())
+            } else {
+                String::new()
+            };
+            let regex_name = &pattern.name;
+            let regex_string = &pattern.original;
+            let count = &match_counts[index];
 
             // Get the most frequent variant matches for the regex.
-            let mut most_frequent_matches: Vec<_> = match_strings
-                .get(&converted_regex_string)
-                .map(|matches| {
-                    let mut matches_vec: Vec<_> = matches.iter().collect();
-                    matches_vec.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
-                    matches_vec
-                })
-                .unwrap_or_default();
+            let mut most_frequent_matches: Vec<_> = match_strings[index].iter().collect();
+            most_frequent_matches.sort_by_key(|&(_, &(count, _))| std::cmp::Reverse(count));
 
             // Determine how many variant matches to include.
             let top_n = cli
@@ -286,63 +420,97 @@ pub fn run_summarise(cli: &Cli, include_count: bool) -> io::Result<()> {
                 .unwrap_or(1);
             most_frequent_matches.truncate(top_n);
 
-            let variants_array = regex["variants"]
-                .as_array()
-                .unwrap_or_else(|| Box::leak(Box::new(Vec::new())));
-            let variants = variants_array;
             let most_frequent_matches_json: Vec<_> = most_frequent_matches
                 .into_iter()
-                .map(|(seq, count)| {
-                    let variant_name = variants.iter().find_map(|variant| {
-                        if variant["variantString"].as_str() == Some(seq) {
-                            variant["variantName"].as_str().map(|s| s.to_string())
-                        } else {
-                            None
-                        }
-                    });
-                    json!({"variant": seq, "count": count, "variantName": variant_name})
+                .map(|(seq, &(count, distance))| {
+                    let variant_name = pattern
+                        .variants
+                        .iter()
+                        .find(|variant| &variant.sequence == seq)
+                        .map(|variant| variant.name.clone());
+                    json!({
+                        "variant": seq,
+                        "count": count,
+                        "variantName": variant_name,
+                        "distance": distance
+                    })
                 })
                 .collect();
 
-            regex_matches.push(json!({
+            let mut regex_match = json!({
                 "regexName": regex_name,
                 "regexString": regex_string,
                 "regexCount": count,
+                "strand": pattern.strand.as_str(),
                 "variants": most_frequent_matches_json
-            }));
+            });
+            // Report the frames in which a protein pattern was found, e.g. "+1: 2, -3: 1".
+            let frame_label = if pattern.protein {
+                let frames = &summary.frame_counts[index];
+                regex_match["frameCounts"] = Value::Object(
+                    frames
+                        .iter()
+                        .map(|(frame, count)| (format!("{:+}", frame), json!(count)))
+                        .collect(),
+                );
+                let frames: Vec<String> = frames
+                    .iter()
+                    .map(|(frame, count)| format!("{:+}: {}", frame, count))
+                    .collect();
+                format!(" [frames {}]", frames.join(", "))
+            } else {
+                String::new()
+            };
+            // Report hits rejected for low quality where a match quality is given.
+            let rejected_label = if pattern.match_quality.is_set() {
+                regex_match["rejectedCount"] = json!(rejected_counts[index]);
+                format!(" (rejected: {})", rejected_counts[index])
+            } else {
+                String::new()
+            };
+            regex_matches.push(regex_match);
 
             // Print summary information for each regex.
             if matches!(cli.command.as_ref(), Some(crate::arg::Commands::Summarise(s)) if s.include_names)
             {
                 if include_count {
-                    println!("{} ({}): {}", regex_name, regex_string, count);
+                    println!(
+                        "{} ({}){}: {}{}{}",
+                        regex_name, regex_string, strand_label, count, rejected_label, frame_label
+                    );
                 } else {
-                    println!("{} ({})", regex_name, regex_string);
+                    println!("{} ({}){}", regex_name, regex_string, strand_label);
                 }
-            } else if *count > 0 {
+            } else if *count > 0 || rejected_counts[index] > 0 {
                 if include_count {
-                    println!("{}: {}", regex_string, count);
+                    println!(
+                        "{}{}: {}{}{}",
+                        regex_string, strand_label, count, rejected_label, frame_label
+                    );
                 } else {
-                    println!("{}", regex_string);
+                    println!("{}{}", regex_string, strand_label);
                 }
             }
         }
 
-        // If JSON output is desired, write detailed match information to "matches.json".
-        if let Some(crate::arg::Commands::Summarise(summarise)) = &cli.command {
-            if summarise.json_matches && summarise.include_names && include_count {
-                let json_output = json!({
-                    "regexSet": {
-                        "regexSetName": regex_set_name,
-                        "regex": regex_matches
-                    }
-                });
-                let file = File::create("matches.json")?;
-                serde_json::to_writer(file, &json_output)?;
-            }
+        let mut regex_set = json!({
+            "regexSetName": regex_set_name,
+            "regex": regex_matches
+        });
+        if !pattern_set.amplicons().is_empty() {
+            regex_set["amplicons"] =
+                json!(report_amplicons(cli, include_count, pattern_set, summary));
         }
+        Some(regex_set)
     } else {
-        // For plain text patterns, print each pattern and its match count.
+        // For plain text patterns, print each pattern and its match count, sorted in
+        // descending order of count.
+        let mut match_counts: Vec<_> = patterns
+            .iter()
+            .map(|pattern| &pattern.regex)
+            .zip(match_counts.iter().copied())
+            .collect();
+        match_counts.sort_by_key(|b| std::cmp::Reverse(b.1));
         for (pattern, count) in &match_counts {
             if count > &0 {
                 if include_count {
@@ -352,7 +520,56 @@ pub fn run_summarise(cli: &Cli, include_count: bool) -> io::Result<()> {
                 }
             }
         }
+        None
     }
+}
 
-    Ok(())
+// Function: report_amplicons
+// Prints the number of records in which each amplicon of a regex set was found, with the
+// range and mean of its insert lengths, and returns them as written to matches.json. As
+// for regexes, amplicons not found are only printed with --names.
+fn report_amplicons(
+    cli: &Cli,
+    include_count: bool,
+    pattern_set: &PatternSet,
+    summary: &SetSummary,
+) -> Vec<Value> {
+    let include_names = matches!(
+        cli.command.as_ref(),
+        Some(crate::arg::Commands::Summarise(s)) if s.include_names
+    );
+    let mut amplicon_matches = vec![];
+    for (amplicon, inserts) in pattern_set
+        .amplicons()
+        .iter()
+        .zip(&summary.amplicon_inserts)
+    {
+        let mean = inserts.mean();
+        amplicon_matches.push(json!({
+            "ampliconName": amplicon.name,
+            "forward": amplicon.forward,
+            "reverse": amplicon.reverse,
+            "ampliconCount": inserts.count,
+            "minimumInsertLength": mean.map(|_| inserts.minimum),
+            "maximumInsertLength": mean.map(|_| inserts.maximum),
+            "meanInsertLength": mean
+        }));
+
+        if !include_names && inserts.count == 0 {
+            continue;
+        }
+        let label = format!(
+            "Amplicon {} ({}..{})",
+            amplicon.name, amplicon.forward, amplicon.reverse
+        );
+        match mean.filter(|_| include_count) {
+            Some(mean) => println!(
+                "{}: {} (insert {}-{}, mean {:.1})",
+                label, inserts.count, inserts.minimum, inserts.maximum, mean
+            ),
+            None if include_count => println!("{}: 0", label),
+            None => println!("{}", label),
+        }
+    }
+    amplicon_matches
 }
@@ -27,8 +27,19 @@
 #[cfg(test)]
 mod test_module {
     // Import modules used in tests.
+    use crate::arg;
+    use crate::header;
     use crate::initialise;
+    use crate::input;
+    use crate::iupac;
+    use crate::lint;
+    use crate::matcher;
+    use crate::output;
+    use crate::paired;
+    use crate::patterns;
     use crate::quality;
+    use crate::schema;
+    use crate::translate;
     use serde_json::Value;
     use std::io::Write;
     use tempfile::NamedTempFile;
@@ -52,27 +63,28 @@ mod test_module {
         // Additional comment: Testing correct conversion for both unambiguous and ambiguous nucleotide codes.
         // Test: Verify conversion of IUPAC nucleotide codes to regex strings.
         // Standard bases remain unchanged.
-        assert_eq!(initialise::convert_iupac_to_regex("ACTG"), "ACTG");
+        assert_eq!(initialise::convert_iupac_to_regex("ACTG").unwrap(), "ACTG");
         // Ambiguous codes are converted to their regex equivalents.
-        assert_eq!(initialise::convert_iupac_to_regex("N"), "[ACGT]"); // 'N' to [ACGT]
-        assert_eq!(initialise::convert_iupac_to_regex("Y"), "[CT]");
-        assert_eq!(initialise::convert_iupac_to_regex("R"), "[AG]");
-        assert_eq!(initialise::convert_iupac_to_regex("W"), "[AT]");
-        assert_eq!(initialise::convert_iupac_to_regex("S"), "[CG]");
-        assert_eq!(initialise::convert_iupac_to_regex("K"), "[GT]");
-        assert_eq!(initialise::convert_iupac_to_regex("M"), "[AC]");
-        assert_eq!(initialise::convert_iupac_to_regex("B"), "[CGT]");
-        assert_eq!(initialise::convert_iupac_to_regex("D"), "[AGT]");
-        assert_eq!(initialise::convert_iupac_to_regex("H"), "[ACT]");
-        assert_eq!(initialise::convert_iupac_to_regex("V"), "[ACG]");
+        assert_eq!(initialise::convert_iupac_to_regex("N").unwrap(), "[ACGT]"); // 'N' to [ACGT]
+        assert_eq!(initialise::convert_iupac_to_regex("Y").unwrap(), "[CT]");
+        assert_eq!(initialise::convert_iupac_to_regex("R").unwrap(), "[AG]");
+        assert_eq!(initialise::convert_iupac_to_regex("W").unwrap(), "[AT]");
+        assert_eq!(initialise::convert_iupac_to_regex("S").unwrap(), "[CG]");
+        assert_eq!(initialise::convert_iupac_to_regex("K").unwrap(), "[GT]");
+        assert_eq!(initialise::convert_iupac_to_regex("M").unwrap(), "[AC]");
+        assert_eq!(initialise::convert_iupac_to_regex("B").unwrap(), "[CGT]");
+        assert_eq!(initialise::convert_iupac_to_regex("D").unwrap(), "[AGT]");
+        assert_eq!(initialise::convert_iupac_to_regex("H").unwrap(), "[ACT]");
+        assert_eq!(initialise::convert_iupac_to_regex("V").unwrap(), "[ACG]");
     }
 
     #[test]
-    #[should_panic(expected = "Illegal character found in pattern")]
     fn test_illegal_iupac_conversion() {
-        // Additional comment: This test confirms that an illegal character triggers a panic.
-        // Test: Ensure that the conversion panics when an illegal character is encountered.
-        initialise::convert_iupac_to_regex("AXTG");
+        // Additional comment: This test confirms that an illegal character triggers an error.
+        // Test: Ensure that the conversion fails when an illegal character is encountered.
+        let error = initialise::convert_iupac_to_regex("AXTG").unwrap_err();
+        assert_eq!(error.reason, "Illegal character found in pattern: X");
+        assert_eq!(error.offset, Some(1));
     }
 
     #[test]
@@ -114,9 +126,33 @@ mod test_module {
         // Write the patterns into the temporary file.
         temp_file.as_file().write_all(patterns.as_bytes()).unwrap();
         // Parse the file and ensure that exactly 30 regex patterns are obtained.
-        let result = initialise::parse_patterns_file(temp_file.path().to_str().unwrap())
+        let result = patterns::PatternSet::from_file(temp_file.path().to_str().unwrap(), None)
             .expect("Failed to parse patterns file");
-        assert_eq!(result.0.patterns().len(), 30);
+        assert_eq!(result.patterns().len(), 30);
+    }
+
+    #[test]
+    fn test_paired_end_rules() {
+        // Test: Mates are paired by read ID, ignoring comments and /1, /2 suffixes.
+        assert_eq!(
+            paired::mate_id(b"SRR001.1/1 length=150"),
+            paired::mate_id(b"SRR001.1/2 length=150")
+        );
+        assert_eq!(
+            paired::mate_id(b"A00627:493:1:1101:15772:1063 1:N:0:ACGT"),
+            b"A00627:493:1:1101:15772:1063"
+        );
+        assert_ne!(paired::mate_id(b"SRR001.1"), paired::mate_id(b"SRR001.2"));
+
+        // Test: Each rule combines the results of matching the mates it searches.
+        use paired::PairRule;
+        assert!(PairRule::Either.holds(false, true));
+        assert!(!PairRule::Both.holds(false, true));
+        assert!(PairRule::Both.holds(true, true));
+        assert_eq!(PairRule::R1.searched(), (true, false));
+        assert!(!PairRule::R1.holds(false, true));
+        assert_eq!(PairRule::R2.searched(), (false, true));
+        assert!(PairRule::R2.holds(false, true));
     }
 
     #[test]
@@ -185,8 +221,8 @@ mod test_module {
         // Additional comment: Ensures that lowercase inputs are correctly transformed to uppercase.
         // Test: Ensure that input in lowercase is converted to uppercase
         // and that ambiguous bases convert correctly.
-        assert_eq!(initialise::convert_iupac_to_regex("actg"), "ACTG");
-        assert_eq!(initialise::convert_iupac_to_regex("n"), "[ACGT]");
+        assert_eq!(initialise::convert_iupac_to_regex("actg").unwrap(), "ACTG");
+        assert_eq!(initialise::convert_iupac_to_regex("n").unwrap(), "[ACGT]");
     }
 
     #[test]
@@ -198,14 +234,14 @@ mod test_module {
         let temp_file = NamedTempFile::new().unwrap();
         let patterns = "ACTG\nN";
         temp_file.as_file().write_all(patterns.as_bytes()).unwrap();
-        // Copy to a ".txt" file since parse_patterns_file checks the file extension.
+        // Copy to a ".txt" file, as is usual for plain-text pattern files.
         let txt_path = temp_file.path().with_extension("txt");
         fs::copy(temp_file.path(), &txt_path).unwrap();
 
-        let result = initialise::parse_patterns_file(txt_path.to_str().unwrap())
+        let result = patterns::PatternSet::from_file(txt_path.to_str().unwrap(), None)
             .expect("Failed to parse plain text pattern file");
         // Expect exactly 2 regex patterns.
-        assert_eq!(result.0.patterns().len(), 2);
+        assert_eq!(result.patterns().len(), 2);
 
         // Cleanup the temporary ".txt" file.
         let _ = fs::remove_file(&txt_path);
@@ -230,7 +266,308 @@ mod test_module {
         // H -> [ACT]
         // V -> [ACG]
         let expected = "[ACGT][CT][AG][CG][AT][GT][AC][CGT][AGT][ACT][ACG]";
-        assert_eq!(initialise::convert_iupac_to_regex(pattern), expected);
+        assert_eq!(
+            initialise::convert_iupac_to_regex(pattern).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_mixed_regex_iupac_conversion() {
+        // Test: IUPAC codes are expanded wherever they are literals, keeping regex
+        // metacharacters intact.
+        let cases = [
+            ("GGRNGG.{0,5}CAG", "GG[AG][ACGT]GG.{0,5}CAG"),
+            ("R{2}ACN+", "[AG]{2}AC[ACGT]+"),
+            ("^TTY(GA|CW)*$", "^TT[CT](GA|C[AT])*$"),
+            ("[RC]T[^N]", "[AGC]T[^ACGT]"),
+            (r"\d?A", r"\d?A"),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(
+                initialise::convert_iupac_to_regex(pattern).unwrap(),
+                expected
+            );
+        }
+
+        // Mixed patterns are expanded when parsing pattern files.
+        let pattern_set =
+            patterns::PatternSet::from_str("GGRNGG.{0,5}CAG", patterns::PatternFormat::Text)
+                .unwrap();
+        assert!(pattern_set.matcher().is_match(b"TTGGAAGGTTCAGTT"));
+    }
+
+    #[test]
+    fn test_mixed_regex_keeps_literals() {
+        // Test: In a mixed pattern, literals keep their case, ambiguity codes are expanded
+        // in the case they are written in, and letters that are not nucleotide codes are
+        // kept as they are
+        let cases = [
+            ("acg.?t", "acg.?t"),
+            ("ggr.*N", "gg[ag].*[ACGT]"),
+            ("GGATTZ|GGATTAG", "GGATTZ|GGATTAG"),
+            ("GG[AX]", "GG[AX]"),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(
+                initialise::convert_iupac_to_regex(pattern).unwrap(),
+                expected
+            );
+        }
+        // A lowercase mixed pattern matches lowercase reads, as written.
+        let pattern_set =
+            patterns::PatternSet::from_str("ggatt.*g", patterns::PatternFormat::Text).unwrap();
+        assert!(pattern_set.matcher().is_match(b"ccccggattag"));
+        assert!(!pattern_set.matcher().is_match(b"CCCCGGATTAG"));
+        assert!(
+            patterns::PatternSet::from_str("GGATTZ|GGATTAG", patterns::PatternFormat::Text).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_reverse_complement_regex() {
+        // Test: Verify reverse complements of IUPAC and regex patterns.
+        // IUPAC codes are complemented (R <-> Y, W stays W).
+        assert_eq!(
+            iupac::reverse_complement_regex("RAATWGRCGGGG").unwrap(),
+            "CCCCGYCWATTY"
+        );
+        // Character classes are complemented and sorted.
+        assert_eq!(
+            iupac::reverse_complement_regex("[AG]AAT[AT]G[AG]CGGGG").unwrap(),
+            "CCCCG[CT]C[AT]ATT[CT]"
+        );
+        // Repetitions, groups and anchors are preserved and reversed.
+        assert_eq!(
+            iupac::reverse_complement_regex("^GG.{0,5}(CA|TT)+").unwrap(),
+            "(TG|AA)+.{0,5}CC$"
+        );
+        assert_eq!(iupac::reverse_complement_regex("[A-D]").unwrap(), "[GHTV]");
+        assert_eq!(iupac::reverse_complement_sequence("AACG"), "CGTT");
+    }
+
+    #[test]
+    fn test_reverse_complement_json_parsing() {
+        // Test: A set-wide reverseComplement adds a reverse strand twin for each
+        // regex, unless overridden per regex.
+        let json_content = r#"
+        {
+            "regexSet": {
+                "regexSetName": "ReverseComplementTest",
+                "reverseComplement": true,
+                "regex": [
+                    {
+                        "regexName": "Primer contig 06a",
+                        "regexString": "RAATWGRCGGGG"
+                    },
+                    {
+                        "regexName": "Forward only",
+                        "regexString": "ACTG",
+                        "reverseComplement": false
+                    }
+                ]
+            }
+        }
+        "#;
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .write_all(json_content.as_bytes())
+            .unwrap();
+        let json_path = temp_file.path().with_extension("json");
+        std::fs::copy(temp_file.path(), &json_path).unwrap();
+
+        let pattern_set =
+            patterns::PatternSet::from_file(json_path.to_str().unwrap(), None).unwrap();
+        assert_eq!(
+            pattern_set.matcher().patterns(),
+            ["[AG]AAT[AT]G[AG]CGGGG", "CCCCG[CT]C[AT]ATT[CT]", "ACTG"]
+        );
+        let names: Vec<_> = pattern_set.patterns().iter().map(|p| &p.name).collect();
+        assert_eq!(
+            names,
+            ["Primer contig 06a", "Primer contig 06a", "Forward only"]
+        );
+        let strands: Vec<_> = pattern_set.patterns().iter().map(|p| p.strand).collect();
+        assert_eq!(
+            strands,
+            [
+                patterns::Strand::Forward,
+                patterns::Strand::Reverse,
+                patterns::Strand::Forward
+            ]
+        );
+        assert_eq!(pattern_set.patterns()[1].original, "CCCCGYCWATTY");
+        assert!(pattern_set.is_stranded(0) && pattern_set.is_stranded(1));
+        assert!(!pattern_set.is_stranded(2));
+
+        let _ = std::fs::remove_file(&json_path);
+    }
+
+    #[test]
+    fn test_mismatch_tolerant_matching() {
+        // Test: A pattern with maxMismatches matches within the Hamming distance only.
+        let patterns = vec!["AC[AG]TGG".to_string()];
+        let matcher = matcher::PatternMatcher::new(
+            &patterns,
+            &[Some(matcher::Distance::Mismatches(1))],
+            false,
+        )
+        .unwrap();
+        assert!(matcher.is_match(b"TTACGTGGTT"));
+        assert!(matcher.is_match(b"TTACCTGGTT")); // One mismatch.
+        assert!(!matcher.is_match(b"TTACCTCGTT")); // Two mismatches.
+        assert_eq!(
+            matcher.find(0, b"TTACCTGGTT"),
+            Some(matcher::Hit {
+                start: 2,
+                end: 8,
+                distance: 1,
+                frame: None
+            })
+        );
+        // Exact matches are preferred over overlapping approximate matches.
+        assert_eq!(matcher.find_iter(0, b"ACATGG")[0].distance, 0);
+    }
+
+    #[test]
+    fn test_literal_fast_path() {
+        // Test: Patterns that expand to a bounded set of literals are matched as the regex
+        // engine matches them, alongside patterns that must be left to it.
+        let patterns: Vec<String> = [
+            "GG[AG][ACGT]GG",
+            "C(AT|GC)G",
+            "ACGT.{0,3}TT",
+            "^TTT",
+            "[ACGT]{12}",
+            "",
+            "TAGA[AT]ACCC[ACGT][ACGT]G",
+        ]
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect();
+        let mut state: u32 = 7;
+        let mut sequences: Vec<Vec<u8>> = (0..200)
+            .map(|_| {
+                (0..30)
+                    .map(|_| {
+                        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                        b"ACGTacgt"[(state >> 16) as usize % 8]
+                    })
+                    .collect()
+            })
+            .collect();
+        sequences.push(b"GGAAGGCATGACGTCTT".to_vec());
+        sequences.push(b"ggatggcgcg".to_vec());
+        for case_insensitive in [false, true] {
+            let matcher = matcher::PatternMatcher::new(&patterns, &[], case_insensitive).unwrap();
+            assert_eq!(matcher.patterns(), patterns.as_slice());
+            let regex_set = regex::bytes::RegexSetBuilder::new(&patterns)
+                .case_insensitive(case_insensitive)
+                .build()
+                .unwrap();
+            for seq in &sequences {
+                let expected: Vec<usize> = regex_set.matches(seq).into_iter().collect();
+                assert_eq!(matcher.matches(seq), expected);
+                assert_eq!(matcher.is_match(seq), regex_set.is_match(seq));
+            }
+        }
+    }
+
+    #[test]
+    fn test_edit_tolerant_matching() {
+        // Test: A pattern with maxEdits tolerates insertions and deletions.
+        let patterns = vec!["ACGTACGT".to_string(), "TTTTTTTT".to_string()];
+        let matcher = matcher::PatternMatcher::new(
+            &patterns,
+            &[Some(matcher::Distance::Edits(1)), None],
+            false,
+        )
+        .unwrap();
+        // One deletion.
+        assert_eq!(
+            matcher.find(0, b"GGACGACGTGG"),
+            Some(matcher::Hit {
+                start: 2,
+                end: 9,
+                distance: 1,
+                frame: None
+            })
+        );
+        // One insertion.
+        assert_eq!(matcher.find(0, b"GGACGTTACGTGG").unwrap().distance, 1);
+        assert_eq!(matcher.matches(b"GGACGTTACGTGG"), vec![0]);
+        // Two edits is too many.
+        assert!(!matcher.is_match(b"GGACAGTTACGTGG"));
+        // Patterns that are not of fixed length cannot be matched approximately.
+        assert!(matcher::PatternMatcher::new(
+            &["AC+GT".to_string()],
+            &[Some(matcher::Distance::Edits(1))],
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_windowed_matching() {
+        // Test: A regex confined to a window only matches within it, and its hits are
+        // reported in the coordinates of the whole sequence.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "WindowTest",
+                "regex": [
+                    {"regexName": "Barcode", "regexString": "ACGT", "window": {"first": 6}},
+                    {"regexName": "Adapter", "regexString": "TTTT", "window": {"last": 6}},
+                    {
+                        "regexName": "Internal",
+                        "regexString": "GGCC",
+                        "maxMismatches": 1,
+                        "window": {"start": 4, "end": 12}
+                    }
+                ]
+            }
+        });
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let matcher = pattern_set.matcher();
+
+        let seq = b"AACGTAGGACAAAAATTTT";
+        assert_eq!(matcher.matches(seq), vec![0, 1, 2]);
+        assert_eq!(
+            matcher.find(1, seq),
+            Some(matcher::Hit {
+                start: 15,
+                end: 19,
+                distance: 0,
+                frame: None
+            })
+        );
+        assert_eq!(matcher.find(2, seq).unwrap().start, 6);
+
+        // The same patterns outside their windows are not matched.
+        assert!(!matcher.is_match(b"AAAAAAACGTAAAAAATTTTAAAAAAA"));
+        assert_eq!(matcher.find_iter(0, b"AAAAAAACGTAA"), vec![]);
+
+        // A window with no positions, or an empty range, is an error.
+        for window in [
+            serde_json::json!({}),
+            serde_json::json!({"first": 5, "last": 5}),
+            serde_json::json!({"start": 10, "end": 5}),
+        ] {
+            let json = serde_json::json!({
+                "regexSet": {
+                    "regexSetName": "WindowTest",
+                    "regex": [{"regexName": "Bad", "regexString": "ACGT", "window": window}]
+                }
+            });
+            let error = patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json)
+                .err()
+                .unwrap();
+            assert_eq!(
+                error.location,
+                Some(patterns::ErrorLocation::Regex("Bad".to_string()))
+            );
+        }
     }
 
     #[test]
@@ -259,7 +596,7 @@ mod test_module {
             .as_file()
             .write_all(json_content.as_bytes())
             .unwrap();
-        let result = initialise::parse_patterns_file(temp_file.path().to_str().unwrap());
+        let result = patterns::PatternSet::from_file(temp_file.path().to_str().unwrap(), None);
         assert!(
             result.is_err(),
             "Expected error due to invalid variant DNA sequence."
@@ -299,35 +636,1058 @@ mod test_module {
         let json_path = temp_file.path().with_extension("json");
         fs::copy(temp_file.path(), &json_path).unwrap();
 
-        let result = initialise::parse_patterns_file(json_path.to_str().unwrap());
+        let result = patterns::PatternSet::from_file(json_path.to_str().unwrap(), None);
         assert!(
             result.is_ok(),
             "Expected valid JSON pattern parsing to succeed."
         );
 
-        if let Ok((regex_set, header, min_len, min_qual, quality_enc, regex_names, variants)) =
-            result
-        {
+        if let Ok(pattern_set) = result {
+            let predicates = pattern_set.predicates();
             // Verify one regex is parsed.
-            assert_eq!(regex_set.patterns().len(), 1);
+            assert_eq!(pattern_set.patterns().len(), 1);
+            // Verify the regex set name.
+            assert_eq!(pattern_set.name(), Some("ValidTest"));
             // Verify headerRegex was parsed.
-            assert!(header.is_some());
+            assert!(predicates.header_regex.is_some());
             // Verify minimum sequence length.
-            assert_eq!(min_len, Some(4));
+            assert_eq!(predicates.minimum_sequence_length, Some(4));
             // Verify minimum quality.
-            assert_eq!(min_qual, Some(30.0));
+            assert_eq!(predicates.minimum_average_quality, Some(30.0));
             // Verify quality encoding.
-            assert_eq!(quality_enc, Some("Phred+33".to_string()));
-            // Verify regex names length.
-            assert_eq!(regex_names.len(), 1);
+            assert_eq!(predicates.quality_encoding, Some("Phred+33".to_string()));
+            // Verify the regex name.
+            assert_eq!(pattern_set.patterns()[0].name, "TestRegex");
             // Verify no variants (since none are provided).
-            assert_eq!(variants.len(), 0);
+            assert!(pattern_set.patterns()[0].variants.is_empty());
+            // Verify no reverse complements were added.
+            assert!(!pattern_set.has_reverse_complements());
         }
 
         // Clean up the temp file
         let _ = fs::remove_file(&json_path);
     }
 
+    #[test]
+    fn test_yaml_pattern_parsing() {
+        // Test: A YAML pattern file is validated and parsed like its JSON equivalent.
+        let yaml_content = r#"
+regexSet:
+  regexSetName: YamlTest
+  minimumSequenceLength: 4
+  regex:
+    - regexName: TestRegex
+      regexString: ACTN
+      variants:
+        - variantName: Variant1
+          variantString: ACTG
+    - regexName: OtherRegex
+      regexString: GGCC
+"#;
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .write_all(yaml_content.as_bytes())
+            .unwrap();
+        let yaml_path = temp_file.path().with_extension("yaml");
+        std::fs::copy(temp_file.path(), &yaml_path).unwrap();
+
+        let pattern_set =
+            patterns::PatternSet::from_file(yaml_path.to_str().unwrap(), None).unwrap();
+        assert_eq!(pattern_set.format(), patterns::PatternFormat::Yaml);
+        assert_eq!(pattern_set.matcher().patterns(), ["ACT[ACGT]", "GGCC"]);
+        assert_eq!(pattern_set.predicates().minimum_sequence_length, Some(4));
+        let names: Vec<_> = pattern_set.patterns().iter().map(|p| &p.name).collect();
+        assert_eq!(names, ["TestRegex", "OtherRegex"]);
+        assert_eq!(
+            pattern_set.patterns()[0].variants,
+            [patterns::Variant {
+                name: "Variant1".to_string(),
+                sequence: "ACTG".to_string()
+            }]
+        );
+
+        // Schema violations are reported for YAML files too.
+        std::fs::write(&yaml_path, "regexSet:\n  regexSetName: Invalid\n").unwrap();
+        assert!(patterns::PatternSet::from_file(yaml_path.to_str().unwrap(), None).is_err());
+
+        let _ = std::fs::remove_file(&yaml_path);
+    }
+
+    #[test]
+    fn test_pattern_set_constructors() {
+        // Test: A PatternSet can be built from a string or a serde value, without a file.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "LibraryTest",
+                "regex": [
+                    {
+                        "regexName": "Primer",
+                        "regexString": "GGRC",
+                        "reverseComplement": true,
+                        "variants": [{"variantName": "Variant1", "variantString": "GGAC"}]
+                    }
+                ]
+            }
+        });
+        let from_value =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let from_str =
+            patterns::PatternSet::from_str(&json.to_string(), patterns::PatternFormat::Json)
+                .unwrap();
+        assert_eq!(from_value.patterns(), from_str.patterns());
+
+        // The reverse complement carries its own original string, regex and variants.
+        let reverse = &from_value.patterns()[1];
+        assert_eq!(reverse.name, "Primer");
+        assert_eq!(reverse.original, "GYCC");
+        assert_eq!(reverse.regex, "G[CT]CC");
+        assert_eq!(reverse.variants[0].sequence, "GTCC");
+        assert_eq!(from_value.matcher().matches(b"AAGTCCAA"), vec![1]);
+
+        // Plain-text patterns are named after their converted regex.
+        let text =
+            patterns::PatternSet::from_str("GGRC\nACTG", patterns::PatternFormat::Text).unwrap();
+        assert_eq!(text.patterns()[0].name, "GG[AG]C");
+        assert_eq!(text.patterns()[0].original, "GGRC");
+        assert_eq!(text.name(), None);
+    }
+
+    #[test]
+    fn test_per_regex_predicates() {
+        // Test: Predicates given on a regex entry override the set-wide ones for that regex only.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "PredicatesTest",
+                "regex": [
+                    {
+                        "regexName": "Amplicon primer",
+                        "regexString": "GGACTA",
+                        "minimumSequenceLength": 20,
+                        "headerRegex": "^amplicon"
+                    },
+                    {
+                        "regexName": "Adapter",
+                        "regexString": "AGATCG"
+                    }
+                ],
+                "minimumSequenceLength": 10,
+                "minimumAverageQuality": 20,
+                "qualityEncoding": "Phred+33"
+            }
+        });
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let primer = &pattern_set.patterns()[0].predicates;
+        assert_eq!(primer.minimum_sequence_length, Some(20));
+        assert_eq!(primer.minimum_average_quality, Some(20.0));
+        assert_eq!(primer.header_regex.as_deref(), Some("^amplicon"));
+        assert_eq!(primer.quality_encoding.as_deref(), Some("Phred+33"));
+        assert_eq!(
+            &pattern_set.patterns()[1].predicates,
+            pattern_set.predicates()
+        );
+
+        let short = b"GGACTAAGATCG"; // 12 bases: too short for the primer.
+        let long = b"GGACTAAGATCGAAAAAAAAAA"; // 22 bases.
+        let high = |seq: &[u8]| vec![b'I'; seq.len()]; // Phred 40
+        let low = |seq: &[u8]| vec![b'$'; seq.len()]; // Phred 3
+
+        assert_eq!(
+            pattern_set.matches(b"amplicon_1", short, &high(short)),
+            vec![1]
+        );
+        assert_eq!(
+            pattern_set.matches(b"amplicon_1", long, &high(long)),
+            vec![0, 1]
+        );
+        assert_eq!(pattern_set.matches(b"read_1", long, &high(long)), vec![1]);
+        // The set-wide quality threshold still applies to both regexes.
+        assert!(!pattern_set.is_match(b"amplicon_1", long, &low(long)));
+        assert!(!pattern_set.passes_predicates(b"amplicon_1", long, &low(long)));
+
+        // A regex header filter is checked when the patterns are read.
+        let invalid = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "PredicatesTest",
+                "regex": [{"regexName": "Bad", "regexString": "ACGT", "headerRegex": "("}]
+            }
+        });
+        let err = patterns::PatternSet::from_value(&invalid, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.location,
+            Some(patterns::ErrorLocation::Regex("Bad".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_match_quality() {
+        // Test: A hit is accepted only if the bases within it meet the match quality.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "MatchQualityTest",
+                "regex": [
+                    {"regexName": "Primer", "regexString": "ACGT", "minimumMatchQuality": 20},
+                    {
+                        "regexName": "Adapter",
+                        "regexString": "GGCC",
+                        "minimumMatchAverageQuality": 30
+                    },
+                    {"regexName": "Anywhere", "regexString": "TTTT"}
+                ],
+                "qualityEncoding": "Phred+33"
+            }
+        });
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let seq = b"ACGTTTTTGGCCACGT";
+        // The first ACGT has a Q5 base; the second is high quality. GGCC has a mean of 25.
+        let qual = b"II&IIIIII555IIII";
+        assert_eq!(pattern_set.matches(b"read", seq, qual), vec![0, 2]);
+        assert_eq!(pattern_set.rejected(b"read", seq, qual), vec![1]);
+        assert_eq!(
+            pattern_set.find(0, seq, qual),
+            Some(matcher::Hit {
+                start: 12,
+                end: 16,
+                distance: 0,
+                frame: None
+            })
+        );
+
+        // Records with only rejected hits do not match.
+        let low = b"AAAAAAAAAAAA&III";
+        assert!(!pattern_set.is_match(b"read", b"AAAAAAAAAAAAACGT", low));
+        assert_eq!(
+            pattern_set.rejected(b"read", b"AAAAAAAAAAAAACGT", low),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_regex_combination() {
+        // Test: A combination keeps a record only if the expression over regex names holds.
+        let pattern_set = |combination: serde_json::Value| {
+            let json = serde_json::json!({
+                "regexSet": {
+                    "regexSetName": "CombinationTest",
+                    "regex": [
+                        {"regexName": "Forward", "regexString": "AAAC"},
+                        {"regexName": "Reverse", "regexString": "GGGT"},
+                        {"regexName": "Adapter", "regexString": "CTCT"}
+                    ],
+                    "combination": combination
+                }
+            });
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json)
+        };
+        let is_match = |set: &patterns::PatternSet, seq: &[u8]| {
+            set.is_match(b"read", seq, &vec![b'I'; seq.len()])
+        };
+
+        let both = pattern_set(serde_json::json!({"all": ["Forward", "Reverse"]})).unwrap();
+        assert!(is_match(&both, b"AAACTTGGGT"));
+        assert!(!is_match(&both, b"AAACTTTTTT"));
+
+        let two_of_three = pattern_set(serde_json::json!({
+            "atLeast": 2,
+            "of": ["Forward", "Reverse", "Adapter"]
+        }))
+        .unwrap();
+        assert!(is_match(&two_of_three, b"AAACCTCT"));
+        assert!(!is_match(&two_of_three, b"CTCTTTTT"));
+
+        let and_not = pattern_set(serde_json::json!({
+            "all": ["Forward", {"not": "Adapter"}]
+        }))
+        .unwrap();
+        assert!(is_match(&and_not, b"AAACGGGT"));
+        assert!(!is_match(&and_not, b"AAACCTCT"));
+        // Records that do not satisfy the combination have no patterns found.
+        assert_eq!(
+            and_not.matches(b"read", b"AAACCTCT", b"IIIIIIII"),
+            Vec::<usize>::new()
+        );
+        assert_eq!(
+            and_not.matches(b"read", b"AAACGGGT", b"IIIIIIII"),
+            vec![0, 1]
+        );
+
+        // Unknown regex names and impossible thresholds are errors.
+        let unknown = pattern_set(serde_json::json!({"any": ["Forward", "Missing"]}))
+            .err()
+            .unwrap();
+        assert_eq!(
+            unknown.location,
+            Some(patterns::ErrorLocation::Field("combination".to_string()))
+        );
+        assert!(unknown.reason.contains("Missing"));
+        assert!(pattern_set(serde_json::json!({"atLeast": 4, "of": ["Forward"]})).is_err());
+    }
+
+    #[test]
+    fn test_positioned_pattern_errors() {
+        // Test: Errors in a patterns file name the regex entry (or line) at fault and
+        // the offset of the offending character.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "ErrorTest",
+                "regex": [
+                    {"regexName": "Good", "regexString": "ACGT"},
+                    {"regexName": "Bad", "regexString": "GGRNACGTCXG"}
+                ]
+            }
+        });
+        let error = patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Regex("Bad".to_string()))
+        );
+        assert_eq!(error.offset, Some(9));
+        assert_eq!(
+            error.to_string(),
+            "Error in patterns file at regex \"Bad\", offset 9: Illegal character found in pattern: X"
+        );
+
+        // Plain-text pattern files report the line number, including for syntax errors.
+        let error = patterns::PatternSet::from_str("ACGT\nAC(GT", patterns::PatternFormat::Text)
+            .err()
+            .unwrap();
+        assert_eq!(error.location, Some(patterns::ErrorLocation::Line(2)));
+        assert_eq!(error.offset, Some(2));
+
+        // Invalid variants, header regexes and schema violations are located too.
+        let variant = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "ErrorTest",
+                "regex": [{"regexName": "R1", "regexString": "ACGT",
+                           "variants": [{"variantName": "V1", "variantString": "ACXT"}]}]
+            }
+        });
+        let error = patterns::PatternSet::from_value(&variant, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Regex("R1".to_string()))
+        );
+        assert_eq!(error.offset, Some(2));
+
+        let header = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "ErrorTest",
+                "regex": [{"regexName": "R1", "regexString": "ACGT"}],
+                "headerRegex": "^@("
+            }
+        });
+        let error = patterns::PatternSet::from_value(&header, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Field("headerRegex".to_string()))
+        );
+
+        let schema = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "ErrorTest",
+                "regex": [{"regexName": "R1", "regexString": 5}]
+            }
+        });
+        let error = patterns::PatternSet::from_value(&schema, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Regex("R1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_pattern_format_detection() {
+        // Test: The pattern file format is detected from its content, not its extension.
+        let json = r#"{"regexSet": {"regexSetName": "S", "regex": [{"regexName": "A", "regexString": "ACTG"}]}}"#;
+        let yaml = "# Comment\nregexSet:\n  regex:\n    - regexName: A\n      regexString: ACTG\n";
+        let text = "ACTG\nGG[AG]C\n";
+        assert_eq!(
+            patterns::PatternFormat::detect(json),
+            patterns::PatternFormat::Json
+        );
+        assert_eq!(
+            patterns::PatternFormat::detect(yaml),
+            patterns::PatternFormat::Yaml
+        );
+        assert_eq!(
+            patterns::PatternFormat::detect(text),
+            patterns::PatternFormat::Text
+        );
+        assert_eq!(
+            patterns::PatternFormat::detect(""),
+            patterns::PatternFormat::Text
+        );
+
+        // An upper case extension, or none at all, no longer affects parsing.
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.as_file().write_all(json.as_bytes()).unwrap();
+        let upper_path = temp_file.path().with_extension("JSON");
+        std::fs::copy(temp_file.path(), &upper_path).unwrap();
+        for path in [temp_file.path(), upper_path.as_path()] {
+            let pattern_set =
+                patterns::PatternSet::from_file(path.to_str().unwrap(), None).unwrap();
+            assert_eq!(pattern_set.format(), patterns::PatternFormat::Json);
+            assert_eq!(pattern_set.patterns()[0].name, "A");
+        }
+
+        // An explicit format overrides detection.
+        let pattern_set = patterns::PatternSet::from_file(
+            upper_path.to_str().unwrap(),
+            Some(patterns::PatternFormat::Yaml),
+        )
+        .unwrap();
+        assert_eq!(pattern_set.format(), patterns::PatternFormat::Yaml);
+
+        let _ = std::fs::remove_file(&upper_path);
+    }
+
+    #[test]
+    fn test_header_conditions() {
+        // Test: Header conditions include or exclude records by the fields of their header.
+        let condition = |expression: &str, exclude: bool| header::HeaderCondition {
+            expression: expression.to_string(),
+            exclude,
+        };
+        let illumina = header::HeaderFilter::new(
+            &[
+                condition("lane == 2", false),
+                condition("tile >= 1101", false),
+                condition("flowcell == H7KJ2BBXX", true),
+            ],
+            Some(header::HeaderFormat::Illumina),
+        )
+        .unwrap();
+        assert!(illumina.passes(b"A00627:493:HKF5GDSX5:2:1101:15239:1047 1:N:0:ACGT"));
+        assert!(illumina.passes(b"SRX22685872.1 A00627:493:HKF5GDSX5:2:1102:15239:1047"));
+        assert!(!illumina.passes(b"A00627:493:HKF5GDSX5:1:1101:15239:1047 1:N:0:ACGT"));
+        assert!(!illumina.passes(b"A00627:493:H7KJ2BBXX:2:1101:15239:1047 1:N:0:ACGT"));
+        assert!(!illumina.passes(b"read_without_fields"));
+        let read = header::HeaderFilter::new(
+            &[
+                condition("read == 2", false),
+                condition("index =~ ^AC", false),
+            ],
+            Some(header::HeaderFormat::Illumina),
+        )
+        .unwrap();
+        assert!(read.passes(b"A00627:493:HKF5GDSX5:2:1101:15239:1047 2:N:0:ACGT"));
+        assert!(!read.passes(b"A00627:493:HKF5GDSX5:2:1101:15239:1047 1:N:0:ACGT"));
+
+        let nanopore = header::HeaderFilter::new(
+            &[
+                condition("barcode == barcode05", false),
+                condition("ch < 100", false),
+            ],
+            Some(header::HeaderFormat::Nanopore),
+        )
+        .unwrap();
+        assert!(nanopore.passes(b"0a1b2c runid=9f ch=42 barcode=barcode05"));
+        assert!(!nanopore.passes(b"0a1b2c runid=9f ch=420 barcode=barcode05"));
+        assert!(!nanopore.passes(b"0a1b2c runid=9f ch=42 barcode=barcode06"));
+
+        // Invalid conditions are reported when the patterns file is read.
+        let error = |conditions: &[header::HeaderCondition], format| {
+            header::HeaderFilter::new(conditions, format).err().unwrap()
+        };
+        assert_eq!(
+            error(&[condition("lane == 2", false)], None),
+            "Header field \"lane\" requires a headerFormat (illumina or nanopore)"
+        );
+        assert_eq!(
+            error(
+                &[condition("pane == 2", false)],
+                Some(header::HeaderFormat::Illumina)
+            ),
+            "Unknown Illumina header field \"pane\""
+        );
+        assert!(error(
+            &[condition("lane", false)],
+            Some(header::HeaderFormat::Illumina)
+        )
+        .starts_with("Invalid header condition \"lane\""));
+
+        // Conditions given in a patterns file apply as predicates.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "HeaderTest",
+                "regex": [{"regexName": "A", "regexString": "ACGT"}],
+                "headerFormat": "nanopore",
+                "headerConditions": [{"exclude": "barcode == unclassified"}]
+            }
+        });
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        assert!(pattern_set.is_match(b"r1 barcode=barcode01", b"ACGT", b""));
+        assert!(!pattern_set.is_match(b"r1 barcode=unclassified", b"ACGT", b""));
+    }
+
+    #[test]
+    fn test_multiple_regex_sets() {
+        // Test: A patterns file may give several named regex sets, each with its own predicates.
+        let json = serde_json::json!({
+            "regexSets": [
+                {
+                    "regexSetName": "Primers",
+                    "regex": [{"regexName": "Forward", "regexString": "GGRNGG"}],
+                    "output": "primers.fastq"
+                },
+                {
+                    "regexSetName": "Adapters",
+                    "regex": [{"regexName": "Universal", "regexString": "AGATCGG"}],
+                    "minimumSequenceLength": 20
+                }
+            ]
+        });
+        let pattern_sets =
+            patterns::PatternSet::sets_from_value(&json, patterns::PatternFormat::Json).unwrap();
+        assert_eq!(pattern_sets.len(), 2);
+        assert_eq!(pattern_sets[0].name(), Some("Primers"));
+        assert_eq!(pattern_sets[0].output(), Some("primers.fastq"));
+        assert_eq!(pattern_sets[1].name(), Some("Adapters"));
+        assert_eq!(pattern_sets[1].output(), None);
+        let seq = b"GGAAGGAGATCGG";
+        assert!(pattern_sets[0].is_match(b"read", seq, b""));
+        assert!(!pattern_sets[1].is_match(b"read", seq, b""));
+        assert!(pattern_sets[1].is_match(b"read", b"GGAAGGAGATCGGTTTTTTTTT", b""));
+
+        // Commands that take a single regex set reject several.
+        let error = patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert!(error.reason.starts_with("Found 2 regexSets"));
+
+        // Set names must be unique, and schema violations are located within their set.
+        let mut duplicate = json.clone();
+        duplicate["regexSets"][1]["regexSetName"] = serde_json::json!("Primers");
+        let error =
+            patterns::PatternSet::sets_from_value(&duplicate, patterns::PatternFormat::Json)
+                .err()
+                .unwrap();
+        assert_eq!(error.reason, "Duplicate regexSetName \"Primers\"");
+        let mut invalid = json.clone();
+        invalid["regexSets"][1]["regex"][0]["maxMismatches"] = serde_json::json!("one");
+        let error = patterns::PatternSet::sets_from_value(&invalid, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Regex("Universal".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_multiple_regex_sets_bucket_files() {
+        // Test: With several regex sets, each set is written to its own file or, in bucket
+        // mode, only to the bucket file of each regexName, prefixed with the regexSetName.
+        use clap::Parser;
+        let json = serde_json::json!({
+            "regexSets": [
+                {
+                    "regexSetName": "Primers",
+                    "regex": [{"regexName": "Forward", "regexString": "GGRNGG"}],
+                    "reverseComplement": true,
+                    "output": "primers.fastq"
+                },
+                {
+                    "regexSetName": "Adapters",
+                    "regex": [
+                        {"regexName": "Universal", "regexString": "AGATCGG"},
+                        {"regexName": "Nextera", "regexString": "CTGTCTCTTATA"}
+                    ]
+                }
+            ]
+        });
+        let pattern_sets =
+            patterns::PatternSet::sets_from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let cli = arg::Cli::parse_from(["grepq", "-R", "patterns.json", "reads.fastq"]);
+        let files = output::output_files(&cli, &pattern_sets);
+        assert_eq!(
+            files.sets,
+            vec![
+                Some("primers.fastq".to_string()),
+                Some("Adapters.fastq".to_string())
+            ]
+        );
+        assert!(files.buckets.is_empty());
+
+        // A regex and its reverse complement share a bucket, and no set output is created.
+        let cli = arg::Cli::parse_from(["grepq", "-R", "--bucket", "patterns.json", "reads.fastq"]);
+        let files = output::output_files(&cli, &pattern_sets);
+        assert!(files.sets.is_empty());
+        let bucket = |name: &str, file: &str| (name.to_string(), file.to_string());
+        assert_eq!(
+            files.buckets,
+            vec![
+                vec![bucket("Forward", "Primers_Forward.fastq")],
+                vec![
+                    bucket("Universal", "Adapters_Universal.fastq"),
+                    bucket("Nextera", "Adapters_Nextera.fastq")
+                ]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pattern_includes() {
+        // Test: A regexSet pulls regex entries from included files, found relative to it.
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        let write = |name: &str, contents: &str| {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        };
+        write(
+            "shared/adapters.yaml",
+            "regexSet:\n  regexSetName: adapters\n  include: [markers.fasta]\n  regex:\n    - regexName: Universal\n      regexString: AGATCGG\n",
+        );
+        write("shared/markers.fasta", ">Marker\nGGRNGG\n");
+        write(
+            "project.json",
+            r#"{"regexSet": {"regexSetName": "Project", "include": ["shared/adapters.yaml"], "regex": [{"regexName": "Own", "regexString": "ACGT"}]}}"#,
+        );
+        let project = dir.path().join("project.json");
+        let pattern_set = patterns::PatternSet::from_file(project.to_str().unwrap(), None).unwrap();
+        let names: Vec<&str> = pattern_set
+            .patterns()
+            .iter()
+            .map(|pattern| pattern.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Own", "Universal", "Marker"]);
+        // The source is the resolved set, so the query can be reproduced.
+        let resolved: serde_json::Value = serde_json::from_str(pattern_set.source()).unwrap();
+        assert!(resolved["regexSet"].get("include").is_none());
+        assert_eq!(resolved["regexSet"]["regex"][2]["regexString"], "GGRNGG");
+
+        // Included regexes may not share a name with another regex of the set.
+        write("shared/markers.fasta", ">Own\nGGRNGG\n");
+        let error = patterns::PatternSet::from_file(project.to_str().unwrap(), None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Field("include".to_string()))
+        );
+        assert!(error.reason.contains("has the same name as another regex"));
+
+        // A file that includes itself, directly or not, is reported.
+        write(
+            "shared/adapters.yaml",
+            "regexSet:\n  regexSetName: adapters\n  include: [../project.json]\n",
+        );
+        let error = patterns::PatternSet::from_file(project.to_str().unwrap(), None)
+            .err()
+            .unwrap();
+        assert!(error.reason.starts_with("Include cycle: "));
+    }
+
+    #[test]
+    fn test_detect_compression() {
+        // Test: The compression of a FASTQ file is detected from its first bytes
+        use initialise::InputCompression;
+        let start = |path: &str| std::fs::read(path).unwrap()[..4].to_vec();
+        assert_eq!(
+            InputCompression::detect(&start("examples/small-copy.fastq.gz")),
+            InputCompression::Gzip
+        );
+        assert_eq!(
+            InputCompression::detect(&start("examples/small-copy.fastq.zst")),
+            InputCompression::Zstd
+        );
+        assert_eq!(
+            InputCompression::detect(&start("examples/small.fastq")),
+            InputCompression::Plain
+        );
+        assert_eq!(InputCompression::detect(b""), InputCompression::Plain);
+    }
+
+    #[test]
+    fn test_multiple_input_files() {
+        // Test: Several FASTQ files, given as a glob, are read in turn, and each record is
+        // attributed to the file it was read from
+        use seq_io::fastq::Record;
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+        };
+        // A zero-length read has empty sequence and quality lines, and the last line of a
+        // file may lack a newline.
+        write(
+            "a.fastq",
+            "@r1\nACGT\n+\nIIII\n@e\n\n+\n\n@r2\nACGT\n+\nIIII",
+        );
+        write("b.fastq", "@e\n\n+\n\n");
+        write("c.fastq", "");
+        write(".hidden.fastq", "@r3\nACGT\n+\nIIII\n");
+        write("notes.txt", "");
+        let glob = dir.path().join("*.fastq").to_string_lossy().into_owned();
+        let paths = input::input_files(&[glob]).unwrap();
+        let names: Vec<String> = paths
+            .iter()
+            .map(|path| path.rsplit('/').next().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["a.fastq", "b.fastq", "c.fastq"]);
+
+        // Record sets may be processed out of order, so the records are compared sorted.
+        let mut records = Vec::new();
+        input::parallel_fastq_files(
+            input::FastqFiles::new(paths, None),
+            2,
+            2,
+            |record, length: &mut usize| *length = record.seq().len(),
+            |record, length, file| {
+                records.push((
+                    String::from_utf8_lossy(record.head()).into_owned(),
+                    *length,
+                    file,
+                ))
+            },
+        )
+        .unwrap();
+        records.sort_unstable();
+        let expected = [("e", 0, 0), ("e", 0, 1), ("r1", 4, 0), ("r2", 4, 0)];
+        let expected: Vec<(String, usize, usize)> = expected
+            .iter()
+            .map(|&(head, length, file)| (head.to_string(), length, file))
+            .collect();
+        assert_eq!(records, expected);
+
+        assert!(input::input_files(&["missing.fastq".to_string()]).is_err());
+        let glob = dir.path().join("*.fq").to_string_lossy().into_owned();
+        assert!(input::input_files(&[glob])
+            .unwrap_err()
+            .starts_with("No files match"));
+        let glob = dir.path().join("[a.fastq").to_string_lossy().into_owned();
+        assert!(input::input_files(&[glob])
+            .unwrap_err()
+            .starts_with("Invalid glob"));
+        assert!(input::input_files(&["-".to_string(), "-".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_six_frame_translation() {
+        // Test: Protein patterns are matched against the six-frame translation, and hits
+        // are reported with their frame and the coordinates of the bases encoding them
+        let orf = "ATGAAACATGAAGCTGCTCACTGG"; // MKHEAAHW
+        assert_eq!(translate::translate(orf.as_bytes(), false), b"MKHEAAHW");
+        assert_eq!(translate::translate(b"TAANNN", false), b"*X");
+
+        let forward = format!("C{}CCCCC", orf);
+        let reverse = iupac::reverse_complement_sequence(&format!("GG{}TTTTTT", orf));
+        let matcher = matcher::PatternMatcher::with_protein(
+            &["HE..H".to_string(), "ACGTACGT".to_string()],
+            &[None, None],
+            &[true, false],
+            false,
+        )
+        .unwrap();
+        assert_eq!(matcher.matches(forward.as_bytes()), vec![0]);
+        assert_eq!(
+            matcher.find(0, forward.as_bytes()),
+            Some(matcher::Hit {
+                start: 7,
+                end: 22,
+                distance: 0,
+                frame: Some(2)
+            })
+        );
+        assert_eq!(
+            matcher.find(0, reverse.as_bytes()),
+            Some(matcher::Hit {
+                start: 9,
+                end: 24,
+                distance: 0,
+                frame: Some(-3)
+            })
+        );
+        let hit = matcher.find(0, reverse.as_bytes()).unwrap();
+        assert_eq!(
+            translate::peptide(reverse.as_bytes(), &hit, false).unwrap(),
+            b"HEAAH"
+        );
+        // The nucleotide pattern is not matched against the translation.
+        assert!(!matcher.is_match(b"ACGACGACG"));
+
+        // Protein variants are amino acids, and protein patterns tolerate no distance.
+        let json = r#"{"regexSet": {"regexSetName": "motifs", "regex": [
+            {"regexName": "zinc", "regexString": "HE..H", "patternType": "protein",
+                "variants": [{"variantName": "HEAAH", "variantString": "HEAAH"}]}]}}"#;
+        let pattern_set =
+            patterns::PatternSet::from_str(json, patterns::PatternFormat::Json).unwrap();
+        assert!(pattern_set.patterns()[0].protein);
+        assert!(pattern_set.is_match(b"", forward.as_bytes(), b""));
+        let fuzzy = json.replace(r#""patternType""#, r#""maxMismatches": 1, "patternType""#);
+        assert!(patterns::PatternSet::from_str(&fuzzy, patterns::PatternFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_amplicons() {
+        // Test: A read contains an amplicon if the forward primer is followed by the reverse
+        // complement of the reverse primer, or vice versa, with an insert of allowed length
+        let json = r#"{"regexSet": {"regexSetName": "amplicons", "regex": [
+            {"regexName": "F", "regexString": "GGRNGGCNGCAG"},
+            {"regexName": "R", "regexString": "CNNGGGTWTCTA"}],
+            "amplicons": [{"ampliconName": "V3-V4", "forward": "F", "reverse": "R",
+                "minimumInsert": 10, "maximumInsert": 40}]}}"#;
+        let pattern_set =
+            patterns::PatternSet::from_str(json, patterns::PatternFormat::Json).unwrap();
+        let insert = "ACGTACGTACGTACGTACGT";
+        // The forward primer, an insert, and the reverse complement of the reverse primer.
+        let forward = format!("TTT{}{}TAGAAACCCTTGTTT", "GGAAGGCAGCAG", insert);
+        let hits = pattern_set.find_amplicons(forward.as_bytes(), b"");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].1.insert_start, 15);
+        assert_eq!(hits[0].1.insert_length(), insert.len());
+        assert_eq!(hits[0].1.strand, patterns::Strand::Forward);
+        assert!(pattern_set.is_match(b"", forward.as_bytes(), b""));
+
+        // The same amplicon read from the other strand.
+        let reverse = iupac::reverse_complement_sequence(&forward);
+        let hits = pattern_set.find_amplicons(reverse.as_bytes(), b"");
+        assert_eq!(hits[0].1.strand, patterns::Strand::Reverse);
+        assert_eq!(hits[0].1.insert_length(), insert.len());
+
+        // An insert too short, or a primer alone, is not an amplicon.
+        let short = "TTTGGAAGGCAGCAGACGTTAGAAACCCTTGTTT";
+        assert!(!pattern_set.is_match(b"", short.as_bytes(), b""));
+        assert!(!pattern_set.is_match(b"", b"TTTGGAAGGCAGCAGTTT", b""));
+
+        // Each primer keeps the window of its regex, which applies to its reverse complement
+        // too, and its match quality.
+        let windowed = json.replace(
+            r#""regexString": "GGRNGGCNGCAG"}"#,
+            r#""regexString": "GGRNGGCNGCAG", "window": {"first": 20}}"#,
+        );
+        let pattern_set =
+            patterns::PatternSet::from_str(&windowed, patterns::PatternFormat::Json).unwrap();
+        assert!(pattern_set.is_match(b"", forward.as_bytes(), b""));
+        assert!(!pattern_set.is_match(b"", reverse.as_bytes(), b""));
+        let quality = json.replace(
+            r#""regexString": "CNNGGGTWTCTA"}"#,
+            r#""regexString": "CNNGGGTWTCTA", "minimumMatchQuality": 30}"#,
+        );
+        let pattern_set =
+            patterns::PatternSet::from_str(&quality, patterns::PatternFormat::Json).unwrap();
+        let qual = "I".repeat(forward.len());
+        assert!(pattern_set.is_match(b"", forward.as_bytes(), qual.as_bytes()));
+        let low = format!("{}#{}", &qual[..40], &qual[41..]);
+        assert!(!pattern_set.is_match(b"", forward.as_bytes(), low.as_bytes()));
+        assert!(pattern_set
+            .find_amplicons(forward.as_bytes(), low.as_bytes())
+            .is_empty());
+
+        // Primers must name regexes of the set.
+        let unknown = json.replace(r#""reverse": "R""#, r#""reverse": "X""#);
+        let error = patterns::PatternSet::from_str(&unknown, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Field("amplicons".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_schema_versions() {
+        // The schema file in the examples directory is the embedded schema.
+        assert_eq!(
+            include_str!("../examples/grepq-schema.json").trim(),
+            initialise::SCHEMA.trim()
+        );
+
+        // A version 1 file, whose variants are plain sequences, is migrated.
+        let v1 = r#"{"regexSet": {"regexSetName": "s", "regex": [
+            {"regexName": "a", "regexString": "GATTACA", "variants": ["GATTACA"]}]}}"#;
+        let (version, migrated) = schema::migrate_str(v1, patterns::PatternFormat::Json).unwrap();
+        assert_eq!(version, 1);
+        let migrated: Value = serde_json::from_str(&migrated).unwrap();
+        assert_eq!(migrated["schemaVersion"], schema::SCHEMA_VERSION);
+        assert_eq!(
+            migrated["regexSet"]["regex"][0]["variants"][0]["variantString"],
+            "GATTACA"
+        );
+        let pattern_set =
+            patterns::PatternSet::from_str(v1, patterns::PatternFormat::Json).unwrap();
+        assert_eq!(pattern_set.patterns()[0].variants[0].sequence, "GATTACA");
+
+        // A declared version is validated against the schema of that version.
+        let v2 = r#"{"schemaVersion": 2, "regexSet": {"regexSetName": "s", "regex": [
+            {"regexName": "a", "regexString": "GATTACA", "variants": ["GATTACA"]}]}}"#;
+        assert!(patterns::PatternSet::from_str(v2, patterns::PatternFormat::Json).is_err());
+        let unsupported = r#"{"schemaVersion": 9, "regexSet": {"regexSetName": "s",
+            "regex": [{"regexName": "a", "regexString": "GATTACA"}]}}"#;
+        let error = patterns::PatternSet::from_str(unsupported, patterns::PatternFormat::Json)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.location,
+            Some(patterns::ErrorLocation::Field("schemaVersion".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        // Test: The lint command reports empty, duplicate, subsumed and reverse complement
+        // patterns of a plain-text patterns file, by line.
+        let text = "GGRNGG\n\nGG[AG][ACGT]GG\nGGAAGGT\nCCNYCC\nACGTTT\n";
+        let pattern_set =
+            patterns::PatternSet::from_str(text, patterns::PatternFormat::Text).unwrap();
+        let issues: Vec<(&str, Vec<usize>)> = lint::lint(&pattern_set)
+            .iter()
+            .map(|issue| (issue.kind.as_str(), issue.patterns.clone()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("empty", vec![1]),
+                ("duplicate", vec![2, 0]),
+                ("subsumed", vec![3, 0]),
+                ("reverseComplement", vec![4, 0]),
+            ]
+        );
+
+        // A palindromic pattern given twice is a duplicate, not also a reverse complement.
+        let pattern_set =
+            patterns::PatternSet::from_str("ACGT\nACGT\n", patterns::PatternFormat::Text).unwrap();
+        let issues: Vec<(&str, Vec<usize>)> = lint::lint(&pattern_set)
+            .iter()
+            .map(|issue| (issue.kind.as_str(), issue.patterns.clone()))
+            .collect();
+        assert_eq!(issues, vec![("duplicate", vec![1, 0])]);
+
+        // Variants the regex cannot match, and costly regexes, are reported by name. A
+        // pattern is only redundant with another searched for in the same way.
+        let json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "Lint",
+                "regex": [
+                    {
+                        "regexName": "Primer",
+                        "regexString": "GGRNGG",
+                        "variants": [
+                            {"variantName": "Good", "variantString": "GGATGG"},
+                            {"variantName": "Bad", "variantString": "GGTTGG"}
+                        ]
+                    },
+                    {"regexName": "Longer", "regexString": "GGAAGGT", "maxMismatches": 1},
+                    {"regexName": "Spacer", "regexString": "ACGT.{0,500}ACGT"}
+                ]
+            }
+        });
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        let issues = lint::lint(&pattern_set);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].kind, lint::IssueKind::VariantMismatch);
+        assert!(issues[0].message.starts_with("Variant \"Bad\" (GGTTGG)"));
+        assert_eq!(issues[1].kind, lint::IssueKind::DfaCost);
+        assert_eq!(issues[1].patterns, vec![2]);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        // Test: A caseInsensitive regexSet matches soft-masked (lowercase) bases, exactly
+        // and approximately, and the quality statistics count them once unmasked.
+        let mut json = serde_json::json!({
+            "regexSet": {
+                "regexSetName": "SoftMasked",
+                "regex": [
+                    {"regexName": "Exact", "regexString": "GGRNGG"},
+                    {"regexName": "Fuzzy", "regexString": "ACGTACGT", "maxMismatches": 1}
+                ]
+            }
+        });
+        let seq = b"ttggaaggttacgtaggttt";
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        assert!(!pattern_set.case_insensitive());
+        assert!(!pattern_set.is_match(b"read", seq, b""));
+        json["regexSet"]["caseInsensitive"] = serde_json::json!(true);
+        let pattern_set =
+            patterns::PatternSet::from_value(&json, patterns::PatternFormat::Json).unwrap();
+        assert!(pattern_set.case_insensitive());
+        assert_eq!(pattern_set.matches(b"read", seq, b""), vec![0, 1]);
+        assert_eq!(pattern_set.find(1, seq, b"").unwrap().distance, 1);
+
+        // Lowercase bases are ignored by the statistics unless unmasked.
+        assert_eq!(quality::gc_content(b"gcAT"), 0.0);
+        assert_eq!(quality::gc_content(&quality::unmask(b"gcAT")), 50.0);
+        assert_eq!(quality::tetranucleotide_frequencies(b"acgtACGT", None).1, 1);
+        assert_eq!(
+            quality::tetranucleotide_frequencies(&quality::unmask(b"acgtACGT"), None).1,
+            4
+        );
+        assert!(matches!(
+            quality::unmask(b"ACGT"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_fasta_patterns() {
+        // Test: A FASTA file of primers is read as a pattern set, with header tags.
+        let fasta = "; regexSetName=\"Primers\" minimumSequenceLength=10\n\
+                     >Forward maxMismatches=1 headerRegex=\"^read 1\"\n\
+                     GGRNGG\n\
+                     cngcag\n\
+                     >Reverse reverseComplement=true\n\
+                     ACGTAA\n";
+        assert_eq!(
+            patterns::PatternFormat::detect(fasta),
+            patterns::PatternFormat::Fasta
+        );
+        let pattern_set =
+            patterns::PatternSet::from_str(fasta, patterns::PatternFormat::Fasta).unwrap();
+        assert_eq!(pattern_set.name(), Some("Primers"));
+        assert_eq!(pattern_set.source(), fasta);
+        assert_eq!(pattern_set.predicates().minimum_sequence_length, Some(10));
+        let patterns = pattern_set.patterns();
+        assert_eq!(patterns.len(), 3);
+        assert_eq!(patterns[0].name, "Forward");
+        assert_eq!(patterns[0].original, "GGRNGGCNGCAG");
+        assert_eq!(patterns[0].distance, Some(matcher::Distance::Mismatches(1)));
+        assert_eq!(
+            patterns[0].predicates.header_regex.as_deref(),
+            Some("^read 1")
+        );
+        assert_eq!(patterns[2].original, "TTACGT");
+        assert!(pattern_set.is_match(b"read 1", b"TTGGAAGGCTGCAGTT", b""));
+        assert!(!pattern_set.is_match(b"read 2", b"TTGGAAGGCTGCAGTT", b""));
+
+        // The set is named after the file, unless named by a tag.
+        let mut temp_file = tempfile::Builder::new()
+            .suffix(".fasta")
+            .tempfile()
+            .unwrap();
+        temp_file.write_all(b">Forward\nACGT\n").unwrap();
+        let path = temp_file.path();
+        let pattern_set = patterns::PatternSet::from_file(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(
+            pattern_set.name(),
+            path.file_stem().and_then(|stem| stem.to_str())
+        );
+
+        // Malformed tags are reported at their line.
+        let error = patterns::PatternSet::from_str(
+            ">Forward\nACGT\n>Reverse mismatches=1\nACGT",
+            patterns::PatternFormat::Fasta,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.location, Some(patterns::ErrorLocation::Line(3)));
+        assert_eq!(error.reason, "Unknown tag \"mismatches\"");
+    }
+
     // #[test]
     // fn test_tetranucleotide_frequencies_variable_kmer_size() {
     //     // Test: Verify that k-mer frequencies work with different sizes.
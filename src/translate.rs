@@ -0,0 +1,135 @@
+// MIT License
+
+// Copyright (c) 2024 - present Nicholas D. Crosbie
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// This module translates nucleotide sequences into protein, for protein patterns, which
+// are matched against the six-frame translation of each sequence. Frames +1, +2 and +3
+// start at the first, second and third base of the sequence; frames -1, -2 and -3 start
+// at the first, second and third base of its reverse complement. Codons are translated
+// with the standard genetic code, stop codons to '*' and codons with any base other than
+// A, C, G or T to 'X'.
+
+use crate::matcher::Hit;
+
+// The amino acids encoded by each codon, with the bases of a codon ordered T, C, A, G.
+const GENETIC_CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+// The frames of the six-frame translation, in the order returned by six_frames.
+pub const FRAMES: [i8; 6] = [1, 2, 3, -1, -2, -3];
+
+// Function: base_index
+// Returns the index of a base in the order of GENETIC_CODE, if it is A, C, G or T. When
+// case_insensitive is set, lowercase (soft-masked) bases are accepted too.
+#[inline(always)]
+fn base_index(base: u8, case_insensitive: bool) -> Option<usize> {
+    let base = if case_insensitive {
+        base.to_ascii_uppercase()
+    } else {
+        base
+    };
+    match base {
+        b'T' => Some(0),
+        b'C' => Some(1),
+        b'A' => Some(2),
+        b'G' => Some(3),
+        _ => None,
+    }
+}
+
+// Function: complement
+// Returns the complement of a base, keeping its case; other bytes are returned as is.
+#[inline(always)]
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'a' => b't',
+        b't' => b'a',
+        b'c' => b'g',
+        b'g' => b'c',
+        other => other,
+    }
+}
+
+// Function: translate
+// Translates the whole codons of a sequence, from its first base.
+pub fn translate(seq: &[u8], case_insensitive: bool) -> Vec<u8> {
+    seq.chunks_exact(3)
+        .map(|codon| {
+            match (
+                base_index(codon[0], case_insensitive),
+                base_index(codon[1], case_insensitive),
+                base_index(codon[2], case_insensitive),
+            ) {
+                (Some(a), Some(b), Some(c)) => GENETIC_CODE[a * 16 + b * 4 + c],
+                _ => b'X',
+            }
+        })
+        .collect()
+}
+
+// Function: six_frames
+// Returns the translation of a sequence in each of the six frames, in the order of
+// FRAMES.
+pub fn six_frames(seq: &[u8], case_insensitive: bool) -> [Vec<u8>; 6] {
+    let reverse: Vec<u8> = seq.iter().rev().map(|&base| complement(base)).collect();
+    let frame = |strand: &[u8], offset: usize| {
+        translate(strand.get(offset..).unwrap_or_default(), case_insensitive)
+    };
+    [
+        frame(seq, 0),
+        frame(seq, 1),
+        frame(seq, 2),
+        frame(&reverse, 0),
+        frame(&reverse, 1),
+        frame(&reverse, 2),
+    ]
+}
+
+// Function: nucleotide_range
+// Returns the start and end, in the sequence, of the bases encoding the amino acids from
+// start up to (but not including) end of the translation in the given frame. The bases
+// of a hit in a reverse frame are those of the forward strand.
+pub fn nucleotide_range(frame: i8, start: usize, end: usize, len: usize) -> (usize, usize) {
+    let offset = frame.unsigned_abs() as usize - 1;
+    let (start, end) = (offset + 3 * start, offset + 3 * end);
+    if frame > 0 {
+        (start, end)
+    } else {
+        (len - end, len - start)
+    }
+}
+
+// Function: peptide
+// Returns the amino acids of a hit of a protein pattern, translated in its frame, or
+// None for the hit of a nucleotide pattern.
+pub fn peptide(seq: &[u8], hit: &Hit, case_insensitive: bool) -> Option<Vec<u8>> {
+    let bases = &seq[hit.start..hit.end];
+    match hit.frame? {
+        frame if frame > 0 => Some(translate(bases, case_insensitive)),
+        _ => {
+            let reverse: Vec<u8> = bases.iter().rev().map(|&base| complement(base)).collect();
+            Some(translate(&reverse, case_insensitive))
+        }
+    }
+}
@@ -21,10 +21,8 @@
 // SOFTWARE.
 
 use crate::arg::Cli;
-use crate::initialise::{create_reader, parse_patterns_file};
-use crate::quality;
+use crate::initialise::{create_file_reader, input_paths, load_patterns};
 //use log::info;
-use regex::bytes::Regex;
 use seq_io::fastq::Record;
 use serde_json::json;
 use std::collections::HashMap;
@@ -33,115 +31,80 @@ use std::io::{self};
 
 // Main function to run the tune command
 pub fn run_tune(cli: &Cli, num_matches: usize, include_count: bool) -> io::Result<()> {
-    let patterns_path = &cli.patterns;
-
     // Parse the patterns file
-    let (regex_set, header_regex, minimum_sequence_length, minimum_quality, quality_encoding, _, _) =
-        parse_patterns_file(patterns_path).map_err(io::Error::other)?;
-
-    let header_regex = header_regex.map(|re| Regex::new(&re).unwrap());
-    let mut reader = create_reader(cli);
-
-    let mut match_counts: HashMap<String, usize> = HashMap::new();
-    let mut match_strings: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    let pattern_set = load_patterns(cli);
+    let patterns = pattern_set.patterns();
+
+    // Match counts and sub-matches are indexed by pattern. Sub-matches are stored with
+    // their count and their distance from the pattern.
+    let mut match_counts: Vec<usize> = vec![0; patterns.len()];
+    let mut match_strings: Vec<HashMap<String, (usize, usize)>> =
+        vec![HashMap::new(); patterns.len()];
     let mut total_matches = 0;
 
     // Iterate through each record in the reader
+    // The records of several input files are tuned on together, in turn.
     let mut records_processed = 0;
-    while let Some(result) = reader.next() {
-        let record = match result {
-            Ok(record) => record,
-            Err(e) => {
-                return Err(io::Error::other(format!(
-                    "grepq only supports the fastq format. Check your input file.: {}",
-                    e
-                )));
-            }
-        };
-
-        // Check sequence length, header, and quality
-        let seq_len_check = minimum_sequence_length
-            .map(|len| record.seq().len() >= len as usize)
-            .unwrap_or(true);
-        let header_check = header_regex
-            .as_ref()
-            .map(|re| re.is_match(record.head()))
-            .unwrap_or(true);
-        let qual_check = minimum_quality
-            .map(|min_q| {
-                quality::average_quality(
-                    record.qual(),
-                    quality_encoding.as_deref().unwrap_or("Phred+33"),
-                ) >= min_q
-            })
-            .unwrap_or(true);
-
-        // If all checks pass, match the sequence against the regex set
-        if seq_len_check && header_check && qual_check {
-            for mat in regex_set.matches(record.seq()).into_iter() {
-                let matched_pattern = regex_set.patterns()[mat].to_string();
-                let converted_pattern = crate::initialise::convert_iupac_to_regex(&matched_pattern);
-                *match_counts.entry(converted_pattern.clone()).or_insert(0) += 1;
-                let entry = match_strings.entry(converted_pattern.clone()).or_default();
-                let matched_substring = Regex::new(&regex_set.patterns()[mat])
-                    .unwrap()
-                    .find_iter(record.seq())
-                    .next()
-                    .unwrap();
-                let matched_substring =
-                    &record.seq()[matched_substring.start()..matched_substring.end()];
-                *entry
+    'files: for path in input_paths(cli) {
+        let mut reader = create_file_reader(cli, &path);
+        while let Some(result) = reader.next() {
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    return Err(io::Error::other(format!(
+                        "grepq only supports the fastq format. Check your input file.: {}",
+                        e
+                    )));
+                }
+            };
+
+            // Match the sequence against the regex set, counting only the patterns whose
+            // predicates (sequence length, header, and quality) the record passes
+            for mat in pattern_set.matches(record.head(), record.seq(), record.qual()) {
+                match_counts[mat] += 1;
+                let matched = pattern_set.find(mat, record.seq(), record.qual()).unwrap();
+                let matched_substring = &record.seq()[matched.start..matched.end];
+                match_strings[mat]
                     .entry(String::from_utf8_lossy(matched_substring).to_string())
-                    .or_insert(0) += 1;
+                    .or_insert((0, matched.distance))
+                    .0 += 1;
                 total_matches += 1;
                 //  info!("Total matches: {}", total_matches);
                 if total_matches >= num_matches {
                     break;
                 }
             }
-        }
-        records_processed += 1;
-        //  info!("Records processed: {}", records_processed);
-        if total_matches >= num_matches || records_processed >= num_matches {
-            break;
+            records_processed += 1;
+            //  info!("Records processed: {}", records_processed);
+            if total_matches >= num_matches || records_processed >= num_matches {
+                break 'files;
+            }
         }
     }
 
-    let mut match_counts: Vec<_> = match_counts.into_iter().collect();
-    match_counts.sort_by(|a, b| b.1.cmp(&a.1));
-
-    // Handle JSON patterns file
-    if patterns_path.ends_with(".json") {
-        let json: serde_json::Value = serde_json::from_reader(std::fs::File::open(patterns_path)?)?;
-        let regex_set_name = json["regexSet"]["regexSetName"]
-            .as_str()
-            .unwrap_or("Unknown");
+    // Handle JSON and YAML patterns files
+    if pattern_set.format().is_structured() {
+        let regex_set_name = pattern_set.name().unwrap_or("Unknown");
 
         if matches!(cli.command.as_ref(), Some(crate::arg::Commands::Tune(t)) if t.include_names) {
             println!("Regex Set Name: {}", regex_set_name);
         }
 
-        let regex_array = json["regexSet"]["regex"].as_array().unwrap();
         let mut regex_matches = vec![];
 
-        for regex in regex_array {
-            let regex_string = regex["regexString"].as_str().unwrap();
-            let converted_regex_string = crate::initialise::convert_iupac_to_regex(regex_string);
-            let regex_name = regex["regexName"].as_str().unwrap_or("Unknown");
-            let count = match_counts
-                .iter()
-                .find(|(pattern, _)| pattern == &converted_regex_string)
-                .map(|(_, count)| count)
-                .unwrap_or(&0);
-
-            let mut most_frequent_matches: Vec<_> = match_strings
-                .get(&converted_regex_string)
-                .map(|matches| {
-                    let mut matches_vec: Vec<_> = matches.iter().collect();
-                    matches_vec.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
-                    matches_vec
-                })
-                .unwrap_or_default();
+        // Report the reverse complement of a regex on its own row, where enabled.
+        for (index, pattern) in patterns.iter().enumerate() {
+            let strand_label = if pattern_set.is_stranded(index) {
+                format!(" [{}]", pattern.strand.as_str())
+            } else {
+                String::new()
+            };
+            let regex_name = &pattern.name;
+            let regex_string = &pattern.original;
+            let count = &match_counts[index];
+
+            let mut most_frequent_matches: Vec<_> = match_strings[index].iter().collect();
+            most_frequent_matches.sort_by_key(|&(_, &(count, _))| std::cmp::Reverse(count));
 
             let top_n = cli
                 .command
@@ -161,21 +124,20 @@ pub fn run_tune(cli: &Cli, num_matches: usize, include_count: bool) -> io::Resul
 
             most_frequent_matches.truncate(top_n);
 
-            let variants_array = regex["variants"]
-                .as_array()
-                .unwrap_or_else(|| Box::leak(Box::new(Vec::new())));
-            let variants = variants_array;
             let most_frequent_matches_json: Vec<_> = most_frequent_matches
                 .into_iter()
-                .map(|(seq, count)| {
-                    let variant_name = variants.iter().find_map(|variant| {
-                        if variant["variantString"].as_str() == Some(seq) {
-                            variant["variantName"].as_str().map(|s| s.to_string())
-                        } else {
-                            None
-                        }
-                    });
-                    json!({"variant": seq, "count": count, "variantName": variant_name})
+                .map(|(seq, &(count, distance))| {
+                    let variant_name = pattern
+                        .variants
+                        .iter()
+                        .find(|variant| &variant.sequence == seq)
+                        .map(|variant| variant.name.clone());
+                    json!({
+                        "variant": seq,
+                        "count": count,
+                        "variantName": variant_name,
+                        "distance": distance
+                    })
                 })
                 .collect();
 
@@ -183,21 +145,25 @@ pub fn run_tune(cli: &Cli, num_matches: usize, include_count: bool) -> io::Resul
                 "regexName": regex_name,
                 "regexString": regex_string,
                 "regexCount": count,
+                "strand": pattern.strand.as_str(),
                 "variants": most_frequent_matches_json
             }));
 
             if matches!(cli.command.as_ref(), Some(crate::arg::Commands::Tune(t)) if t.include_names)
             {
                 if include_count {
-                    println!("{} ({}): {}", regex_name, regex_string, count);
+                    println!(
+                        "{} ({}){}: {}",
+                        regex_name, regex_string, strand_label, count
+                    );
                 } else {
-                    println!("{} ({})", regex_name, regex_string);
+                    println!("{} ({}){}", regex_name, regex_string, strand_label);
                 }
             } else if *count > 0 {
                 if include_count {
-                    println!("{}: {}", regex_string, count);
+                    println!("{}{}: {}", regex_string, strand_label, count);
                 } else {
-                    println!("{}", regex_string);
+                    println!("{}{}", regex_string, strand_label);
                 }
             }
         }
@@ -215,6 +181,12 @@ pub fn run_tune(cli: &Cli, num_matches: usize, include_count: bool) -> io::Resul
             }
         }
     } else {
+        let mut match_counts: Vec<_> = patterns
+            .iter()
+            .map(|pattern| &pattern.regex)
+            .zip(match_counts)
+            .collect();
+        match_counts.sort_by_key(|b| std::cmp::Reverse(b.1));
         for (pattern, count) in &match_counts {
             if count > &0 {
                 if include_count {